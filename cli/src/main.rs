@@ -0,0 +1,358 @@
+pub mod tui;
+
+use std::{env, io::Read, path::PathBuf, process, time::Duration};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use chat_sdk::{
+    data::{SystemEvent, UserEvent},
+    keys,
+    payload::Payload,
+    Client, Error, Update,
+};
+
+const SERVER: &str = "ws://127.0.0.1:8081/chat";
+const KEY_FILE: &str = "chat-key.pem";
+/// How long `send` waits for the server to report on delivery.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "\
+usage: chat-cli [options] [command]
+
+commands:
+  chat                 talk interactively (the default)
+  tui                  full-screen terminal interface
+  key                  print this client's public key
+  users                list who is online
+  send <id> [text]     send one message, read from stdin without text
+  listen               print incoming messages until stopped
+
+options:
+  --server <url>       server to connect to [env CHAT_SERVER, default ws://127.0.0.1:8081/chat]
+  --key <path>         key pair file, created on first use [env CHAT_KEY, default chat-key.pem]
+  --count <n>          listen: stop after n messages
+  --timeout <seconds>  listen: stop after this long
+  --accept             listen: accept every contact request";
+
+const HELP: &str = "\
+/users          list who is online
+/to <id>        write to someone
+/accept <id>    accept a contact request
+/decline <id>   decline a contact request
+/block <id>     block someone
+/unblock <id>   unblock someone
+/trust <id>     accept someone's new keys
+/quit           leave
+anything else is sent to whoever /to picked";
+
+enum Command {
+    Chat,
+    Tui,
+    Key,
+    Users,
+    Send { to: usize, text: Option<String> },
+    Listen,
+}
+
+struct Options {
+    server: String,
+    key: PathBuf,
+    count: Option<usize>,
+    timeout: Option<u64>,
+    accept: bool,
+    command: Command,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Options {
+            server: env::var("CHAT_SERVER").unwrap_or_else(|_| SERVER.to_string()),
+            key: env::var("CHAT_KEY").unwrap_or_else(|_| KEY_FILE.to_string()).into(),
+            count: None,
+            timeout: None,
+            accept: false,
+            command: Command::Chat,
+        };
+        let mut positional = Vec::new();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+            match arg.as_str() {
+                "--server" => options.server = value("--server")?,
+                "--key" => options.key = value("--key")?.into(),
+                "--count" => options.count = Some(number(&value("--count")?)?),
+                "--timeout" => options.timeout = Some(number(&value("--timeout")?)?),
+                "--accept" => options.accept = true,
+                "-h" | "--help" => return Err(String::new()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => positional.push(arg)
+            }
+        }
+        let mut positional = positional.into_iter();
+        options.command = match positional.next().as_deref() {
+            None | Some("chat") => Command::Chat,
+            Some("tui") => Command::Tui,
+            Some("key") => Command::Key,
+            Some("users") => Command::Users,
+            Some("listen") => Command::Listen,
+            Some("send") => {
+                let to = positional.next().ok_or("send needs a user id")?;
+                let text: Vec<String> = positional.by_ref().collect();
+                Command::Send { to: id(&to)?, text: (!text.is_empty()).then(|| text.join(" ")) }
+            }
+            Some(other) => return Err(format!("unknown command {}", other))
+        };
+        if positional.next().is_some() {
+            return Err("too many arguments".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} is not a number", value))
+}
+
+/// User ids may be written as `#5`, the way they are printed.
+fn id(value: &str) -> Result<usize, String> {
+    number(value.trim_start_matches('#'))
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    match run(options).await {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the command, `Ok(false)` when it ran but didn't succeed.
+async fn run(options: Options) -> Result<bool, Error> {
+    let identity = keys::load_or_create(&options.key)?;
+    match options.command {
+        Command::Key => {
+            println!("{}", identity.public_key());
+            return Ok(true);
+        }
+        Command::Tui => {
+            tui::run(&options.server, identity).await?;
+            return Ok(true);
+        }
+        _ => ()
+    }
+    let text = match &options.command {
+        Command::Send { text: None, .. } => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            Some(text.trim_end().to_string())
+        }
+        Command::Send { text, .. } => text.clone(),
+        _ => None
+    };
+    let mut client = Client::connect(&options.server, identity).await?;
+    eprintln!("connected as #{}", client.my_id().unwrap_or_default());
+    let done = match options.command {
+        Command::Users => {
+            print_users(&client);
+            true
+        }
+        Command::Send { to, .. } => send(&mut client, to, text.unwrap_or_default()).await?,
+        Command::Listen => listen(&mut client, &options).await?,
+        Command::Chat => chat(&mut client).await?,
+        Command::Key | Command::Tui => unreachable!()
+    };
+    client.close().await;
+    Ok(done)
+}
+
+fn print_users(client: &Client) {
+    for (id, dialog) in client.state.dialogs.iter().filter(|(id, _)| Some(**id) != client.my_id()) {
+        println!(
+            "#{}\t{}\t{} device(s)\t{}{}",
+            id,
+            if dialog.online { "online" } else { "offline" },
+            dialog.devices.len().max(1),
+            dialog.safety_number.clone().unwrap_or_default(),
+            if dialog.key_changed { "\tkeys changed" } else { "" }
+        );
+    }
+}
+
+async fn send(client: &mut Client, to: usize, text: String) -> Result<bool, Error> {
+    let random_id = client.send_payload(to, Payload::Text { text, reply: None }).await?;
+    let delivered = tokio::time::timeout(DELIVERY_TIMEOUT, async {
+        while let Some(update) = client.recv().await? {
+            if let Update::Event(SystemEvent::MessageStatus { random_id: id, status, .. }) = update {
+                if id == random_id {
+                    return Ok(status);
+                }
+            }
+        }
+        Ok::<bool, Error>(false)
+    }).await.unwrap_or(Ok(false))?;
+    if !delivered {
+        eprintln!("message to #{} was not delivered", to);
+    }
+    Ok(delivered)
+}
+
+async fn listen(client: &mut Client, options: &Options) -> Result<bool, Error> {
+    let mut count = 0;
+    let listening = async {
+        while let Some(update) = client.recv().await? {
+            match update {
+                Update::Event(SystemEvent::ContactRequest(from)) if options.accept => client.send(UserEvent::AcceptContact(from)).await?,
+                Update::Message { .. } => {
+                    if let Some(line) = describe(client, &update) {
+                        println!("{}", line);
+                    }
+                    count += 1;
+                    if options.count.is_some_and(|x| count >= x) {
+                        break;
+                    }
+                }
+                update => if let Some(line) = describe(client, &update) {
+                    eprintln!("{}", line);
+                }
+            }
+        }
+        Ok::<(), Error>(())
+    };
+    match options.timeout {
+        Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), listening).await.unwrap_or(Ok(()))?,
+        None => listening.await?
+    }
+    Ok(true)
+}
+
+async fn chat(client: &mut Client) -> Result<bool, Error> {
+    eprintln!("{}", HELP);
+    let mut lines = BufReader::new(io::stdin()).lines();
+    let mut dialog: Option<usize> = None;
+    loop {
+        tokio::select! {
+            update = client.recv() => match update? {
+                Some(update) => if let Some(line) = describe(client, &update) {
+                    println!("{}", line);
+                },
+                None => {
+                    eprintln!("the server closed the connection");
+                    return Ok(false);
+                }
+            },
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    None => return Ok(true)
+                };
+                let (command, argument) = match line.split_once(' ') {
+                    Some((command, argument)) => (command, argument.trim()),
+                    None => (line.as_str(), "")
+                };
+                let event = match command {
+                    "/quit" => return Ok(true),
+                    "/help" => {
+                        eprintln!("{}", HELP);
+                        continue;
+                    }
+                    "/users" => {
+                        print_users(client);
+                        continue;
+                    }
+                    "/to" => {
+                        match id(argument) {
+                            Ok(to) if client.state.dialogs.contains_key(&to) => {
+                                dialog = Some(to);
+                                let number = client.state.dialogs[&to].safety_number.clone();
+                                eprintln!("writing to #{}, safety number {}", to, number.unwrap_or_default());
+                            }
+                            _ => eprintln!("there is no user {}", argument)
+                        }
+                        continue;
+                    }
+                    "/trust" => {
+                        match id(argument) {
+                            Ok(other) if client.state.dialogs.contains_key(&other) => {
+                                client.accept_keys(other);
+                                let number = client.state.dialogs[&other].safety_number.clone();
+                                eprintln!("accepted the keys of #{}, safety number {}", other, number.unwrap_or_default());
+                            }
+                            _ => eprintln!("there is no user {}", argument)
+                        }
+                        continue;
+                    }
+                    "/accept" | "/decline" | "/block" | "/unblock" => {
+                        let other = match id(argument) {
+                            Ok(other) => other,
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                continue;
+                            }
+                        };
+                        match command {
+                            "/accept" => UserEvent::AcceptContact(other),
+                            "/decline" => UserEvent::DeclineContact(other),
+                            "/block" => UserEvent::Block(other),
+                            _ => UserEvent::Unblock(other)
+                        }
+                    }
+                    _ if command.starts_with('/') => {
+                        eprintln!("unknown command {}, try /help", command);
+                        continue;
+                    }
+                    _ => {
+                        match dialog {
+                            Some(to) if !line.trim().is_empty() => {
+                                if let Err(e) = client.send_payload(to, Payload::Text { text: line, reply: None }).await {
+                                    eprintln!("{}", e);
+                                }
+                            }
+                            Some(_) => (),
+                            None => eprintln!("pick someone to write to with /to <id> first")
+                        }
+                        continue;
+                    }
+                };
+                client.send(event).await?;
+            }
+        }
+    }
+}
+
+/// How an update reads in the terminal, `None` for bookkeeping nobody needs to see.
+fn describe(client: &Client, update: &Update) -> Option<String> {
+    let event = match update {
+        Update::Message { dialog, from, payload, .. } => {
+            let who = match Some(*from) == client.my_id() {
+                true => format!("me → #{}", dialog),
+                false => format!("#{}", from)
+            };
+            return Some(payload.describe(&who));
+        }
+        Update::Event(event) => event
+    };
+    match event {
+        SystemEvent::MessageStatus { random_id, status: false, .. } => Some(format!("message {} was not delivered", random_id)),
+        SystemEvent::UserIn(user) if client.state.dialogs.get(&user.id).is_some_and(|x| x.key_changed) => {
+            Some(format!("#{} has new keys, compare safety numbers and /trust {} to write to them", user.id, user.id))
+        }
+        SystemEvent::UserIn(user) if Some(user.id) != client.my_id() => Some(format!("#{} is online", user.id)),
+        SystemEvent::UserOut(user) if Some(user.id) != client.my_id() => Some(format!("#{} went offline", user.id)),
+        SystemEvent::ContactRequest(from) => Some(format!("#{} wants to write to you, /accept {} to let them", from, from)),
+        SystemEvent::BlockList(blocked) => Some(format!("blocked: {:?}", blocked)),
+        SystemEvent::Revoked => Some("this device was unlinked from its account".to_string()),
+        _ => None
+    }
+}
@@ -0,0 +1,292 @@
+use std::{future::Future, pin::Pin, time::Duration};
+use client_core::{
+    attachment::format_size,
+    data::SystemEvent,
+    fingerprint,
+    message::{Message, MessageState},
+    payload::Payload,
+    state::{Input, State},
+};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::StreamExt;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use chat_sdk::{Client, Error, Identity, Update};
+
+/// How long to wait before trying a dropped connection again.
+const RETRY: Duration = Duration::from_secs(3);
+
+/// A connection on its way, after waiting out the retry delay.
+type Connecting = Pin<Box<dyn Future<Output = Result<Client, Error>>>>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Status {
+    Connecting,
+    Online,
+    Reconnecting,
+}
+
+struct App {
+    identity: Identity,
+    fingerprint: String,
+    status: Status,
+    /// The dialogs while there is no connection to hold them, so they
+    /// survive a reconnect.
+    state: State,
+    input: String,
+    /// The last thing worth telling that doesn't belong to a dialog.
+    notice: Option<String>,
+}
+
+pub async fn run(url: &str, identity: Identity) -> Result<(), Error> {
+    let mut terminal = ratatui::init();
+    let result = App::new(identity).run(&mut terminal, url).await;
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(identity: Identity) -> Self {
+        let fingerprint = fingerprint::key_fingerprint(&identity.public_key()).unwrap_or_default();
+        let state = State::new(identity.public_key());
+        Self {
+            identity,
+            fingerprint,
+            status: Status::Connecting,
+            state,
+            input: String::new(),
+            notice: None,
+        }
+    }
+    async fn run(&mut self, terminal: &mut DefaultTerminal, url: &str) -> Result<(), Error> {
+        let mut events = EventStream::new();
+        let mut client: Option<Client> = None;
+        // raced against the keyboard below, so Esc works while it connects
+        let mut connecting: Option<Connecting> = Some(self.connect(url, Duration::ZERO));
+        loop {
+            terminal.draw(|frame| self.draw(frame, client.as_ref()))?;
+            tokio::select! {
+                update = async { client.as_mut().unwrap().recv().await }, if client.is_some() => match update {
+                    Ok(Some(update)) => self.handle_update(update),
+                    _ => {
+                        self.state = client.take().unwrap().state;
+                        self.status = Status::Reconnecting;
+                        connecting = Some(self.connect(url, RETRY));
+                    }
+                },
+                connected = async { connecting.as_mut().unwrap().await }, if connecting.is_some() => {
+                    connecting = None;
+                    match connected {
+                        Ok(mut connected) => {
+                            // what was picked while it connected
+                            connected.state.selected = self.state.selected;
+                            self.status = Status::Online;
+                            self.notice = None;
+                            client = Some(connected);
+                        }
+                        Err(e) => {
+                            self.status = Status::Reconnecting;
+                            self.notice = Some(e.to_string());
+                            connecting = Some(self.connect(url, RETRY));
+                        }
+                    }
+                },
+                event = events.next() => match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        if !self.handle_key(client.as_mut(), key).await {
+                            break;
+                        }
+                    }
+                    Some(Ok(_)) => (),
+                    _ => break
+                }
+            }
+        }
+        if let Some(client) = client {
+            client.close().await;
+        }
+        Ok(())
+    }
+    /// Connects after `delay`, picking up the dialogs as they are now.
+    fn connect(&self, url: &str, delay: Duration) -> Connecting {
+        let (url, identity, state) = (url.to_string(), self.identity.clone(), self.state.clone());
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            Client::resume(&url, identity, state).await
+        })
+    }
+    /// The state to draw: the connection's while there is one.
+    fn state<'a>(&'a self, client: Option<&'a Client>) -> &'a State {
+        client.map_or(&self.state, |x| &x.state)
+    }
+    fn handle_update(&mut self, update: Update) {
+        match update {
+            Update::Event(SystemEvent::ContactRequest(from)) => {
+                self.notice = Some(format!("#{} wants to write to you, answer to accept", from));
+            }
+            Update::Event(SystemEvent::Revoked) => self.notice = Some("this device was unlinked from its account".to_string()),
+            _ => ()
+        }
+    }
+    /// Returns `false` when it's time to quit.
+    async fn handle_key(&mut self, mut client: Option<&mut Client>, key: KeyEvent) -> bool {
+        let state = match client.as_deref_mut() {
+            Some(client) => &mut client.state,
+            None => &mut self.state
+        };
+        let ids: Vec<usize> = state.dialogs.keys().copied().collect();
+        let position = state.selected.and_then(|id| ids.iter().position(|x| *x == id));
+        match key.code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => if let Some(id) = state.selected {
+                state.handle(Input::AcceptKeys(id));
+            },
+            KeyCode::Up | KeyCode::BackTab if !ids.is_empty() => {
+                let next = position.map_or(ids.len() - 1, |x| x.checked_sub(1).unwrap_or(ids.len() - 1));
+                state.handle(Input::Select(Some(ids[next])));
+            }
+            KeyCode::Down | KeyCode::Tab if !ids.is_empty() => {
+                let next = position.map_or(0, |x| (x + 1) % ids.len());
+                state.handle(Input::Select(Some(ids[next])));
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Enter => {
+                let (to, client) = match (state.selected, client) {
+                    (Some(to), Some(client)) if !self.input.trim().is_empty() => (to, client),
+                    _ => return true
+                };
+                let text = std::mem::take(&mut self.input);
+                if let Err(e) = client.send_payload(to, Payload::Text { text: text.clone(), reply: None }).await {
+                    self.input = text;
+                    self.notice = Some(e.to_string());
+                }
+            }
+            _ => ()
+        }
+        true
+    }
+    fn draw(&self, frame: &mut Frame, client: Option<&Client>) {
+        let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list, chat] = Layout::horizontal([Constraint::Percentage(30), Constraint::Min(0)]).areas(main);
+        self.draw_dialogs(frame, list, client);
+        self.draw_chat(frame, chat, client);
+        frame.render_widget(Paragraph::new(self.status_line(client)), status);
+    }
+    fn draw_dialogs(&self, frame: &mut Frame, area: Rect, client: Option<&Client>) {
+        let state = self.state(client);
+        let items: Vec<ListItem> = state.dialogs.values().map(|dialog| {
+            let name = if Some(dialog.id) == state.my_id { "Me".to_string() } else { format!("User#{}", dialog.id) };
+            let mut spans = vec![Span::raw(name)];
+            if !dialog.online {
+                spans.push(Span::styled(" offline", Style::default().fg(Color::DarkGray)));
+            }
+            if dialog.is_request {
+                spans.push(Span::styled(" request", Style::default().fg(Color::Yellow)));
+            }
+            if dialog.unchecked_count > 0 {
+                let count = format!(" ({})", dialog.unchecked_count);
+                spans.push(Span::styled(count, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)));
+            }
+            ListItem::new(Line::from(spans))
+        }).collect();
+        let selected = state.selected.and_then(|id| state.dialogs.keys().position(|x| *x == id));
+        let mut list_state = ListState::default().with_selected(selected);
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Dialogs"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut list_state);
+    }
+    fn draw_chat(&self, frame: &mut Frame, area: Rect, client: Option<&Client>) {
+        let [head, messages, input] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(3),
+        ]).areas(area);
+        let state = self.state(client);
+        let dialog = state.selected.and_then(|id| state.dialogs.get(&id));
+        let title = match dialog {
+            Some(dialog) => {
+                let number = dialog.safety_number.as_ref().map(|x| format!("safety number {}", x));
+                let warning = if dialog.key_changed { "  new keys, compare and press Ctrl+T to accept" } else { "" };
+                format!("User#{}  {}{}", dialog.id, number.unwrap_or_default(), warning)
+            }
+            None => "Choose a dialog with ↑/↓".to_string()
+        };
+        frame.render_widget(Paragraph::new(title).block(Block::default().borders(Borders::ALL)), head);
+
+        let width = messages.width.saturating_sub(2).max(1) as usize;
+        let mut lines: Vec<Line> = Vec::new();
+        for message in dialog.map_or(&[][..], |x| &x.messages[..]) {
+            let mine = Some(message.from) == state.my_id;
+            let (who, style) = if mine {
+                ("me".to_string(), Style::default().fg(Color::Cyan))
+            } else {
+                (format!("#{}", message.from), Style::default().fg(Color::Green))
+            };
+            let mark = match message.state {
+                MessageState::Sent if mine => " ✓",
+                MessageState::Failed => " ✗ not delivered",
+                _ => ""
+            };
+            let text = format!("{}: {}{}", who, text_of(message), mark);
+            let chars: Vec<char> = text.chars().collect();
+            for chunk in chars.chunks(width) {
+                lines.push(Line::styled(chunk.iter().collect::<String>(), style));
+            }
+        }
+        let height = messages.height.saturating_sub(2) as usize;
+        let skip = lines.len().saturating_sub(height);
+        frame.render_widget(
+            Paragraph::new(lines.split_off(skip)).block(Block::default().borders(Borders::ALL)),
+            messages
+        );
+        frame.render_widget(
+            Paragraph::new(self.input.as_str()).block(Block::default().borders(Borders::ALL).title("Message")),
+            input
+        );
+        let cursor = self.input.chars().count() as u16;
+        frame.set_cursor_position((input.x + 1 + cursor.min(input.width.saturating_sub(3)), input.y + 1));
+    }
+    fn status_line(&self, client: Option<&Client>) -> Line<'_> {
+        let (state, color) = match self.status {
+            Status::Connecting => ("connecting".to_string(), Color::Yellow),
+            Status::Online => (format!("online as #{}", client.and_then(|x| x.my_id()).unwrap_or_default()), Color::Green),
+            Status::Reconnecting => ("reconnecting".to_string(), Color::Red),
+        };
+        let mut spans = vec![
+            Span::styled(format!("● {}", state), Style::default().fg(color)),
+            Span::raw(format!("  key {}", self.fingerprint)),
+        ];
+        match &self.notice {
+            Some(notice) => spans.push(Span::styled(format!("  {}", notice), Style::default().fg(Color::Yellow))),
+            None => spans.push(Span::styled("  ↑/↓ dialogs · Enter send · Esc quit", Style::default().fg(Color::DarkGray)))
+        }
+        Line::from(spans)
+    }
+}
+
+/// A message as one line of the message pane, before wrapping.
+fn text_of(message: &Message) -> String {
+    let mut text = match (&message.attachment, &message.reply) {
+        (Some(file), _) => format!("📎 {} ({})", file.name, format_size(file.size)),
+        (None, Some(reply)) => format!("↪ \"{}\" {}", reply.snippet, message.content),
+        (None, None) => message.content.clone()
+    };
+    if message.edited {
+        text.push_str(" (edited)");
+    }
+    for emoji in message.reactions.values() {
+        text.push(' ');
+        text.push_str(emoji);
+    }
+    text
+}
@@ -0,0 +1,70 @@
+use js_sys::{Array, Uint8Array};
+use reqwasm::http::Request;
+use sha2::{Digest, Sha256};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, File, Url};
+use crate::crypt::SecretKey;
+
+pub use client_core::attachment::{format_size, Attachment, Preview};
+
+/// Raw bytes per upload chunk; base64 keeps the frame under actix's 64 KiB limit.
+pub const CHUNK: usize = 32 * 1024;
+
+/// An encrypted file waiting for the server to assign it a blob.
+pub struct Sealed {
+    pub attachment: Attachment,
+    pub data: Vec<u8>,
+}
+
+fn error(e: impl ToString) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Encrypts `file` under a fresh key that only ever travels inside the message.
+pub async fn seal(file: &File) -> Result<Sealed, JsValue> {
+    let plain = Uint8Array::new(&JsFuture::from(file.array_buffer()).await?).to_vec();
+    let mut raw = [0u8; 32];
+    getrandom::getrandom(&mut raw).map_err(error)?;
+    let mut iv = [0u8; 12];
+    getrandom::getrandom(&mut iv).map_err(error)?;
+    let data = SecretKey::import(&raw).await?.encrypt(&iv, &plain).await?;
+    Ok(Sealed {
+        attachment: Attachment {
+            blob: String::new(),
+            name: file.name(),
+            mime: file.type_(),
+            size: plain.len() as u64,
+            key: base64::encode(raw),
+            iv: base64::encode(iv),
+            digest: base64::encode(Sha256::digest(&data)),
+            // the local file previews our own copy without a round trip
+            preview: Url::create_object_url_with_blob(file).map(Preview::Ready).unwrap_or_default(),
+        },
+        data,
+    })
+}
+
+/// Downloads, verifies and decrypts the attachment's blob.
+pub async fn open(server: &str, attachment: &Attachment) -> Result<Vec<u8>, JsValue> {
+    let response = Request::get(&format!("{}/blobs/{}", server, attachment.blob))
+        .send().await
+        .map_err(error)?;
+    if !response.ok() {
+        return Err(JsValue::from_str("attachment is no longer available"));
+    }
+    let data = response.binary().await.map_err(error)?;
+    if base64::encode(Sha256::digest(&data)) != attachment.digest {
+        return Err(JsValue::from_str("attachment digest mismatch"));
+    }
+    let key = SecretKey::import(&base64::decode(&attachment.key).map_err(error)?).await?;
+    let iv = base64::decode(&attachment.iv).map_err(error)?;
+    key.decrypt(&iv, &data).await
+}
+
+pub fn object_url(data: &[u8], mime: &str) -> Result<String, JsValue> {
+    let parts = Array::of1(&Uint8Array::from(data));
+    let mut options = BlobPropertyBag::new();
+    options.type_(mime);
+    Url::create_object_url_with_blob(&Blob::new_with_u8_array_sequence_and_options(&parts, &options)?)
+}
@@ -1,151 +1,344 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
+use client_core::{crypto::{Backend, Job}, native::{self, IV, WRAPPED_KEY}, payload::Payload, state::Input};
 use futures::lock::Mutex;
-use js_sys::{Object, Uint8Array, Map, Reflect, Array};
+use js_sys::{Object, Uint8Array, Map, Array};
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::{spawn_local, JsFuture};
-use web_sys::{window, console};
+use web_sys::{window, console, CryptoKey, SubtleCrypto};
 use yew::Callback;
-use crate::{Chat, rsa_crypto::RsaCrypto, user::SafeUser, dialogs::MiniDialog};
+use crate::{Chat, rsa_crypto::{KeyPair, RsaCrypto}, storage, user::SafeUser};
 
-const EXPONENT: [u8; 3] = [1u8, 0u8, 1u8];
-const BITS: usize = 2048;
+/// Local storage key that forces a backend, `web` or `native`.
+pub const BACKEND: &str = "crypto";
 
-pub trait Crypt {
-    fn get_rsa() -> Arc<Mutex<RsaCrypto>>;
-    fn go_crypt(&self, callback: Callback<String>);
-    fn send_public_key(&self, callback: Callback<String>);
-    fn go_decrypt(&self, data: String, callback: Callback<String>);
-    fn parse_user(&self, user: SafeUser, callback: Callback<MiniDialog>);
+/// Whether WebCrypto does the work. Browsers only offer it on secure
+/// origins; elsewhere, or when `BACKEND` says `native`, the pure-Rust
+/// backend of `client_core::native` takes over. Both read and write the
+/// same keys and envelopes.
+pub fn web_crypto() -> bool {
+    match storage::get(BACKEND).as_deref() {
+        Some("native") => false,
+        _ => window().is_some_and(|x| x.is_secure_context())
+    }
 }
 
-impl Crypt for Chat {
-    fn get_rsa() -> Arc<Mutex<RsaCrypto>> {
-        let w = window().unwrap();
-        let crypt = w.crypto().unwrap();
-        let subtle_crypto = crypt.subtle();
-        let array = ["encrypt", "decrypt"].iter()
+pub fn subtle() -> SubtleCrypto {
+    window()
+        .and_then(|x| x.crypto().ok())
+        .map(|x| x.subtle())
+        .unwrap()
+}
+
+fn error(e: impl ToString) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+pub fn aes_gcm(iv: &Uint8Array) -> Result<Object, JsValue> {
+    let a = Map::new();
+    a.set(&"name".into(), &"AES-GCM".into());
+    a.set(&"iv".into(), iv);
+    Object::from_entries(&a)
+}
+
+fn message_key() -> Result<Object, JsValue> {
+    let a = Map::new();
+    a.set(&"name".into(), &"AES-GCM".into());
+    a.set(&"length".into(), &256.into());
+    Object::from_entries(&a)
+}
+
+/// An AES-256-GCM key in whichever backend is in use.
+#[derive(Clone, Debug)]
+pub enum SecretKey {
+    Web(CryptoKey),
+    Native(Vec<u8>),
+}
+
+impl SecretKey {
+    /// Takes up raw key bytes, not extractable again through WebCrypto.
+    pub async fn import(raw: &[u8]) -> Result<Self, JsValue> {
+        if !web_crypto() {
+            return Ok(SecretKey::Native(raw.to_vec()));
+        }
+        let usages = ["encrypt", "decrypt"].iter()
             .map(|x| JsValue::from_str(x))
             .collect::<Array>();
-        let algorithm = {
-            let a = Map::new();
-            a.set(&"name".into(), &"RSA-OAEP".into());
-            a.set(&"publicExponent".into(), &Uint8Array::new(
-                &EXPONENT.map(|x| JsValue::from(x)).iter().collect::<Array>()));
-            a.set(&"modulusLength".into(), &BITS.into());
-            a.set(&"hash".into(), &"SHA-256".into());
-            Object::from_entries(&a).unwrap()
-        };
+        let key = subtle().import_key_with_object("raw", &Uint8Array::from(raw), &message_key()?, false, &usages)?;
+        Ok(SecretKey::Web(JsFuture::from(key).await?.into()))
+    }
+    pub async fn encrypt(&self, iv: &[u8], plain: &[u8]) -> Result<Vec<u8>, JsValue> {
+        match self {
+            SecretKey::Web(key) => {
+                let data = subtle().encrypt_with_object_and_buffer_source(&aes_gcm(&Uint8Array::from(iv))?, key, &Uint8Array::from(plain))?;
+                Ok(Uint8Array::new(&JsFuture::from(data).await?).to_vec())
+            }
+            SecretKey::Native(key) => native::encrypt(key, iv, plain).map_err(error)
+        }
+    }
+    pub async fn decrypt(&self, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        match self {
+            SecretKey::Web(key) => {
+                let plain = subtle().decrypt_with_object_and_buffer_source(&aes_gcm(&Uint8Array::from(iv))?, key, &Uint8Array::from(data))?;
+                Ok(Uint8Array::new(&JsFuture::from(plain).await?).to_vec())
+            }
+            SecretKey::Native(key) => native::decrypt(key, iv, data).map_err(error)
+        }
+    }
+}
 
-        let rsa = Arc::new(Mutex::new(RsaCrypto::new()));
-        let promise_key = subtle_crypto.generate_key_with_object(&algorithm, true, &array).unwrap();
-        let future_key = JsFuture::from(promise_key);
+/// RSA-OAEP alone fits ~190 bytes, so messages are sealed under a one-off
+/// AES-GCM key and only that key goes through RSA:
+/// `wrapped key (256) || iv (12) || ciphertext`.
+async fn seal(public_key: &CryptoKey, plain: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let subtle = subtle();
+    let usages = Array::of1(&"encrypt".into());
+    let key: CryptoKey = JsFuture::from(subtle.generate_key_with_object(&message_key()?, true, &usages)?).await?.into();
+    let raw = JsFuture::from(subtle.export_key("raw", &key)?).await?;
+    let wrapped = JsFuture::from(subtle.encrypt_with_str_and_buffer_source("RSA-OAEP", public_key, &raw.into())?).await?;
+    let mut iv = [0u8; IV];
+    getrandom::getrandom(&mut iv).map_err(error)?;
+    let data = SecretKey::Web(key).encrypt(&iv, plain).await?;
+    let mut envelope = Uint8Array::new(&wrapped).to_vec();
+    envelope.extend_from_slice(&iv);
+    envelope.extend(data);
+    Ok(envelope)
+}
+
+async fn open(private_key: &CryptoKey, envelope: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let subtle = subtle();
+    if envelope.len() == WRAPPED_KEY {
+        let plain = subtle.decrypt_with_str_and_buffer_source("RSA-OAEP", private_key, &Uint8Array::from(envelope))?;
+        return Ok(Uint8Array::new(&JsFuture::from(plain).await?).to_vec());
+    }
+    if envelope.len() < WRAPPED_KEY + IV {
+        return Err(JsValue::from_str("envelope is too short"));
+    }
+    let (wrapped, rest) = envelope.split_at(WRAPPED_KEY);
+    let (iv, data) = rest.split_at(IV);
+    let raw = subtle.decrypt_with_str_and_buffer_source("RSA-OAEP", private_key, &Uint8Array::from(wrapped))?;
+    let raw = Uint8Array::new(&JsFuture::from(raw).await?).to_vec();
+    SecretKey::import(&raw).await?.decrypt(iv, data).await
+}
+
+async fn import_public(key: &str) -> Result<CryptoKey, JsValue> {
+    let decode_key = base64::decode(key).map_err(error)?;
+    let object_key = Uint8Array::from(&decode_key[..]);
+    let array = Array::of1(&"encrypt".into());
+    let algorithm = {
+        let a = Map::new();
+        a.set(&"name".into(), &"RSA-OAEP".into());
+        a.set(&"hash".into(), &"SHA-256".into());
+        Object::from_entries(&a)?
+    };
+    let future_key = subtle().import_key_with_object("spki", &object_key, &algorithm, true, &array)?;
+    Ok(JsFuture::from(future_key).await?.into())
+}
+
+/// Whether `key` is a base64 SPKI public key we can encrypt for.
+async fn check_public(key: &str) -> Result<(), JsValue> {
+    match web_crypto() {
+        true => import_public(key).await.map(|_| ()),
+        false => native::import_public(key).map(|_| ()).map_err(error)
+    }
+}
+
+fn describe(e: JsValue) -> String {
+    e.as_string().unwrap_or_else(|| format!("{:?}", e))
+}
+
+impl Backend for KeyPair {
+    type Error = String;
+
+    async fn public_key(&self) -> Result<String, String> {
+        match self {
+            KeyPair::Web { public_key, .. } => {
+                let export_key = subtle().export_key("spki", public_key).map_err(describe)?;
+                let array_key = JsFuture::from(export_key).await.map_err(describe)?;
+                Ok(base64::encode(Uint8Array::new(&array_key).to_vec()))
+            }
+            KeyPair::Native(identity) => Ok(identity.public_key())
+        }
+    }
+    async fn seal(&self, key: &str, plain: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            KeyPair::Web { .. } => seal(&import_public(key).await.map_err(describe)?, plain).await.map_err(describe),
+            KeyPair::Native(identity) => identity.seal(key, plain).await.map_err(|e| e.to_string())
+        }
+    }
+    async fn open(&self, envelope: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            KeyPair::Web { private_key, .. } => open(private_key, envelope).await.map_err(describe),
+            KeyPair::Native(identity) => identity.open(envelope).map_err(|e| e.to_string())
+        }
+    }
+}
+
+pub trait Crypt {
+    fn get_rsa(ready: Callback<()>, failed: Callback<String>) -> Arc<Mutex<RsaCrypto>>;
+    fn send_public_key(&self, callback: Callback<String>);
+    fn perform(&self, job: Job, callback: Callback<Input>);
+    fn parse_user(&self, user: SafeUser, callback: Callback<SafeUser>);
+    fn keep(&self, payload: Payload, callback: Callback<String>);
+    fn reseal(&self, copy: String, keys: Vec<(usize, String)>, callback: Callback<HashMap<usize, String>>);
+    fn rotate(&self, copies: Vec<(usize, String)>, kept: Callback<(usize, String)>, ready: Callback<()>);
+}
 
+/// Seals `plain` for our own key.
+async fn seal_own(backend: &KeyPair, plain: &[u8]) -> Result<String, String> {
+    let key = backend.public_key().await?;
+    Ok(base64::encode(backend.seal(&key, plain).await?))
+}
+
+/// Opens a copy sealed by `seal_own`.
+async fn open_own(backend: &KeyPair, copy: &str) -> Result<Vec<u8>, String> {
+    let envelope = base64::decode(copy).map_err(|e| e.to_string())?;
+    backend.open(&envelope).await
+}
+
+/// Seals `payload` for our own key.
+async fn keep(backend: &KeyPair, payload: &Payload) -> Result<String, String> {
+    let plain = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+    seal_own(backend, &plain).await
+}
+
+/// Opens a copy made by `keep` and seals it for each of `keys`, by device id.
+async fn reseal(backend: &KeyPair, copy: &str, keys: Vec<(usize, String)>) -> Result<HashMap<usize, String>, String> {
+    let plain = open_own(backend, copy).await?;
+    let mut messages = HashMap::new();
+    for (device, key) in keys {
+        messages.insert(device, base64::encode(backend.seal(&key, &plain).await?));
+    }
+    Ok(messages)
+}
+
+impl Crypt for Chat {
+    /// Loads our identity, making one only when there is none stored. A
+    /// storage error is not that: making a new one would replace the
+    /// identity for good, so it goes to `failed` instead.
+    fn get_rsa(ready: Callback<()>, failed: Callback<String>) -> Arc<Mutex<RsaCrypto>> {
+        let rsa = Arc::new(Mutex::new(RsaCrypto::new()));
         spawn_local({
             let rsa = rsa.clone();
             async move {
                 let mut rsa_lock = rsa.lock().await;
-                let keys = future_key.await.unwrap();
-                let public_key = Reflect::get(&keys, &JsValue::from_str("publicKey")).unwrap();
-                let private_key = Reflect::get(&keys, &JsValue::from_str("privateKey")).unwrap();
-                rsa_lock.set_private(private_key.into()).set_public(public_key.into());
+                match rsa_lock.load().await {
+                    Ok(true) => (),
+                    Ok(false) => if let Err(e) = rsa_lock.rotate().await {
+                        console::error_2(&JsValue::from_str("could not store keys:"), &e);
+                    },
+                    Err(e) => {
+                        console::error_2(&JsValue::from_str("could not load keys:"), &e);
+                        return failed.emit(describe(e));
+                    }
+                }
+                if rsa_lock.is_ready() {
+                    ready.emit(());
+                }
             }
         });
         rsa
     }
-    fn parse_user(&self, user: SafeUser, callback: Callback<MiniDialog>) {
-        let decode_key = base64::decode(user.key).unwrap();
-        let object_key = Uint8Array::from(&decode_key[..]);
-
-        let subtle = window()
-                .and_then(|x| x.crypto().map(|x| Some(x)).unwrap_or(None))
-                .and_then(|x| Some(x.subtle()))
-                .unwrap();
-
-        let array = ["encrypt"].iter()
-            .map(|x| JsValue::from_str(x))
-            .collect::<Array>();
-            
-        let algorithm = {
-            let a = Map::new();
-            a.set(&"name".into(), &"RSA-OAEP".into());
-            a.set(&"hash".into(), &"SHA-256".into());
-            Object::from_entries(&a).unwrap()
-        };
-        
-        let future_key = subtle.import_key_with_object("spki", &object_key, &algorithm, true, &array).unwrap();
-
+    /// Hands on `user` with only the keys we can encrypt for.
+    fn parse_user(&self, mut user: SafeUser, callback: Callback<SafeUser>) {
         spawn_local(async move {
-            let key = JsFuture::from(future_key).await.unwrap();
-            callback.emit(MiniDialog::new(user.id, key.into()))
+            if let Err(e) = check_public(&user.key).await {
+                return console::error_2(&JsValue::from_str("could not import key:"), &e);
+            }
+            let mut devices = vec![];
+            for device in user.devices {
+                match check_public(&device.key).await {
+                    Ok(()) => devices.push(device),
+                    Err(e) => console::error_2(&JsValue::from_str("could not import device key:"), &e)
+                }
+            }
+            user.devices = devices;
+            callback.emit(user)
         });
     }
     fn send_public_key(&self, callback: Callback<String>) {
-        let subtle = window()
-            .and_then(|x| x.crypto().map(|x| Some(x)).unwrap_or(None))
-            .and_then(|x| Some(x.subtle()))
-            .unwrap();
-
         let clone_rsa = self.rsa.clone();
         spawn_local(async move {
             let mutex_rsa = clone_rsa.lock().await;
-            let public_key = mutex_rsa.get_public().unwrap();
-            let export_key = subtle.export_key("spki", public_key).unwrap();
-            let array_key = JsFuture::from(export_key).await.unwrap();
-            let bytes_key = Uint8Array::new(&array_key);
-            callback.emit(base64::encode(bytes_key.to_vec()))
+            match mutex_rsa.get_keys().unwrap().public_key().await {
+                Ok(key) => callback.emit(key),
+                Err(e) => console::error_1(&JsValue::from_str(&format!("could not export key: {}", e)))
+            }
         });
     }
-    fn go_decrypt(&self, data: String, callback: Callback<String>) {
-        let decode_text = base64::decode(data).unwrap();
-        let object_text = Uint8Array::from(&decode_text[..]);
-        console::log_2(&JsValue::from_str("receive data:"), &object_text);
-
-        let subtle = window()
-            .and_then(|x| x.crypto().map(|x| Some(x)).unwrap_or(None))
-            .and_then(|x| Some(x.subtle()))
-            .unwrap();
-
-
+    /// Does a job of the state machine with our keys, handing back what to
+    /// feed it next.
+    fn perform(&self, job: Job, callback: Callback<Input>) {
         let clone_rsa = self.rsa.clone();
         spawn_local(async move {
             let mutex_rsa = clone_rsa.lock().await;
-            let key = mutex_rsa.get_private().unwrap();
-            let result = subtle.decrypt_with_str_and_buffer_source(
-                "RSA-OAEP", 
-                key,
-                &object_text
-            ).unwrap();
-            let future_result = JsFuture::from(result).await
-                .unwrap();
-            let bytes_result = Uint8Array::new(&future_result);
-            callback.emit(String::from_utf8(bytes_result.to_vec()).unwrap());
+            let backend = match mutex_rsa.get_keys() {
+                Some(backend) => backend,
+                None => return console::error_1(&JsValue::from_str("keys are not loaded"))
+            };
+            match job.perform(backend).await {
+                Ok(input) => callback.emit(input),
+                Err(e) => console::error_1(&JsValue::from_str(&format!("crypto failed: {}", e)))
+            }
         });
     }
-    fn go_crypt(&self, callback: Callback<String>) {
-        let decode_text = self.text.as_bytes();
-        let object_text = Uint8Array::from(&decode_text[..]);
-        let dialog_id = match self.dialog_id {
-            Some(id) => id,
-            None => return
-        };
-        let public_key = self.dialogs.get(&dialog_id).unwrap().dialog_key.clone();
-
-        let subtle = window()
-            .and_then(|x| x.crypto().map(|x| Some(x)).unwrap_or(None))
-            .and_then(|x| Some(x.subtle()))
-            .unwrap();
-
+    /// Seals a copy of an outgoing payload for ourselves, so it can be sealed
+    /// again for the devices the recipient has when it gets resent.
+    fn keep(&self, payload: Payload, callback: Callback<String>) {
+        let clone_rsa = self.rsa.clone();
         spawn_local(async move {
-            let result = subtle.encrypt_with_str_and_buffer_source(
-                "RSA-OAEP", 
-                &public_key,
-                &object_text
-            ).unwrap();
-            let future_result = JsFuture::from(result).await
-                .unwrap();
-            let bytes_result = Uint8Array::new(&future_result);
-            console::log_2(&JsValue::from_str("send data:"), &bytes_result);
-            callback.emit(base64::encode(bytes_result.to_vec()));
+            let mutex_rsa = clone_rsa.lock().await;
+            let backend = match mutex_rsa.get_keys() {
+                Some(backend) => backend,
+                None => return console::error_1(&JsValue::from_str("keys are not loaded"))
+            };
+            match keep(backend, &payload).await {
+                Ok(copy) => callback.emit(copy),
+                Err(e) => console::error_1(&JsValue::from_str(&format!("could not keep message: {}", e)))
+            }
         });
     }
-}
\ No newline at end of file
+    fn reseal(&self, copy: String, keys: Vec<(usize, String)>, callback: Callback<HashMap<usize, String>>) {
+        let clone_rsa = self.rsa.clone();
+        spawn_local(async move {
+            let mutex_rsa = clone_rsa.lock().await;
+            let backend = match mutex_rsa.get_keys() {
+                Some(backend) => backend,
+                None => return console::error_1(&JsValue::from_str("keys are not loaded"))
+            };
+            match reseal(backend, &copy, keys).await {
+                Ok(messages) => callback.emit(messages),
+                Err(e) => console::error_1(&JsValue::from_str(&format!("could not seal message: {}", e)))
+            }
+        });
+    }
+    /// Replaces our keys. The outbox `copies` are sealed again for the new
+    /// ones and go to `kept`, as the old ones are gone once they could be
+    /// resent.
+    fn rotate(&self, copies: Vec<(usize, String)>, kept: Callback<(usize, String)>, ready: Callback<()>) {
+        let clone_rsa = self.rsa.clone();
+        spawn_local(async move {
+            let mut mutex_rsa = clone_rsa.lock().await;
+            let mut plains = Vec::new();
+            if let Some(backend) = mutex_rsa.get_keys() {
+                for (random_id, copy) in copies {
+                    match open_own(backend, &copy).await {
+                        Ok(plain) => plains.push((random_id, plain)),
+                        Err(e) => console::warn_1(&JsValue::from_str(&format!("could not open message {}: {}", random_id, e)))
+                    }
+                }
+            }
+            if let Err(e) = mutex_rsa.rotate().await {
+                console::error_2(&JsValue::from_str("could not store keys:"), &e);
+            }
+            if let Some(backend) = mutex_rsa.get_keys() {
+                for (random_id, plain) in plains {
+                    match seal_own(backend, &plain).await {
+                        Ok(copy) => kept.emit((random_id, copy)),
+                        Err(e) => console::error_1(&JsValue::from_str(&format!("could not keep message: {}", e)))
+                    }
+                }
+            }
+            ready.emit(());
+        });
+    }
+}
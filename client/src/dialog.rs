@@ -1,9 +1,18 @@
-use web_sys::{FocusEvent, HtmlInputElement};
-use yew::{Component, Context, html, Html, Callback, Properties, NodeRef};
-use crate::message::Message;
+use std::collections::{HashMap, HashSet};
+use web_sys::{Event, File, FocusEvent, HtmlInputElement, HtmlSelectElement};
+use yew::{Component, Context, html, Html, Callback, Properties, NodeRef, TargetCast};
+use client_core::dialog::{Verification, TIMERS};
+use crate::{message::{self, Actions, Bubble, Message, Reply}, fingerprint};
 
 pub enum Msg {
-    DoCallback
+    DoCallback,
+    ToggleSafety,
+    StartEdit(usize),
+    CancelEdit,
+    StartReply((usize, usize)),
+    CancelReply,
+    Jump((usize, usize)),
+    ToggleThread((usize, usize))
 }
 
 #[derive(Properties, PartialEq)]
@@ -11,17 +20,121 @@ pub struct Props {
     pub me: usize,
     pub id: usize,
     pub messages: Box<Vec<Message>>,
-    pub callback: Callback<String>
+    pub safety_number: Option<String>,
+    pub verification: Verification,
+    pub key_changed: bool,
+    /// Whether the contact is online.
+    pub online: bool,
+    /// Whether we are; messages written meanwhile wait in the outbox.
+    pub connected: bool,
+    pub has_more_history: bool,
+    /// Messages of this conversation that never reached us.
+    pub missing: usize,
+    pub timer: Option<u64>,
+    /// Ticks every second while messages are counting down.
+    pub now: u64,
+    pub callback: Callback<String>,
+    pub on_block: Callback<()>,
+    pub on_verify: Callback<()>,
+    pub on_accept_key: Callback<()>,
+    pub on_load_more: Callback<()>,
+    pub on_clear_history: Callback<()>,
+    pub on_retry: Callback<usize>,
+    pub on_attach: Callback<File>,
+    pub on_edit: Callback<(usize, String)>,
+    pub on_delete: Callback<usize>,
+    pub on_reply: Callback<(Reply, String)>,
+    pub on_react: Callback<((usize, usize), Option<String>)>,
+    pub on_timer: Callback<Option<u64>>
 }
 
 pub struct Dialog {
     pub name: String,
     pub message: NodeRef,
+    pub show_safety: bool,
+    /// The message being edited, its new text is typed into the usual input.
+    pub editing: Option<usize>,
+    /// The message being answered, quoted in the next message sent.
+    pub replying: Option<Reply>,
+    /// Threads folded under their first message, by its `(from, id)`.
+    pub collapsed: HashSet<(usize, usize)>,
+    /// The element to scroll to once the next render has put it on the page.
+    pub jump_to: Option<String>,
 }
 
 impl Dialog {
     pub fn new(name: String) -> Self {
-        Self { name, message: NodeRef::default() }
+        Self {
+            name,
+            message: NodeRef::default(),
+            show_safety: false,
+            editing: None,
+            replying: None,
+            collapsed: HashSet::new(),
+            jump_to: None
+        }
+    }
+    fn view_messages(&self, ctx: &Context<Self>, actions: &Actions) -> Html {
+        let link = ctx.link();
+        let props = ctx.props();
+        let roots = thread_roots(&props.messages);
+        let mut replies: HashMap<(usize, usize), usize> = HashMap::new();
+        for (key, root) in &roots {
+            if key != root {
+                *replies.entry(*root).or_default() += 1;
+            }
+        }
+        let mut last_day = None;
+        props.messages.iter().filter_map(|x| {
+            let key = (x.from, x.id);
+            let root = roots[&key];
+            if root != key && self.collapsed.contains(&root) {
+                return None;
+            }
+            let day = x.stamp.map(|stamp| message::day(stamp.timestamp))
+                .filter(|day| last_day.as_ref() != Some(day));
+            if day.is_some() {
+                last_day = day.clone();
+            }
+            let count = replies.get(&key).copied().unwrap_or(0);
+            let label = if self.collapsed.contains(&key) {
+                format!("Show {} replies", count)
+            } else {
+                format!("Hide {} replies", count)
+            };
+            Some(html! {
+                <>
+                    if let Some(day) = day {
+                        <p class="day">{day}</p>
+                    }
+                    { x.view(props.me, props.now, actions) }
+                    if count != 0 {
+                        <button class="thread-toggle" onclick={link.callback(move |_| Msg::ToggleThread(key))}>{label}</button>
+                    }
+                </>
+            })
+        }).collect()
+    }
+    fn view_safety(&self, ctx: &Context<Self>) -> Html {
+        let props = ctx.props();
+        let number = match &props.safety_number {
+            Some(number) => number,
+            None => return html! {}
+        };
+        let qr = fingerprint::qr_svg(number)
+            .map(|svg| format!("data:image/svg+xml;base64,{}", base64::encode(svg)));
+        html! {
+            <div class="safety">
+                <p class="hint">{"Compare this number with your contact, or scan their code in person."}</p>
+                <p class="number">{number.clone()}</p>
+                if let Some(qr) = qr {
+                    <img class="qr" src={qr} alt="Safety number QR code" />
+                }
+                if props.verification != Verification::Verified {
+                    <button class="verify" onclick={props.on_verify.reform(|_| ())}>{"Mark as verified"}</button>
+                }
+            </div>
+        }
     }
 }
 
@@ -32,7 +145,7 @@ impl Component for Dialog {
     fn create(ctx: &Context<Self>) -> Self {
         let props = ctx.props();
         Self::new(
-            format!("User#{}", props.id), 
+            format!("User#{}", props.id),
         )
     }
 
@@ -43,19 +156,101 @@ impl Component for Dialog {
             event.prevent_default();
             Msg::DoCallback
         });
+        let actions = Actions {
+            retry: props.on_retry.clone(),
+            edit: link.callback(Msg::StartEdit),
+            delete: props.on_delete.clone(),
+            reply: link.callback(Msg::StartReply),
+            jump: link.callback(Msg::Jump),
+            react: props.on_react.clone(),
+        };
+        let on_timer = props.on_timer.clone();
+        let timer_change = Callback::from(move |event: Event| {
+            let select: HtmlSelectElement = event.target_unchecked_into();
+            on_timer.emit(select.value().parse().ok());
+        });
+        let on_attach = props.on_attach.clone();
+        let onchange = Callback::from(move |event: Event| {
+            let input: HtmlInputElement = event.target_unchecked_into();
+            if let Some(file) = input.files().and_then(|x| x.get(0)) {
+                on_attach.emit(file);
+            }
+            input.set_value("");
+        });
         html! {
             <div class="current-dialog">
                 <div class="dialog-head">
                     <div class="avatar"></div>
                     <p class="name">{self.name.clone()}</p>
+                    if props.id != props.me && !props.online {
+                        <p class="presence">{"offline"}</p>
+                    }
+                    if props.id != props.me {
+                        <button class={format!("verification {:?}", props.verification).to_lowercase()} onclick={link.callback(|_| Msg::ToggleSafety)}>
+                            { match props.verification {
+                                Verification::Verified => "Verified",
+                                Verification::Unverified => "Not verified",
+                                Verification::Changed => "Key changed, verify again"
+                            } }
+                        </button>
+                        <button class="block" onclick={props.on_block.reform(|_| ())}>{"Block"}</button>
+                    }
+                    <select class="timer" title="Disappearing messages" onchange={timer_change} disabled={props.key_changed}>
+                        <option value="" selected={props.timer.is_none()}>{"⏱ Off"}</option>
+                        { TIMERS.iter().map(|(seconds, label)| html! {
+                            <option value={seconds.to_string()} selected={props.timer == Some(*seconds)}>{format!("⏱ {}", label)}</option>
+                        }).collect::<Html>() }
+                        if let Some(seconds) = props.timer.filter(|x| !TIMERS.iter().any(|(seconds, _)| seconds == x)) {
+                            <option value={seconds.to_string()} selected=true>{format!("⏱ {}", message::remaining(seconds))}</option>
+                        }
+                    </select>
+                    <button class="clear-history" onclick={props.on_clear_history.reform(|_| ())}>{"Clear history"}</button>
                 </div>
+                if props.key_changed {
+                    <div class="key-warning">
+                        <p>{format!("{} has keys you haven't accepted yet, from a new device or a reinstall. \
+                            It can also mean that someone is impersonating them. \
+                            Compare safety numbers before you continue.", self.name)}</p>
+                        <button onclick={props.on_accept_key.reform(|_| ())}>{"Accept new keys"}</button>
+                    </div>
+                }
+                if self.show_safety {
+                    { self.view_safety(ctx) }
+                }
                 <div class="dialog-messages">
-                    { props.messages.iter().map(|x| x.view(x.from == props.me)).collect::<Html>() }
+                    if props.has_more_history {
+                        <button class="load-more" onclick={props.on_load_more.reform(|_| ())}>{"Load earlier messages"}</button>
+                    }
+                    if props.missing != 0 {
+                        <p class="missing">{format!("{} messages of this conversation didn't reach this device", props.missing)}</p>
+                    }
+                    { self.view_messages(ctx, &actions) }
                 </div>
+                if self.editing.is_some() {
+                    <div class="editing">
+                        <p>{"Editing message"}</p>
+                        <button onclick={link.callback(|_| Msg::CancelEdit)}>{"Cancel"}</button>
+                    </div>
+                }
+                if let Some(reply) = &self.replying {
+                    <div class="editing replying">
+                        <p>{format!("Replying to: {}", reply.snippet)}</p>
+                        <button onclick={link.callback(|_| Msg::CancelReply)}>{"Cancel"}</button>
+                    </div>
+                }
+                if !props.connected {
+                    <div class="editing offline">
+                        <p>{"No connection. Messages are sent once it is back."}</p>
+                    </div>
+                }
                 <div class="input-holder">
                     <form onsubmit={onsubmit}>
-                        <input ref={self.message.clone()} name="message" type="text" placeholder="Message" autocomplete="off" required=true />
-                        <button type="submit">
+                        <label class={if props.key_changed || !props.connected {"attach disabled"} else {"attach"}} title="Attach a file">
+                            {"📎"}
+                            <input type="file" {onchange} disabled={props.key_changed || !props.connected} />
+                        </label>
+                        <input ref={self.message.clone()} name="message" type="text" placeholder="Message" autocomplete="off" required=true disabled={props.key_changed} />
+                        <button type="submit" disabled={props.key_changed}>
                             <svg xmlns="http://www.w3.org/2000/svg" class="bi bi-send" viewBox="0 0 16 16"><path d="M15.854.146a.5.5 0 0 1 .11.54l-5.819 14.547a.75.75 0 0 1-1.329.124l-3.178-4.995L.643 7.184a.75.75 0 0 1 .124-1.33L15.314.037a.5.5 0 0 1 .54.11ZM6.636 10.07l2.761 4.338L14.13 2.576 6.636 10.07Zm6.787-8.201L1.591 6.602l4.339 2.76 7.494-7.493Z"/></svg>
                         </button>
                     </form>
@@ -64,17 +259,111 @@ impl Component for Dialog {
         }
     }
 
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        let name = format!("User#{}", ctx.props().id);
+        if name != self.name {
+            self.name = name;
+            self.show_safety = false;
+            self.editing = None;
+            self.replying = None;
+            self.collapsed.clear();
+        }
+        true
+    }
+
+    fn rendered(&mut self, _ctx: &Context<Self>, _first_render: bool) {
+        let element = self.jump_to.take()
+            .and_then(|id| web_sys::window()?.document()?.get_element_by_id(&id));
+        if let Some(element) = element {
+            element.scroll_into_view();
+        }
+    }
+
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::DoCallback => {
                 let input = self.message.cast::<HtmlInputElement>()
                     .unwrap();
-                let callback = &ctx.props().callback;
-                callback.emit(input.value());
+                if let Some(id) = self.editing.take() {
+                    ctx.props().on_edit.emit((id, input.value()));
+                } else if let Some(reply) = self.replying.take() {
+                    ctx.props().on_reply.emit((reply, input.value()));
+                } else {
+                    ctx.props().callback.emit(input.value());
+                }
                 input.set_value("");
                 true
             }
+            Msg::StartEdit(id) => {
+                let message = ctx.props().messages.iter().find(|x| x.id == id && x.from == ctx.props().me);
+                if let (Some(message), Some(input)) = (message, self.message.cast::<HtmlInputElement>()) {
+                    input.set_value(&message.content);
+                    let _ = input.focus();
+                    self.editing = Some(id);
+                    self.replying = None;
+                }
+                true
+            }
+            Msg::StartReply((from, id)) => {
+                let message = ctx.props().messages.iter().find(|x| x.id == id && x.from == from);
+                if let (Some(message), Some(input)) = (message, self.message.cast::<HtmlInputElement>()) {
+                    if self.editing.take().is_some() {
+                        input.set_value("");
+                    }
+                    let _ = input.focus();
+                    self.replying = Some(Reply::to(message));
+                }
+                true
+            }
+            Msg::CancelReply => {
+                self.replying = None;
+                true
+            }
+            Msg::Jump(key) => {
+                if let Some(root) = thread_roots(&ctx.props().messages).get(&key) {
+                    self.collapsed.remove(root);
+                }
+                self.jump_to = Some(message::element_id(key.0, key.1));
+                true
+            }
+            Msg::ToggleThread(key) => {
+                if !self.collapsed.remove(&key) {
+                    self.collapsed.insert(key);
+                }
+                true
+            }
+            Msg::CancelEdit => {
+                if let Some(input) = self.message.cast::<HtmlInputElement>() {
+                    input.set_value("");
+                }
+                self.editing = None;
+                true
+            }
+            Msg::ToggleSafety => {
+                self.show_safety = !self.show_safety;
+                true
+            }
         }
     }
 
-}
\ No newline at end of file
+}
+
+/// The first message of the thread each message belongs to, all by
+/// `(from, id)`. Replies to messages that aren't loaded start their own thread.
+fn thread_roots(messages: &[Message]) -> HashMap<(usize, usize), (usize, usize)> {
+    let parents: HashMap<(usize, usize), (usize, usize)> = messages.iter()
+        .filter_map(|x| x.reply.as_ref().map(|reply| ((x.from, x.id), (reply.from, reply.id))))
+        .collect();
+    let loaded: HashSet<(usize, usize)> = messages.iter().map(|x| (x.from, x.id)).collect();
+    messages.iter().map(|x| {
+        let mut root = (x.from, x.id);
+        // bounded, a forged reply could point back at its own descendant
+        for _ in 0..messages.len() {
+            match parents.get(&root) {
+                Some(parent) if loaded.contains(parent) => root = *parent,
+                _ => break
+            }
+        }
+        ((x.from, x.id), root)
+    }).collect()
+}
@@ -0,0 +1,9 @@
+use qrcode::{QrCode, render::svg};
+
+pub fn qr_svg(data: &str) -> Option<String> {
+    QrCode::new(data.as_bytes()).ok().map(|code| code.render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#1c202a"))
+        .light_color(svg::Color("#ffffff"))
+        .build())
+}
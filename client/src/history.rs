@@ -0,0 +1,256 @@
+use js_sys::{Array, Date, Map, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbKeyRange};
+use crate::{crypt::{subtle, web_crypto, SecretKey}, data::Stamp, idb, message::Message, storage};
+
+const STORAGE_KEY: &str = "storage";
+/// The pure-Rust backend's storage key, raw bytes since it has no opaque keys.
+const NATIVE_STORAGE_KEY: &str = "storage-native";
+pub const PAGE: usize = 30;
+
+/// Message history kept in IndexedDB. Every record is sealed with AES-GCM
+/// under a non-extractable storage key that lives next to the identity keys,
+/// so the database is useless when copied out of the browser profile.
+/// Without WebCrypto the key has to be stored raw, which only keeps the
+/// records apart from the rest of the profile.
+pub struct History {
+    db: IdbDatabase,
+    key: SecretKey,
+}
+
+/// How far a dialog's stored history is loaded.
+#[derive(Default)]
+pub struct Paging {
+    pub loaded: bool,
+    /// Key of the oldest loaded record, `None` once the start is reached.
+    pub before: Option<JsValue>,
+}
+
+/// A change to stored history. Changes made before the database is open
+/// wait in this form until it is.
+pub enum Change {
+    Append(Box<Message>),
+    /// Our message `id` was delivered with `stamp`.
+    Restamp { from: usize, id: usize, stamp: Stamp },
+    Edit { from: usize, id: usize, text: String },
+    Delete { from: usize, id: usize },
+    React { from: usize, author: usize, id: usize, emoji: Option<String> },
+}
+
+pub struct Page {
+    pub messages: Vec<Message>,
+    /// Key of the oldest loaded record, `None` once the start is reached.
+    pub before: Option<JsValue>,
+}
+
+fn error(e: impl ToString) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Records are filed by the identity of the account, since ids are handed
+/// out again after a restart, then ordered by the server's clock and
+/// sequence number. Our own messages go in before the server stamped them,
+/// keyed by the local clock until `restamp` moves them; so did all history
+/// from before stamps.
+fn record_key(identity: &str, message: &Message) -> Array {
+    let key = Array::of1(&identity.into());
+    match message.stamp {
+        Some(stamp) => {
+            key.push(&(stamp.timestamp as f64).into());
+            key.push(&(stamp.seq as f64).into());
+        }
+        None => {
+            key.push(&Date::now().into());
+        }
+    }
+    key.push(&(message.id as f64).into());
+    key
+}
+
+fn dialog_range(identity: &str, before: Option<JsValue>) -> Result<IdbKeyRange, JsValue> {
+    let lower = Array::of1(&identity.into());
+    // arrays sort after numbers, so this is past every record of the account
+    let upper = before.unwrap_or_else(|| Array::of2(&identity.into(), &Array::new()).into());
+    IdbKeyRange::bound_with_lower_open_and_upper_open(&lower, &upper, false, true)
+}
+
+/// Every record, strings being the lowest keys a record is filed under.
+fn all() -> Result<IdbKeyRange, JsValue> {
+    IdbKeyRange::lower_bound(&Array::of1(&"".into()))
+}
+
+impl History {
+    pub async fn open() -> Result<Self, JsValue> {
+        let db = idb::open().await?;
+        if !web_crypto() {
+            let mut key = idb::get(&db, idb::KEYS, &NATIVE_STORAGE_KEY.into()).await?;
+            if key.is_undefined() {
+                let mut raw = [0u8; 32];
+                getrandom::getrandom(&mut raw).map_err(error)?;
+                key = Uint8Array::from(&raw[..]).into();
+                idb::put(&db, idb::KEYS, &NATIVE_STORAGE_KEY.into(), &key).await?;
+            }
+            let key = SecretKey::Native(Uint8Array::new(&key).to_vec());
+            return Self { db, key }.forget_ids().await;
+        }
+        let mut key = idb::get(&db, idb::KEYS, &STORAGE_KEY.into()).await?;
+        if key.is_undefined() {
+            let usages = ["encrypt", "decrypt"].iter()
+                .map(|x| JsValue::from_str(x))
+                .collect::<Array>();
+            let algorithm = {
+                let a = Map::new();
+                a.set(&"name".into(), &"AES-GCM".into());
+                a.set(&"length".into(), &256.into());
+                Object::from_entries(&a)?
+            };
+            key = JsFuture::from(subtle().generate_key_with_object(&algorithm, false, &usages)?).await?;
+            idb::put(&db, idb::KEYS, &STORAGE_KEY.into(), &key).await?;
+        }
+        Self { db, key: SecretKey::Web(key.into()) }.forget_ids().await
+    }
+    /// Drops records filed under account ids. Numbers sort before strings,
+    /// so they are all below the identities; they can't be told apart from
+    /// a stranger who got the id later.
+    async fn forget_ids(self) -> Result<Self, JsValue> {
+        let range = IdbKeyRange::upper_bound_with_open(&Array::of1(&"".into()), true)?;
+        idb::delete(&self.db, idb::MESSAGES, &range.into()).await?;
+        Ok(self)
+    }
+    async fn seal(&self, message: &Message) -> Result<Object, JsValue> {
+        let plain = serde_json::to_vec(message).map_err(error)?;
+        let mut iv = [0u8; 12];
+        getrandom::getrandom(&mut iv).map_err(error)?;
+        let data = self.key.encrypt(&iv, &plain).await?;
+        let record = Object::new();
+        Reflect::set(&record, &"id".into(), &(message.id as f64).into())?;
+        // in the clear, so expired records can be found without decrypting them all
+        if let Some(expires) = message.expires {
+            Reflect::set(&record, &"expires".into(), &(expires as f64).into())?;
+        }
+        Reflect::set(&record, &"iv".into(), &Uint8Array::from(&iv[..]))?;
+        Reflect::set(&record, &"data".into(), &Uint8Array::from(&data[..]).buffer())?;
+        Ok(record)
+    }
+    async fn unseal(&self, record: &JsValue) -> Result<Option<Message>, JsValue> {
+        let iv: Uint8Array = Reflect::get(record, &"iv".into())?.unchecked_into();
+        let data = Uint8Array::new(&Reflect::get(record, &"data".into())?);
+        // sealed under the other backend's storage key, unreadable from here
+        let plain = match self.key.decrypt(&iv.to_vec(), &data.to_vec()).await {
+            Ok(plain) => plain,
+            Err(_) => return Ok(None)
+        };
+        Ok(serde_json::from_slice(&plain).ok())
+    }
+    /// The stored message `id` from account `identity` with the key of its record.
+    async fn find(&self, identity: &str, id: usize) -> Result<Option<(JsValue, Message)>, JsValue> {
+        let matches = move |record: &JsValue| Reflect::get(record, &"id".into())
+            .ok()
+            .and_then(|x| x.as_f64())
+            .is_some_and(|x| x as usize == id);
+        let (key, record) = match idb::find(&self.db, idb::MESSAGES, &dialog_range(identity, None)?, matches).await? {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        Ok(self.unseal(&record).await?.map(|message| (key, message)))
+    }
+    /// Applies `change` to the history with account `identity`.
+    pub async fn apply(&self, identity: &str, change: Change) -> Result<(), JsValue> {
+        match change {
+            Change::Append(message) => self.append(identity, &message).await,
+            Change::Restamp { from, id, stamp } => self.restamp(identity, from, id, stamp).await,
+            Change::Edit { from, id, text } => self.update(identity, from, id, move |message| {
+                if message.attachment.is_none() {
+                    message.content = text;
+                    message.edited = true;
+                }
+            }).await,
+            Change::Delete { from, id } => self.remove(identity, from, id).await,
+            Change::React { from, author, id, emoji } => self.update(identity, author, id, move |message| message.react(from, emoji)).await
+        }
+    }
+    async fn append(&self, identity: &str, message: &Message) -> Result<(), JsValue> {
+        let record = self.seal(message).await?;
+        idb::put(&self.db, idb::MESSAGES, &record_key(identity, message), &record).await
+    }
+    /// Files our message `id` under the stamp the server gave it.
+    async fn restamp(&self, identity: &str, from: usize, id: usize, stamp: Stamp) -> Result<(), JsValue> {
+        match self.find(identity, id).await? {
+            Some((key, mut message)) if message.from == from && message.stamp.is_none() => {
+                message.stamp = Some(stamp);
+                idb::delete(&self.db, idb::MESSAGES, &key).await?;
+                self.append(identity, &message).await
+            }
+            _ => Ok(())
+        }
+    }
+    /// Rewrites the stored message `id` sent by `from` in place, keeping its
+    /// position in the dialog.
+    async fn update(&self, identity: &str, from: usize, id: usize, change: impl FnOnce(&mut Message)) -> Result<(), JsValue> {
+        if let Some((key, mut message)) = self.find(identity, id).await? {
+            if message.from == from {
+                change(&mut message);
+                idb::put(&self.db, idb::MESSAGES, &key, &self.seal(&message).await?.into()).await?;
+            }
+        }
+        Ok(())
+    }
+    async fn remove(&self, identity: &str, from: usize, id: usize) -> Result<(), JsValue> {
+        match self.find(identity, id).await? {
+            Some((key, message)) if message.from == from => idb::delete(&self.db, idb::MESSAGES, &key).await,
+            _ => Ok(())
+        }
+    }
+    /// Loads the page of messages right before `before`, or the latest page.
+    pub async fn load(&self, identity: &str, before: Option<JsValue>) -> Result<Page, JsValue> {
+        let records = idb::last(&self.db, idb::MESSAGES, &dialog_range(identity, before)?, PAGE).await?;
+        let before = match records.last() {
+            Some((key, _)) if records.len() == PAGE => Some(key.clone()),
+            _ => None
+        };
+        let now = Date::now() as u64;
+        let mut messages = Vec::with_capacity(records.len());
+        for (_, record) in records.into_iter().rev() {
+            match self.unseal(&record).await? {
+                Some(message) if message.expires.is_none_or(|x| x > now) => messages.push(message),
+                _ => ()
+            }
+        }
+        Ok(Page { messages, before })
+    }
+    /// Deletes every message, in any dialog, whose timer ran out by `now`.
+    pub async fn expire(&self, now: u64) -> Result<(), JsValue> {
+        let expired = move |_: &JsValue, record: &JsValue| Reflect::get(record, &"expires".into())
+            .ok()
+            .and_then(|x| x.as_f64())
+            .is_some_and(|x| x <= now as f64);
+        for key in idb::keys_where(&self.db, idb::MESSAGES, &all()?, expired).await? {
+            idb::delete(&self.db, idb::MESSAGES, &key).await?;
+        }
+        Ok(())
+    }
+    /// Deletes every message, in any dialog, from before `since`.
+    pub async fn prune(&self, since: u64) -> Result<(), JsValue> {
+        let old = move |key: &JsValue, _: &JsValue| Reflect::get(key, &1.into())
+            .ok()
+            .and_then(|x| x.as_f64())
+            .is_some_and(|x| x < since as f64);
+        for key in idb::keys_where(&self.db, idb::MESSAGES, &all()?, old).await? {
+            idb::delete(&self.db, idb::MESSAGES, &key).await?;
+        }
+        Ok(())
+    }
+    pub async fn clear(&self, identity: &str) -> Result<(), JsValue> {
+        idb::delete(&self.db, idb::MESSAGES, &dialog_range(identity, None)?.into()).await
+    }
+    /// Drops every trace of this client: history, identity and storage keys,
+    /// pinned and verified keys.
+    pub async fn wipe(history: Option<&History>) -> Result<(), JsValue> {
+        if let Some(history) = history {
+            history.db.close();
+        }
+        storage::clear();
+        idb::delete_database().await
+    }
+}
@@ -0,0 +1,178 @@
+use std::{cell::RefCell, rc::Rc};
+use js_sys::{Function, Promise};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    window, Event, IdbCursorDirection, IdbCursorWithValue, IdbDatabase, IdbFactory, IdbKeyRange,
+    IdbObjectStore, IdbRequest, IdbTransactionMode
+};
+
+const NAME: &str = "crypto-messanger";
+const VERSION: u32 = 2;
+pub const KEYS: &str = "keys";
+pub const MESSAGES: &str = "messages";
+const STORES: [&str; 2] = [KEYS, MESSAGES];
+
+/// Turns an `IDBRequest` into a future resolving with its `result`.
+fn wait(request: &IdbRequest) -> JsFuture {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let success = request.clone();
+        let onsuccess = Closure::once_into_js(move |_: Event| {
+            let _ = resolve.call1(&JsValue::NULL, &success.result().unwrap_or(JsValue::UNDEFINED));
+        });
+        let failure = request.clone();
+        let onerror = Closure::once_into_js(move |_: Event| {
+            let error = failure.error().ok().flatten()
+                .map(JsValue::from)
+                .unwrap_or_else(|| JsValue::from_str("indexeddb request failed"));
+            let _ = reject.call1(&JsValue::NULL, &error);
+        });
+        request.set_onsuccess(Some(onsuccess.unchecked_ref()));
+        request.set_onerror(Some(onerror.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
+
+fn factory() -> Result<IdbFactory, JsValue> {
+    window()
+        .and_then(|x| x.indexed_db().ok().flatten())
+        .ok_or_else(|| JsValue::from_str("indexeddb is unavailable"))
+}
+
+pub async fn open() -> Result<IdbDatabase, JsValue> {
+    let request = factory()?.open_with_u32(NAME, VERSION)?;
+    let upgrade = request.clone();
+    let onupgradeneeded = Closure::once_into_js(move |_: Event| {
+        let db: IdbDatabase = match upgrade.result() {
+            Ok(db) => db.unchecked_into(),
+            Err(_) => return
+        };
+        let names = db.object_store_names();
+        for store in STORES {
+            if !names.contains(store) {
+                let _ = db.create_object_store(store);
+            }
+        }
+    });
+    request.set_onupgradeneeded(Some(onupgradeneeded.unchecked_ref()));
+    Ok(wait(&request).await?.unchecked_into())
+}
+
+fn store(db: &IdbDatabase, name: &str, mode: IdbTransactionMode) -> Result<IdbObjectStore, JsValue> {
+    db.transaction_with_str_and_mode(name, mode)?.object_store(name)
+}
+
+pub async fn get(db: &IdbDatabase, name: &str, key: &JsValue) -> Result<JsValue, JsValue> {
+    wait(&store(db, name, IdbTransactionMode::Readonly)?.get(key)?).await
+}
+
+pub async fn put(db: &IdbDatabase, name: &str, key: &JsValue, value: &JsValue) -> Result<(), JsValue> {
+    wait(&store(db, name, IdbTransactionMode::Readwrite)?.put_with_key(value, key)?).await.map(|_| ())
+}
+
+pub async fn delete(db: &IdbDatabase, name: &str, key: &JsValue) -> Result<(), JsValue> {
+    wait(&store(db, name, IdbTransactionMode::Readwrite)?.delete(key)?).await.map(|_| ())
+}
+
+/// Walks `range` from its end while `visit` asks for more, newest first.
+async fn scan(
+    db: &IdbDatabase,
+    name: &str,
+    range: &IdbKeyRange,
+    visit: impl FnMut(JsValue, JsValue) -> bool + 'static
+) -> Result<(), JsValue> {
+    let request = store(db, name, IdbTransactionMode::Readonly)?
+        .open_cursor_with_range_and_direction(range, IdbCursorDirection::Prev)?;
+    let mut handlers = None;
+    let mut visit = Some(visit);
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let cursor_request = request.clone();
+        let mut visit = match visit.take() {
+            Some(visit) => visit,
+            None => return
+        };
+        let onsuccess = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+            let cursor = cursor_request.result().ok()
+                .and_then(|x| x.dyn_into::<IdbCursorWithValue>().ok());
+            if let Some(cursor) = cursor {
+                let more = visit(
+                    cursor.key().unwrap_or(JsValue::UNDEFINED),
+                    cursor.value().unwrap_or(JsValue::UNDEFINED)
+                );
+                if more && cursor.continue_().is_ok() {
+                    return;
+                }
+            }
+            let _ = resolve.call0(&JsValue::NULL);
+        });
+        let onerror = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+            let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("indexeddb cursor failed"));
+        });
+        request.set_onsuccess(Some(onsuccess.as_ref().unchecked_ref()));
+        request.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+        handlers = Some((onsuccess, onerror));
+    });
+    let result = JsFuture::from(promise).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+    drop(handlers);
+    result.map(|_| ())
+}
+
+/// Collects up to `count` `(key, value)` records from the end of `range`,
+/// newest first.
+pub async fn last(db: &IdbDatabase, name: &str, range: &IdbKeyRange, count: usize) -> Result<Vec<(JsValue, JsValue)>, JsValue> {
+    let records = Rc::new(RefCell::new(Vec::new()));
+    let found = records.clone();
+    scan(db, name, range, move |key, value| {
+        found.borrow_mut().push((key, value));
+        found.borrow().len() < count
+    }).await?;
+    let records = records.take();
+    Ok(records)
+}
+
+/// The newest `(key, value)` record in `range` matching `predicate`.
+pub async fn find(
+    db: &IdbDatabase,
+    name: &str,
+    range: &IdbKeyRange,
+    predicate: impl Fn(&JsValue) -> bool + 'static
+) -> Result<Option<(JsValue, JsValue)>, JsValue> {
+    let record = Rc::new(RefCell::new(None));
+    let found = record.clone();
+    scan(db, name, range, move |key, value| {
+        if predicate(&value) {
+            *found.borrow_mut() = Some((key, value));
+            return false;
+        }
+        true
+    }).await?;
+    let record = record.take();
+    Ok(record)
+}
+
+/// Keys of every record in `range` matching `predicate`, which gets the
+/// key and the value.
+pub async fn keys_where(
+    db: &IdbDatabase,
+    name: &str,
+    range: &IdbKeyRange,
+    predicate: impl Fn(&JsValue, &JsValue) -> bool + 'static
+) -> Result<Vec<JsValue>, JsValue> {
+    let keys = Rc::new(RefCell::new(Vec::new()));
+    let found = keys.clone();
+    scan(db, name, range, move |key, value| {
+        if predicate(&key, &value) {
+            found.borrow_mut().push(key);
+        }
+        true
+    }).await?;
+    let keys = keys.take();
+    Ok(keys)
+}
+
+pub async fn delete_database() -> Result<(), JsValue> {
+    let request = factory()?.delete_database(NAME)?;
+    wait(&request).await.map(|_| ())
+}
@@ -1,44 +1,593 @@
 pub mod rsa_crypto;
 pub mod crypt;
-pub mod dialogs;
 pub mod dialog;
 pub mod message;
 pub mod wss;
-pub mod data;
-pub mod user;
+pub mod storage;
+pub mod fingerprint;
+pub mod idb;
+pub mod history;
+pub mod outbox;
+pub mod attachment;
+pub mod settings;
+pub use client_core::{data, payload, user};
 
 
+use client_core::{random_id, state::{Effect, Input, State}};
 use crypt::Crypt;
-use data::{UserEvent, SystemEvent};
-use futures::{stream::SplitSink, lock::Mutex, SinkExt};
-use dialogs::MiniDialog;
-use reqwasm::websocket::{futures::WebSocket, Message};
+use data::{Stamp, UserEvent, SystemEvent};
+use futures::lock::Mutex;
 use serde_json::json;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::spawn_local;
-use web_sys::console;
-use std::{sync::Arc, collections::HashMap};
+use web_sys::{console, File, HtmlInputElement, HtmlSelectElement, Notification, NotificationOptions, NotificationPermission};
+use std::{rc::Rc, sync::Arc, collections::HashMap};
 use rsa_crypto::RsaCrypto;
+use user::{DeviceInfo, SafeUser};
 use yew::prelude::*;
 use dialog::Dialog;
+use history::{Change, History, Page, Paging};
+use message::{MessageState, Reply};
+use outbox::Outbox;
+use payload::Payload;
+use attachment::{Preview, Sealed};
+use settings::Settings;
+use gloo_timers::{callback::Interval, future::TimeoutFuture};
+use js_sys::Date;
 
 enum Msg {
+    Connection(wss::Status),
+    KeysReady,
+    /// The stored keys could not be read.
+    KeysFailed(String),
+    RotateKeys,
     SendPublicKey(String),
     Crypt(String),
-    SendCrypt(String),
+    /// Something for the state machine, mostly what a crypto job came back with.
+    Input(Input),
+    Attach(File),
+    Sealed(usize, Sealed),
+    Preview(usize, usize, Preview),
+    Resend(usize),
+    /// An outbox entry sealed for the devices its recipient has now.
+    Resealed { to: usize, random_id: usize, messages: HashMap<usize, String> },
+    /// The copy of an outgoing payload sealed for ourselves.
+    Kept(usize, String),
+    Retry(usize),
+    Edit(usize, String),
+    Delete(usize),
+    Reply(Reply, String),
+    React((usize, usize), Option<String>),
+    SetTimer(Option<u64>),
+    Tick,
     SetDialog(usize),
     HandleData(String),
-    AddMessage(usize, usize, String),
-    AddUser(MiniDialog)
+    /// A user whose keys we can encrypt for.
+    AddUser(SafeUser),
+    AcceptContact(usize),
+    DeclineContact(usize),
+    Block(usize),
+    Unblock(usize),
+    ToggleBlocked,
+    ToggleDevices,
+    ToggleSettings,
+    SaveSettings(Settings),
+    SetServer,
+    RequestLinkCode,
+    Link,
+    Revoke(usize),
+    Verify(usize),
+    AcceptKey(usize),
+    HistoryReady(Rc<History>),
+    HistoryUnavailable,
+    HistoryPage(usize, Page),
+    LoadMoreHistory(usize),
+    ClearHistory(usize),
+    WipeEverything
 }
 
 struct Chat {
-    my_id: Option<usize>,
+    /// Who we are, the dialogs and who is blocked, kept by `client_core`.
+    state: State,
+    keys_ready: bool,
+    keys_error: Option<String>,
+    /// Whom we blocked before rotating keys, to block again once the server
+    /// accepts the new ones: blocks are kept by key.
+    reblock: Vec<usize>,
+    /// How the connection is doing, as shown at the top of the sidebar.
+    status: wss::Status,
+    /// Whether the server knows who we are, so messages can go out.
+    connected: bool,
+    resuming: bool,
     rsa: Arc<Mutex<RsaCrypto>>,
-    text: String,
-    dialog_id: Option<usize>,
-    dialogs: HashMap<usize, MiniDialog>,
-    writer: Arc<Mutex<SplitSink<WebSocket, Message>>>
+    show_blocked: bool,
+    /// Our account's devices, for the linked devices page.
+    devices: Vec<DeviceInfo>,
+    show_devices: bool,
+    link_code: Option<String>,
+    link_input: NodeRef,
+    settings: Settings,
+    show_settings: bool,
+    server_input: NodeRef,
+    history: Option<Rc<History>>,
+    /// Changes waiting for the history to open, `None` once it did or failed.
+    unsaved: Option<Vec<(String, Change)>>,
+    /// How far each dialog's stored history is loaded.
+    paging: HashMap<usize, Paging>,
+    outbox: Outbox,
+    /// Attachments being uploaded, by the id their message will get.
+    uploads: HashMap<usize, (usize, Sealed)>,
+    /// Attachments sealed before the server told us who we are.
+    unsent: Vec<(usize, Sealed)>,
+    _ticker: Interval,
+    writer: wss::Writer
+}
+
+impl Chat {
+    fn send(&self, event: UserEvent) {
+        self.send_many(vec![event]);
+    }
+    /// Sends `events` back to back, without anything else interleaving.
+    fn send_many(&self, events: Vec<UserEvent>) {
+        let writer_clone = self.writer.clone();
+        spawn_local(async move {
+            let mut writer_lock = writer_clone.lock().await;
+            if let Some(writer) = writer_lock.as_mut() {
+                for event in events {
+                    if let Err(e) = writer.send(json!(event).to_string()).await {
+                        console::warn_1(&JsValue::from_str(&format!("could not send: {}", e)));
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    /// Feeds `input` to the state and carries out what comes of it.
+    fn handle(&mut self, ctx: &Context<Self>, input: Input) {
+        for effect in self.state.handle(input) {
+            match effect {
+                Effect::Send(UserEvent::Message { to, messages, random_id, ttl }) => {
+                    // written offline it waits in the outbox for the connection,
+                    // and for a recipient who isn't back under a new id yet
+                    let current = match self.state.dialogs.get(&to) {
+                        Some(dialog) => {
+                            self.outbox.push(random_id, dialog.identity.clone(), ttl);
+                            !dialog.stale
+                        }
+                        None => false
+                    };
+                    if self.connected && current {
+                        self.send(UserEvent::Message { to, messages, random_id, ttl });
+                    }
+                }
+                Effect::Send(UserEvent::Prove(nonce)) => {
+                    let mut events = vec![UserEvent::Prove(nonce)];
+                    events.extend(self.reblock.drain(..).map(UserEvent::Block));
+                    self.send_many(events);
+                }
+                Effect::Send(event) => self.send(event),
+                Effect::Crypto(job) => self.perform(job, ctx.link().callback(Msg::Input)),
+                Effect::Applied { dialog, from, random_id, payload } => {
+                    if Some(from) == self.state.my_id && self.outbox.get(random_id).is_some_and(|x| x.copy.is_none()) {
+                        self.keep(payload.clone(), ctx.link().callback(move |copy| Msg::Kept(random_id, copy)));
+                    }
+                    self.applied(ctx, dialog, from, random_id, payload);
+                }
+                Effect::Trust(trust) => storage::set_json(TRUST, &trust)
+            }
+        }
+    }
+    fn encrypt(&mut self, ctx: &Context<Self>, to: usize, random_id: usize, payload: Payload) {
+        self.handle(ctx, Input::Compose { to, random_id, payload });
+    }
+    fn upload(&mut self, random_id: usize) {
+        if let Some((to, sealed)) = self.uploads.get_mut(&random_id) {
+            sealed.attachment.blob.clear();
+            let size = sealed.data.len() as u64;
+            if let Some(dialog) = self.state.dialogs.get_mut(to) {
+                dialog.set_state(random_id, MessageState::Pending);
+            }
+            self.send(UserEvent::Upload { random_id, size });
+        }
+    }
+    fn fail_upload(&mut self, random_id: usize) {
+        if let Some(dialog) = self.uploads.get(&random_id).and_then(|(to, _)| self.state.dialogs.get_mut(to)) {
+            dialog.set_state(random_id, MessageState::Failed);
+        }
+    }
+    /// Downloads and decrypts the attachment of `message`, if it has one to show.
+    fn fetch_attachment(&self, ctx: &Context<Self>, dialog: usize, message: &message::Message) {
+        let attachment = match &message.attachment {
+            Some(attachment) if attachment.preview == Preview::Loading && !attachment.blob.is_empty() => attachment.clone(),
+            _ => return
+        };
+        let id = message.id;
+        let callback = ctx.link().callback(move |preview| Msg::Preview(dialog, id, preview));
+        spawn_local(async move {
+            let url = attachment::open(&wss::http_base(&server()), &attachment).await
+                .and_then(|data| attachment::object_url(&data, &attachment.mime));
+            match url {
+                Ok(url) => callback.emit(Preview::Ready(url)),
+                Err(e) => {
+                    console::warn_2(&JsValue::from_str("could not open attachment:"), &e);
+                    callback.emit(Preview::Unavailable);
+                }
+            }
+        });
+    }
+    /// Tells the server who we are once both the keys and the connection's
+    /// identity are settled.
+    fn announce(&self, ctx: &Context<Self>) {
+        if self.keys_ready && self.connected {
+            self.send_public_key(ctx.link().callback(Msg::SendPublicKey));
+        }
+    }
+    /// Seals an outbox entry for the devices its recipient has now and sends
+    /// it; it stays there until the server reports it delivered. Entries for
+    /// someone who isn't around, or whose keys wait to be accepted, wait too.
+    fn resend(&self, ctx: &Context<Self>, random_id: usize) {
+        let (to, copy) = match self.outbox.get(random_id).map(|x| (self.state.find(&x.identity), x.copy.clone())) {
+            Some((Some(to), Some(copy))) => (to, copy),
+            _ => return
+        };
+        if let Some(keys) = self.state.recipients(to) {
+            self.reseal(copy, keys, ctx.link().callback(move |messages| Msg::Resealed { to, random_id, messages }));
+        }
+    }
+    /// Resends what waited for `id` to come back or have its keys accepted.
+    fn resend_to(&self, ctx: &Context<Self>, id: usize) {
+        if !self.connected {
+            return;
+        }
+        for (random_id, entry) in self.outbox.pending() {
+            if self.state.find(&entry.identity) == Some(id) {
+                self.resend(ctx, random_id);
+            }
+        }
+    }
+    /// The dialog of outbox entry `random_id`.
+    fn outgoing(&self, random_id: usize) -> Option<usize> {
+        self.outbox.get(random_id).and_then(|x| self.state.find(&x.identity))
+    }
+    fn set_state(&mut self, random_id: usize, state: MessageState) {
+        if let Some(dialog) = self.outgoing(random_id).and_then(|id| self.state.dialogs.get_mut(&id)) {
+            dialog.set_state(random_id, state);
+        }
+    }
+    /// Files our delivered message in history under the stamp the server gave it.
+    fn restamp(&mut self, random_id: usize, stamp: Stamp) {
+        if let (Some(to), Some(me)) = (self.outgoing(random_id), self.state.my_id) {
+            self.record(to, Change::Restamp { from: me, id: random_id, stamp });
+        }
+    }
+    /// Writes `change` to the history of `dialog`, or keeps it for when the
+    /// history is open.
+    fn record(&mut self, dialog: usize, change: Change) {
+        let identity = match self.state.dialogs.get(&dialog) {
+            Some(dialog) => dialog.identity.clone(),
+            None => return
+        };
+        match (self.history.clone(), self.unsaved.as_mut()) {
+            (Some(history), _) => spawn_local(async move {
+                if let Err(e) = history.apply(&identity, change).await {
+                    console::error_2(&JsValue::from_str("could not save history:"), &e);
+                }
+            }),
+            (None, Some(unsaved)) => unsaved.push((identity, change)),
+            (None, None) => ()
+        }
+    }
+    /// Keeps what the state applied in history, and shows new messages
+    /// from others to the user.
+    fn applied(&mut self, ctx: &Context<Self>, dialog: usize, from: usize, random_id: usize, payload: Payload) {
+        match payload {
+            Payload::Text { .. } | Payload::Attachment(_) => {
+                let message = self.state.dialogs.get(&dialog)
+                    .and_then(|x| x.messages.iter().find(|x| x.id == random_id && x.from == from))
+                    .cloned();
+                if let Some(message) = message {
+                    if Some(from) != self.state.my_id {
+                        self.notify(from, &message);
+                    }
+                    self.fetch_attachment(ctx, dialog, &message);
+                    self.record(dialog, Change::Append(Box::new(message)));
+                }
+            }
+            Payload::Edit { id, text } => self.record(dialog, Change::Edit { from, id, text }),
+            Payload::Delete { id } => self.record(dialog, Change::Delete { from, id }),
+            Payload::React { id, from: author, emoji } => self.record(dialog, Change::React { from, author, id, emoji }),
+            Payload::Timer { .. } | Payload::Unknown => ()
+        }
+    }
+    /// The open dialog, if messages can be sent to it.
+    fn writable_dialog(&self) -> Option<usize> {
+        match self.state.selected.and_then(|id| self.state.dialogs.get(&id)) {
+            Some(dialog) if !dialog.key_changed => Some(dialog.id),
+            _ => None
+        }
+    }
+    /// Deletes stored messages whose timer ran out.
+    fn expire_history(&self, now: u64) {
+        if let Some(history) = self.history.clone() {
+            spawn_local(async move {
+                if let Err(e) = history.expire(now).await {
+                    console::error_2(&JsValue::from_str("could not delete expired messages:"), &e);
+                }
+            });
+        }
+    }
+    /// Drops every trace of this client from the browser and starts over.
+    fn wipe(&mut self) {
+        let history = self.history.take();
+        spawn_local(async move {
+            if let Err(e) = History::wipe(history.as_deref()).await {
+                console::error_2(&JsValue::from_str("could not wipe data:"), &e);
+            }
+            let _ = web_sys::window().unwrap().location().reload();
+        });
+    }
+    /// Deletes stored messages older than the retention setting allows.
+    fn prune_history(&self) {
+        let (history, since) = match (&self.history, self.settings.retained_since(Date::now() as u64)) {
+            (Some(history), Some(since)) => (history.clone(), since),
+            _ => return
+        };
+        spawn_local(async move {
+            if let Err(e) = history.prune(since).await {
+                console::error_2(&JsValue::from_str("could not delete old messages:"), &e);
+            }
+        });
+    }
+    /// Shows a desktop notification for a message from someone else, if
+    /// they are wanted and nobody is looking at the tab.
+    fn notify(&self, from: usize, message: &message::Message) {
+        let hidden = web_sys::window()
+            .and_then(|window| window.document())
+            .is_some_and(|document| document.hidden());
+        if !self.settings.notifications || !hidden || Notification::permission() != NotificationPermission::Granted {
+            return;
+        }
+        let body = match self.settings.previews {
+            true => message.content.clone(),
+            false => "New message".to_string()
+        };
+        let mut options = NotificationOptions::new();
+        options.body(&body);
+        if let Err(e) = Notification::new_with_options(&format!("User#{}", from), &options) {
+            console::warn_2(&JsValue::from_str("could not notify:"), &e);
+        }
+    }
+    fn load_history(&mut self, ctx: &Context<Self>, id: usize, before: Option<JsValue>) {
+        let (history, identity) = match (&self.history, self.state.dialogs.get(&id)) {
+            (Some(history), Some(dialog)) => (history.clone(), dialog.identity.clone()),
+            _ => return
+        };
+        self.paging.entry(id).or_default().loaded = true;
+        let callback = ctx.link().callback(move |page| Msg::HistoryPage(id, page));
+        spawn_local(async move {
+            match history.load(&identity, before).await {
+                Ok(page) => callback.emit(page),
+                Err(e) => console::error_2(&JsValue::from_str("could not load history:"), &e)
+            }
+        });
+    }
+    fn view_dialog(&self, ctx: &Context<Self>, dialog: &client_core::dialog::Dialog) -> Html {
+        let link = ctx.link();
+        let id = dialog.id;
+        let onclick = link.callback(move |_| Msg::SetDialog(id));
+        html! {
+            <div {onclick} class={format!("dialog did{}{}", dialog.id, if dialog.online { "" } else { " offline" })}>
+                <div class="avatar"></div>
+                <div class="info">
+                    <p class="name">
+                        { if Some(dialog.id) != self.state.my_id { format!("User#{}", dialog.id) } else { "Me".to_string() } }
+                        if !dialog.online && Some(dialog.id) != self.state.my_id {
+                            <span class="presence">{" · offline"}</span>
+                        }
+                    </p>
+                    if let Some(message) = &dialog.last_message {
+                        <p class="last-message">{ message.content.clone() }</p>
+                    }
+                </div>
+                if dialog.is_request {
+                    <div class="request-actions">
+                        <button class="accept" onclick={link.callback(move |event: MouseEvent| {
+                            event.stop_propagation();
+                            Msg::AcceptContact(id)
+                        })}>{"Accept"}</button>
+                        <button class="decline" onclick={link.callback(move |event: MouseEvent| {
+                            event.stop_propagation();
+                            Msg::DeclineContact(id)
+                        })}>{"Decline"}</button>
+                    </div>
+                }
+                if dialog.unchecked_count != 0 {
+                    <div class="checked">{dialog.unchecked_count}</div>
+                }
+            </div>
+        }
+    }
+    fn view_section(&self, ctx: &Context<Self>, title: &str, filter: impl Fn(&client_core::dialog::Dialog) -> bool) -> Html {
+        let dialogs: Vec<&client_core::dialog::Dialog> = self.state.dialogs.values()
+            .filter(|x| !self.state.blocked.contains(&x.id) && filter(x))
+            .collect();
+        if dialogs.is_empty() {
+            return html! {};
+        }
+        html! {
+            <>
+                <p class="section-title">{title}</p>
+                {dialogs.into_iter().map(|x| self.view_dialog(ctx, x)).collect::<Html>()}
+            </>
+        }
+    }
+    fn view_devices(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let onsubmit = link.callback(|event: FocusEvent| {
+            event.prevent_default();
+            Msg::Link
+        });
+        let qr = self.link_code.as_deref()
+            .and_then(fingerprint::qr_svg)
+            .map(|svg| format!("data:image/svg+xml;base64,{}", base64::encode(svg)));
+        html! {
+            <div class="blocked-list devices">
+                <p class="title">{"Linked devices"}</p>
+                {self.devices.iter().map(|device| {
+                    let id = device.id;
+                    html! {
+                        <div class="blocked-user">
+                            <p class="name">
+                                {format!("Device #{}", id)}
+                                if Some(id) == self.state.my_device {
+                                    {" (this device)"}
+                                } else if !device.online {
+                                    {" (offline)"}
+                                }
+                            </p>
+                            if Some(id) != self.state.my_device {
+                                <button onclick={link.callback(move |_| Msg::Revoke(id))}>{"Revoke"}</button>
+                            }
+                        </div>
+                    }
+                }).collect::<Html>()}
+                <p class="title">{"Link a new device"}</p>
+                if let Some(code) = &self.link_code {
+                    <p class="empty">{"Enter this code on the new device within five minutes. It works once."}</p>
+                    <p class="link-code">{code.clone()}</p>
+                    if let Some(qr) = qr {
+                        <img class="qr" src={qr} alt="Linking code QR code" />
+                    }
+                }
+                <button class="link" onclick={link.callback(|_| Msg::RequestLinkCode)}>{"Show a linking code"}</button>
+                <p class="title">{"Link this device to an account"}</p>
+                <p class="empty">{"This device's own identity and contacts are replaced by the account's."}</p>
+                <form class="link-form" {onsubmit}>
+                    <input ref={self.link_input.clone()} type="text" placeholder="Linking code" autocomplete="off" required=true />
+                    <button type="submit">{"Link"}</button>
+                </form>
+            </div>
+        }
+    }
+    fn view_settings(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let onsubmit = link.callback(|event: FocusEvent| {
+            event.prevent_default();
+            Msg::SetServer
+        });
+        let change = |apply: fn(&mut Settings, &Event)| {
+            let settings = self.settings.clone();
+            link.callback(move |event: Event| {
+                let mut settings = settings.clone();
+                apply(&mut settings, &event);
+                Msg::SaveSettings(settings)
+            })
+        };
+        fn days(event: &Event) -> Option<u64> {
+            event.target_unchecked_into::<HtmlSelectElement>().value().parse().ok()
+        }
+        fn checked(event: &Event) -> bool {
+            event.target_unchecked_into::<HtmlInputElement>().checked()
+        }
+        html! {
+            <div class="blocked-list settings">
+                <p class="title">{"Server"}</p>
+                <p class="empty">{"Leave empty to use the server this page came from. Saving reloads the page."}</p>
+                <form class="link-form" {onsubmit}>
+                    <input ref={self.server_input.clone()} type="url" placeholder={server()} value={self.settings.server.clone()} autocomplete="off" />
+                    <button type="submit">{"Save"}</button>
+                </form>
+                <p class="title">{"Notifications"}</p>
+                <label class="setting">
+                    <input type="checkbox" checked={self.settings.notifications}
+                        onchange={change(|settings, event| settings.notifications = checked(event))} />
+                    {"Notify about messages while the tab is hidden"}
+                </label>
+                <label class="setting">
+                    <input type="checkbox" checked={self.settings.previews} disabled={!self.settings.notifications}
+                        onchange={change(|settings, event| settings.previews = checked(event))} />
+                    {"Show the text in notifications"}
+                </label>
+                <p class="title">{"Keys"}</p>
+                <label class="setting">
+                    {"Rotate keys "}
+                    <select onchange={change(|settings, event| settings.rotate_days = days(event))}>
+                        <option value="" selected={self.settings.rotate_days.is_none()}>{"never"}</option>
+                        {[7, 30, 90].into_iter().map(|x| html! {
+                            <option value={x.to_string()} selected={self.settings.rotate_days == Some(x)}>{format!("every {} days", x)}</option>
+                        }).collect::<Html>()}
+                    </select>
+                </label>
+                <button class="link" disabled={!self.keys_ready} onclick={link.callback(|_| Msg::RotateKeys)}>{"Rotate now"}</button>
+                <p class="title">{"History"}</p>
+                <label class="setting">
+                    {"Keep messages "}
+                    <select onchange={change(|settings, event| settings.retention_days = days(event))}>
+                        <option value="" selected={self.settings.retention_days.is_none()}>{"forever"}</option>
+                        {[1, 7, 30, 365].into_iter().map(|x| html! {
+                            <option value={x.to_string()} selected={self.settings.retention_days == Some(x)}>
+                                { if x == 1 { "for a day".to_string() } else { format!("for {} days", x) } }
+                            </option>
+                        }).collect::<Html>()}
+                    </select>
+                </label>
+            </div>
+        }
+    }
+    fn view_blocked(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let mut blocked: Vec<usize> = self.state.blocked.iter().copied().collect();
+        blocked.sort_unstable();
+        html! {
+            <div class="blocked-list">
+                <p class="title">{"Blocked users"}</p>
+                if blocked.is_empty() {
+                    <p class="empty">{"Nobody is blocked"}</p>
+                }
+                {blocked.into_iter().map(|id| html! {
+                    <div class="blocked-user">
+                        <p class="name">{format!("User#{}", id)}</p>
+                        <button onclick={link.callback(move |_| Msg::Unblock(id))}>{"Unblock"}</button>
+                    </div>
+                }).collect::<Html>()}
+            </div>
+        }
+    }
+    /// The open conversation. Its props are built as a struct, since yew
+    /// 0.19 expands each prop written out in `html!` into a statement
+    /// clippy flags as an unnecessary operation.
+    fn view_dialog_pane(&self, ctx: &Context<Self>, me: usize, dialog: &client_core::dialog::Dialog) -> Html {
+        let link = ctx.link();
+        let id = dialog.id;
+        let props = dialog::Props {
+            me,
+            id: dialog.id,
+            messages: dialog.messages.clone(),
+            safety_number: dialog.safety_number.clone(),
+            verification: dialog.verification,
+            key_changed: dialog.key_changed,
+            online: dialog.online || Some(dialog.id) == self.state.my_id,
+            connected: self.connected,
+            has_more_history: self.paging.get(&id).is_some_and(|x| x.before.is_some()),
+            missing: dialog.missing(),
+            timer: dialog.timer,
+            now: self.state.now,
+            callback: link.callback(Msg::Crypt),
+            on_block: link.callback(move |_| Msg::Block(id)),
+            on_verify: link.callback(move |_| Msg::Verify(id)),
+            on_accept_key: link.callback(move |_| Msg::AcceptKey(id)),
+            on_load_more: link.callback(move |_| Msg::LoadMoreHistory(id)),
+            on_clear_history: link.callback(move |_| Msg::ClearHistory(id)),
+            on_retry: link.callback(Msg::Retry),
+            on_edit: link.callback(|(id, text)| Msg::Edit(id, text)),
+            on_delete: link.callback(Msg::Delete),
+            on_reply: link.callback(|(reply, text)| Msg::Reply(reply, text)),
+            on_react: link.callback(|(key, emoji)| Msg::React(key, emoji)),
+            on_timer: link.callback(Msg::SetTimer),
+            on_attach: link.callback(Msg::Attach),
+        };
+        html! { <Dialog ..props /> }
+    }
 }
 
 impl Component for Chat {
@@ -46,107 +595,566 @@ impl Component for Chat {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
-        let rsa = Self::get_rsa();
-        let writer = wss::run("ws://127.0.0.1:8081/chat", ctx.link().callback(|x| Msg::HandleData(x)));
+        let rsa = Self::get_rsa(ctx.link().callback(|_| Msg::KeysReady), ctx.link().callback(Msg::KeysFailed));
+        let writer = wss::run(
+            &server(),
+            ctx.link().callback(Msg::HandleData),
+            ctx.link().callback(Msg::Connection)
+        );
+        let tick = ctx.link().callback(|_| Msg::Tick);
+        let ticker = Interval::new(1_000, move || tick.emit(()));
+        let history_ready = ctx.link().callback(Msg::HistoryReady);
+        let history_unavailable = ctx.link().callback(|_| Msg::HistoryUnavailable);
+        spawn_local(async move {
+            match History::open().await {
+                Ok(history) => history_ready.emit(Rc::new(history)),
+                Err(e) => {
+                    console::error_2(&JsValue::from_str("history is unavailable:"), &e);
+                    history_unavailable.emit(());
+                }
+            }
+        });
         Self {
-            my_id: None,
+            state: State { trust: storage::get_json(TRUST).unwrap_or_default(), now: Date::now() as u64, ..State::default() },
+            keys_ready: false,
+            reblock: Vec::new(),
+            keys_error: None,
+            status: wss::Status::Connecting,
+            connected: false,
+            resuming: false,
             rsa,
-            text: String::new(),
-            dialog_id: None,
-            dialogs: HashMap::new(),
+            show_blocked: false,
+            devices: Vec::new(),
+            show_devices: false,
+            link_code: None,
+            link_input: NodeRef::default(),
+            settings: Settings::load(),
+            show_settings: false,
+            server_input: NodeRef::default(),
+            history: None,
+            unsaved: Some(vec![]),
+            paging: HashMap::new(),
+            outbox: Outbox::load(),
+            uploads: HashMap::new(),
+            unsent: vec![],
+            _ticker: ticker,
             writer
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
+            Msg::Connection(status) => {
+                self.status = status;
+                if status == wss::Status::Online {
+                    return true;
+                }
+                self.connected = false;
+                self.resuming = false;
+                // whoever is still there comes back with the user list
+                for dialog in self.state.dialogs.values_mut() {
+                    dialog.online = false;
+                }
+                let uploads: Vec<usize> = self.uploads.keys().copied().collect();
+                for random_id in uploads {
+                    self.fail_upload(random_id);
+                }
+                true
+            }
+            Msg::KeysReady => {
+                if self.settings.rotation_due(Date::now() as u64) {
+                    ctx.link().send_message(Msg::RotateKeys);
+                    return false;
+                }
+                self.keys_ready = true;
+                self.announce(ctx);
+                true
+            }
+            Msg::KeysFailed(error) => {
+                self.keys_error = Some(error);
+                true
+            }
+            Msg::RotateKeys => {
+                let now = Date::now() as u64;
+                let confirmed = web_sys::window().unwrap()
+                    .confirm_with_message("Rotate keys? Every contact sees your key change and has to verify you again.")
+                    .unwrap_or(false);
+                if !confirmed {
+                    // a due rotation asks again once the next one is
+                    if self.settings.rotation_due(now) {
+                        Settings::rotated(now);
+                    }
+                    if !self.keys_ready {
+                        ctx.link().send_message(Msg::KeysReady);
+                    }
+                    return false;
+                }
+                self.keys_ready = false;
+                Settings::rotated(now);
+                self.reblock = self.state.blocked.iter().copied().collect();
+                self.rotate(
+                    self.outbox.copies(),
+                    ctx.link().callback(|(random_id, copy)| Msg::Kept(random_id, copy)),
+                    ctx.link().callback(|_| Msg::KeysReady),
+                );
+                true
+            }
             Msg::SendPublicKey(key) => {
-                let data = json!(UserEvent::PublicKey(key));
-                let writer_clone = self.writer.clone();
-                spawn_local(async move {
-                    let mut writer_lock = writer_clone.lock().await;
-                    writer_lock.send(Message::Text(data.to_string())).await.unwrap();
-                });
+                self.state.public_key = Some(key.clone());
+                self.send(UserEvent::PublicKey(key));
                 false
             }
             Msg::Crypt(data) => {
-                self.text = data;
-                self.go_crypt(ctx.link().callback(|x| Msg::SendCrypt(x)));
+                if let Some(to) = self.writable_dialog() {
+                    self.encrypt(ctx, to, random_id(), Payload::Text { text: data, reply: None });
+                }
                 false
             }
-            Msg::SendCrypt(s) => {
-                if self.dialog_id.is_none() {
-                    return false;
-                }
-                let mut rand_bytes = [0u8; 4];
-                getrandom::getrandom(&mut rand_bytes).unwrap();
-                let random_id = usize::from_be_bytes(rand_bytes);
-                let message = UserEvent::Message { 
-                    to: self.dialog_id.unwrap(), 
-                    message: s.clone(), 
-                    random_id
+            Msg::Input(input) => {
+                self.handle(ctx, input);
+                true
+            }
+            Msg::Attach(file) => {
+                let to = match self.writable_dialog() {
+                    Some(to) => to,
+                    None => return false
                 };
-                let data = json!(message);
-                let writer_clone = self.writer.clone();
+                let callback = ctx.link().callback(move |sealed| Msg::Sealed(to, sealed));
                 spawn_local(async move {
-                    let mut writer_lock = writer_clone.lock().await;
-                    writer_lock.send(Message::Text(data.to_string())).await.unwrap();
+                    match attachment::seal(&file).await {
+                        Ok(sealed) => callback.emit(sealed),
+                        Err(e) => console::error_2(&JsValue::from_str("could not encrypt attachment:"), &e)
+                    }
                 });
-                self.dialogs.get_mut(&self.dialog_id.unwrap())
-                    .and_then(|dialog| Some(dialog.add_message(random_id, self.my_id.unwrap(), self.text.clone())));
+                false
+            }
+            Msg::Sealed(to, sealed) => {
+                let me = match self.state.my_id {
+                    Some(me) => me,
+                    None => {
+                        self.unsent.push((to, sealed));
+                        return false;
+                    }
+                };
+                let random_id = random_id();
+                let mut message = match Payload::Attachment(sealed.attachment.clone()).into_message(random_id, me) {
+                    Some(message) => message,
+                    None => return false
+                };
+                message.state = MessageState::Pending;
+                if let Some(dialog) = self.state.dialogs.get_mut(&to) {
+                    message.expires = dialog.expiry(Date::now() as u64);
+                    dialog.add_message(message);
+                }
+                self.uploads.insert(random_id, (to, sealed));
+                self.upload(random_id);
+                true
+            }
+            Msg::Preview(dialog, id, preview) => {
+                if let Some(dialog) = self.state.dialogs.get_mut(&dialog) {
+                    dialog.set_preview(id, preview);
+                }
                 true
             }
+            Msg::Resend(random_id) => {
+                if self.connected && self.outbox.state(random_id) == MessageState::Pending {
+                    self.resend(ctx, random_id);
+                }
+                false
+            }
+            Msg::Resealed { to, random_id, messages } => {
+                if let Some(entry) = self.outbox.get(random_id) {
+                    self.send(UserEvent::Message { to, messages, random_id, ttl: entry.ttl });
+                }
+                false
+            }
+            Msg::Kept(random_id, copy) => {
+                self.outbox.keep(random_id, copy);
+                false
+            }
+            Msg::Retry(random_id) if self.uploads.contains_key(&random_id) => {
+                self.upload(random_id);
+                true
+            }
+            Msg::Retry(random_id) => {
+                if self.outbox.retry(random_id).is_some() {
+                    self.set_state(random_id, MessageState::Pending);
+                    self.resend(ctx, random_id);
+                }
+                true
+            }
+            Msg::Edit(id, text) => {
+                if let Some(to) = self.writable_dialog() {
+                    self.encrypt(ctx, to, random_id(), Payload::Edit { id, text });
+                }
+                false
+            }
+            Msg::Delete(id) => {
+                if let Some(to) = self.writable_dialog() {
+                    self.send(UserEvent::Retract { to, random_id: id });
+                    self.encrypt(ctx, to, random_id(), Payload::Delete { id });
+                }
+                false
+            }
+            Msg::Reply(reply, text) => {
+                if let Some(to) = self.writable_dialog() {
+                    self.encrypt(ctx, to, random_id(), Payload::Text { text, reply: Some(reply) });
+                }
+                false
+            }
+            Msg::React((from, id), emoji) => {
+                if let Some(to) = self.writable_dialog() {
+                    self.encrypt(ctx, to, random_id(), Payload::React { id, from, emoji });
+                }
+                false
+            }
+            Msg::SetTimer(seconds) => {
+                if let Some(to) = self.writable_dialog() {
+                    self.encrypt(ctx, to, random_id(), Payload::Timer { seconds });
+                }
+                false
+            }
+            Msg::Tick => {
+                let now = Date::now() as u64;
+                let (mut expired, mut counting) = (false, false);
+                for message in self.state.dialogs.values().flat_map(|x| x.messages.iter()) {
+                    expired |= message.expires.is_some_and(|x| x <= now);
+                    counting |= message.expires.is_some();
+                }
+                self.state.handle(Input::Tick(now));
+                if expired {
+                    self.expire_history(now);
+                }
+                counting
+            }
             Msg::SetDialog(id) => {
-                self.dialogs.get_mut(&id).and_then(|user| Some({
-                    user.clear();
-                }));
-                self.dialog_id = Some(id);
-                true
-            }
-            Msg::AddMessage(rid, id, message) => {
-                self.dialogs.get_mut(&id)
-                    .and_then(|dialog| Some(
-                        if let Some(cid) = self.dialog_id {
-                            if cid == dialog.id {
-                                dialog.add_message(rid, id, message);
-                            } else {
-                                dialog.add_message(rid, id, message).add_unchecked();
-                            }
-                        } else {
-                            dialog.add_message(rid, id, message).add_unchecked();
+                let load = self.paging.get(&id).is_none_or(|x| !x.loaded);
+                self.handle(ctx, Input::Select(Some(id)));
+                if load {
+                    self.load_history(ctx, id, None);
+                }
+                true
+            }
+            Msg::AddUser(user) => {
+                let id = user.id;
+                let back = self.state.find(&client_core::fingerprint::identity(&user.key)) != Some(id);
+                self.handle(ctx, Input::Event(SystemEvent::UserIn(user)));
+                if back {
+                    self.resend_to(ctx, id);
+                }
+                true
+            }
+            Msg::AcceptContact(id) => {
+                if let Some(dialog) = self.state.dialogs.get_mut(&id) {
+                    if !dialog.is_applied {
+                        dialog.change_applied();
+                    }
+                }
+                self.send(UserEvent::AcceptContact(id));
+                true
+            }
+            // the server blocks them too unless it routes between contacts only
+            Msg::DeclineContact(id) => {
+                if let Some(dialog) = self.state.dialogs.get_mut(&id) {
+                    dialog.is_request = false;
+                }
+                if self.state.selected == Some(id) {
+                    self.state.selected = None;
+                }
+                self.send(UserEvent::DeclineContact(id));
+                true
+            }
+            Msg::Block(id) => {
+                self.state.blocked.insert(id);
+                if self.state.selected == Some(id) {
+                    self.state.selected = None;
+                }
+                self.send(UserEvent::Block(id));
+                true
+            }
+            Msg::Unblock(id) => {
+                self.state.blocked.remove(&id);
+                self.send(UserEvent::Unblock(id));
+                true
+            }
+            Msg::Verify(id) => {
+                self.handle(ctx, Input::Verify(id));
+                true
+            }
+            Msg::AcceptKey(id) => {
+                self.handle(ctx, Input::AcceptKeys(id));
+                self.resend_to(ctx, id);
+                true
+            }
+            Msg::HistoryReady(history) => {
+                // what happened while it was opening goes in first, in order
+                let unsaved = self.unsaved.take().unwrap_or_default();
+                let saving = history.clone();
+                spawn_local(async move {
+                    for (identity, change) in unsaved {
+                        if let Err(e) = saving.apply(&identity, change).await {
+                            console::error_2(&JsValue::from_str("could not save history:"), &e);
                         }
-                    ));
+                    }
+                });
+                self.history = Some(history);
+                self.expire_history(Date::now() as u64);
+                self.prune_history();
+                if let Some(id) = self.state.selected {
+                    self.load_history(ctx, id, None);
+                }
+                false
+            }
+            Msg::HistoryUnavailable => {
+                self.unsaved = None;
+                false
+            }
+            Msg::HistoryPage(id, mut page) => {
+                for message in page.messages.iter_mut().filter(|x| Some(x.from) == self.state.my_id) {
+                    message.state = self.outbox.state(message.id);
+                }
+                for message in &page.messages {
+                    self.fetch_attachment(ctx, id, message);
+                }
+                if let Some(dialog) = self.state.dialogs.get_mut(&id) {
+                    dialog.prepend(page.messages);
+                    self.paging.entry(id).or_default().before = page.before;
+                }
                 true
             }
-            Msg::AddUser(dialog) => {
-                self.dialogs.insert(dialog.id, dialog);
+            Msg::LoadMoreHistory(id) => {
+                let before = self.paging.get(&id).and_then(|x| x.before.clone());
+                if before.is_some() {
+                    self.load_history(ctx, id, before);
+                }
+                false
+            }
+            Msg::ClearHistory(id) => {
+                let identity = match self.state.dialogs.get_mut(&id) {
+                    Some(dialog) => {
+                        dialog.clear_history();
+                        dialog.identity.clone()
+                    }
+                    None => return false
+                };
+                if let Some(paging) = self.paging.get_mut(&id) {
+                    paging.before = None;
+                }
+                if let Some(history) = self.history.clone() {
+                    spawn_local(async move {
+                        if let Err(e) = history.clear(&identity).await {
+                            console::error_2(&JsValue::from_str("could not clear history:"), &e);
+                        }
+                    });
+                }
                 true
             }
+            Msg::WipeEverything => {
+                let confirmed = web_sys::window().unwrap()
+                    .confirm_with_message("Delete all messages, keys and settings from this browser?")
+                    .unwrap_or(false);
+                if !confirmed {
+                    return false;
+                }
+                self.wipe();
+                true
+            }
+            Msg::ToggleBlocked => {
+                self.show_blocked = !self.show_blocked;
+                self.show_devices = false;
+                self.show_settings = false;
+                true
+            }
+            Msg::ToggleDevices => {
+                self.show_devices = !self.show_devices;
+                self.show_blocked = false;
+                self.show_settings = false;
+                true
+            }
+            Msg::ToggleSettings => {
+                self.show_settings = !self.show_settings;
+                self.show_blocked = false;
+                self.show_devices = false;
+                true
+            }
+            Msg::SaveSettings(settings) => {
+                if settings.notifications && !self.settings.notifications {
+                    if let Err(e) = Notification::request_permission() {
+                        console::warn_2(&JsValue::from_str("could not ask for notifications:"), &e);
+                    }
+                }
+                let prune = settings.retention_days != self.settings.retention_days;
+                settings.save();
+                self.settings = settings;
+                if prune {
+                    self.prune_history();
+                }
+                if self.keys_ready && self.settings.rotation_due(Date::now() as u64) {
+                    ctx.link().send_message(Msg::RotateKeys);
+                }
+                true
+            }
+            Msg::SetServer => {
+                let server = match self.server_input.cast::<HtmlInputElement>() {
+                    Some(input) => input.value().trim().to_string(),
+                    None => return false
+                };
+                self.settings.server = server;
+                self.settings.save();
+                let _ = web_sys::window().unwrap().location().reload();
+                false
+            }
+            Msg::RequestLinkCode => {
+                self.send(UserEvent::LinkCode);
+                false
+            }
+            Msg::Link => {
+                let code = match self.link_input.cast::<HtmlInputElement>() {
+                    Some(input) => input.value().trim().to_uppercase(),
+                    None => return false
+                };
+                if !code.is_empty() {
+                    self.send(UserEvent::Link(code));
+                }
+                false
+            }
+            Msg::Revoke(device) => {
+                let confirmed = web_sys::window().unwrap()
+                    .confirm_with_message(&format!("Unlink device #{}? It will lose access to this account.", device))
+                    .unwrap_or(false);
+                if confirmed {
+                    self.send(UserEvent::Revoke(device));
+                }
+                false
+            }
             Msg::HandleData(data) => {
-                let data = serde_json::from_str(&data).unwrap();
+                let data = match serde_json::from_str(&data) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        console::warn_1(&JsValue::from_str(&format!("could not read server event: {}", e)));
+                        return false;
+                    }
+                };
                 let link = ctx.link();
                 match data {
-                    SystemEvent::YourId(id) => {self.my_id = Some(id);false}
-                    SystemEvent::Message { from, message, random_id } => {
-                        self.go_decrypt(message, link.callback(move |x| Msg::AddMessage(random_id, from, x)));
+                    SystemEvent::Devices(devices) => {
+                        self.devices = devices;
+                        true
+                    },
+                    SystemEvent::LinkCode(code) => {
+                        self.link_code = Some(code);
+                        true
+                    },
+                    SystemEvent::Linked(linked) => {
+                        if !linked {
+                            console::warn_1(&JsValue::from_str("the linking code is wrong or expired"));
+                        }
+                        if let (true, Some(input)) = (linked, self.link_input.cast::<HtmlInputElement>()) {
+                            input.set_value("");
+                        }
+                        true
+                    },
+                    SystemEvent::Revoked => {
+                        storage::remove(RESUME_TOKEN);
+                        self.wipe();
                         true
                     },
-                    SystemEvent::SetKey(_) => todo!(),
                     SystemEvent::GetUsersIds(users) => {
                         for user in users {
-                            self.parse_user(user, link.callback(|dialog| Msg::AddUser(dialog)));
+                            self.parse_user(user, link.callback(Msg::AddUser));
                         }
                         false
                     },
-                    SystemEvent::MessageStatus { random_id, status } => {false},
+                    SystemEvent::MessageStatus { random_id, status: true, stamp } => {
+                        if let Some(stamp) = stamp {
+                            self.restamp(random_id, stamp);
+                        }
+                        self.outbox.delivered(random_id);
+                        self.handle(ctx, Input::Event(SystemEvent::MessageStatus { random_id, status: true, stamp }));
+                        true
+                    },
+                    SystemEvent::MessageStatus { random_id, status: false, .. } => {
+                        if self.outbox.failed(random_id) {
+                            let attempts = self.outbox.get(random_id).map_or(0, |x| x.attempts);
+                            let resend = link.callback(Msg::Resend);
+                            spawn_local(async move {
+                                TimeoutFuture::new(Outbox::delay(attempts)).await;
+                                resend.emit(random_id);
+                            });
+                        } else {
+                            self.handle(ctx, Input::Event(SystemEvent::MessageStatus { random_id, status: false, stamp: None }));
+                        }
+                        true
+                    },
                     SystemEvent::UserIn(user) => {
-                        self.parse_user(user, link.callback(|dialog| Msg::AddUser(dialog)));
+                        self.parse_user(user, link.callback(Msg::AddUser));
+                        false
+                    },
+                    SystemEvent::YourId(id) => {
+                        self.handle(ctx, Input::Event(SystemEvent::YourId(id)));
+                        for (to, sealed) in std::mem::take(&mut self.unsent) {
+                            link.send_message(Msg::Sealed(to, sealed));
+                        }
+                        true
+                    },
+                    SystemEvent::ResumeToken(token) => {
+                        match storage::get(RESUME_TOKEN) {
+                            Some(previous) if previous != token && !self.resuming => {
+                                self.resuming = true;
+                                self.send(UserEvent::Resume(previous));
+                            }
+                            _ => {
+                                self.resuming = false;
+                                self.connected = true;
+                                storage::set(RESUME_TOKEN, &token);
+                                self.announce(ctx);
+                                for (random_id, _) in self.outbox.pending() {
+                                    self.resend(ctx, random_id);
+                                }
+                            }
+                        }
+                        false
+                    },
+                    SystemEvent::Upload { random_id, blob: Some(blob) } => {
+                        if let Some((_, sealed)) = self.uploads.get_mut(&random_id) {
+                            sealed.attachment.blob = blob.clone();
+                            let chunks = sealed.data.chunks(attachment::CHUNK)
+                                .map(|data| UserEvent::Chunk { blob: blob.clone(), data: base64::encode(data) })
+                                .collect();
+                            self.send_many(chunks);
+                        }
                         false
                     },
-                    SystemEvent::UserOut(user) => {
-                        self.dialogs.remove(&user.id);
+                    SystemEvent::Upload { random_id, blob: None } => {
+                        console::warn_1(&JsValue::from_str("attachment is over the server's quota"));
+                        self.fail_upload(random_id);
                         true
                     },
+                    SystemEvent::BlobStatus { blob, status } => {
+                        let random_id = match self.uploads.iter().find(|(_, (_, x))| x.attachment.blob == blob) {
+                            Some((random_id, _)) => *random_id,
+                            None => return false
+                        };
+                        if !status {
+                            self.fail_upload(random_id);
+                            return true;
+                        }
+                        if let Some((to, sealed)) = self.uploads.remove(&random_id) {
+                            // the pending copy goes to history once sent, with its blob
+                            let pending = self.state.dialogs.get_mut(&to)
+                                .and_then(|x| x.messages.iter_mut().find(|x| x.id == random_id))
+                                .and_then(|x| x.attachment.as_mut());
+                            if let Some(attachment) = pending {
+                                attachment.blob = sealed.attachment.blob.clone();
+                            }
+                            self.encrypt(ctx, to, random_id, Payload::Attachment(sealed.attachment));
+                        }
+                        false
+                    },
+                    event => {
+                        self.handle(ctx, Input::Event(event));
+                        true
+                    }
                 }
             }
         }
@@ -154,42 +1162,79 @@ impl Component for Chat {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
-        let dialogs: Vec<&MiniDialog> = self.dialogs.values().collect();
         html! {
             <div class="content">
                 <div class="dialogs">
-                    {dialogs.into_iter().map(|x| {
-                        let id = x.id;
-                        let onclick = link.callback(move |_| Msg::SetDialog(id));
-                        html! {
-                            <div {onclick} class={format!("dialog did{}", x.id)}>
-                                <div class="avatar"></div>
-                                <div class="info">
-                                    <p class="name">{ if x.id != self.my_id.unwrap() { format!("User#{}", x.id) } else { "Me".to_string() } }</p>
-                                    if let Some(message) = &x.last_message {
-                                        <p class="last-message">{ message.content.clone() }</p>
-                                    }
-                                </div>
-                                if x.unchecked_count != 0 {
-                                    <div class="checked">{x.unchecked_count}</div>
-                                }
-                            </div>
-                        }
-                    }).collect::<Html>()}
+                    <p class={format!("connection {:?}", self.status).to_lowercase()}>
+                        { match self.status {
+                            wss::Status::Connecting => "Connecting…",
+                            wss::Status::Online => "Online",
+                            wss::Status::Reconnecting => "Reconnecting…",
+                            wss::Status::Offline => "Offline"
+                        } }
+                    </p>
+                    { self.view_section(ctx, "Contacts", |x| x.is_applied) }
+                    { self.view_section(ctx, "Requests", |x| x.is_request) }
+                    { self.view_section(ctx, "Online", |x| x.online && !x.is_applied && !x.is_request) }
+                    if let Some(error) = &self.keys_error {
+                        <p class="keys-loading keys-error">{ format!("Could not load keys ({}). Reload to try again.", error) }</p>
+                    } else if !self.keys_ready {
+                        <p class="keys-loading">{"Loading keys…"}</p>
+                    }
+                    <button class="blocked-toggle" onclick={link.callback(|_| Msg::ToggleBlocked)}>
+                        { format!("Blocked ({})", self.state.blocked.len()) }
+                    </button>
+                    <button class="blocked-toggle devices-toggle" onclick={link.callback(|_| Msg::ToggleDevices)}>
+                        { format!("Linked devices ({})", self.devices.len()) }
+                    </button>
+                    <button class="blocked-toggle settings-toggle" onclick={link.callback(|_| Msg::ToggleSettings)}>
+                        {"Settings"}
+                    </button>
+                    <button class="wipe" onclick={link.callback(|_| Msg::WipeEverything)}>
+                        {"Wipe everything"}
+                    </button>
                 </div>
-                if let Some(dialog) = self.dialog_id {
-                    <Dialog me={self.my_id.unwrap()} id={dialog} messages={self.dialogs.get(&dialog).unwrap().messages.clone()} callback={link.callback(|data: String| Msg::Crypt(data))} />
+                if self.show_blocked {
+                    { self.view_blocked(ctx) }
+                } else if self.show_devices {
+                    { self.view_devices(ctx) }
+                } else if self.show_settings {
+                    { self.view_settings(ctx) }
+                } else if let (Some(me), Some(dialog)) = (self.state.my_id, self.state.selected.and_then(|id| self.state.dialogs.get(&id))) {
+                    { self.view_dialog_pane(ctx, me, dialog) }
                 }
             </div>
         }
     }
 
-    fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
-        if first_render {
-            self.send_public_key(ctx.link().callback(|key| Msg::SendPublicKey(key)));
-        }
+}
+
+/// The socket URL: the one set in the settings, the one the serving chat
+/// server put in the page's `chat-server` meta tag, or `/chat` at the
+/// page's own origin.
+fn server() -> String {
+    let settings = Settings::load();
+    if !settings.server.is_empty() {
+        return settings.server;
+    }
+    let window = web_sys::window().unwrap();
+    let meta = window.document()
+        .and_then(|document| document.query_selector("meta[name=chat-server]").ok().flatten())
+        .and_then(|meta| meta.get_attribute("content"));
+    if let Some(server) = meta {
+        return server;
     }
+    let location = window.location();
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws"
+    };
+    format!("{}://{}/chat", scheme, location.host().unwrap_or_default())
 }
+const RESUME_TOKEN: &str = "resume-token";
+/// Where the accepted keys, verified safety numbers and timers of each
+/// account are kept.
+const TRUST: &str = "trust";
 
 fn main() {
     yew::start_app::<Chat>();
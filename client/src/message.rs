@@ -1,45 +1,154 @@
-use yew::{Component, Context, html, Html};
+use std::collections::BTreeMap;
+use yew::{Callback, html, Html};
+use js_sys::Date;
+use wasm_bindgen::JsValue;
+use crate::attachment::{self, Attachment, Preview};
 
-#[derive(PartialEq, Clone, Debug)]
-pub struct Message {
-    pub id: usize,
-    pub from: usize,
-    pub content: String,
+pub use client_core::message::{remaining, Message, MessageState, Reply};
+
+/// The reactions offered under every message.
+pub const REACTIONS: [&str; 5] = ["👍", "❤️", "😂", "😮", "😢"];
+
+/// What can be done with a message from its bubble, each called with the message id.
+#[derive(PartialEq, Clone)]
+pub struct Actions {
+    pub retry: Callback<usize>,
+    pub edit: Callback<usize>,
+    pub delete: Callback<usize>,
+    /// Replies and jumps pick a message by `(from, id)`, since they work on
+    /// anyone's messages.
+    pub reply: Callback<(usize, usize)>,
+    pub jump: Callback<(usize, usize)>,
+    pub react: Callback<((usize, usize), Option<String>)>,
 }
 
-impl Message {
-    pub fn new(id: usize, from: usize, content: String) -> Self {
-        Self { id, from, content }
-    }
-    pub fn view(&self, is_me: bool) -> Html {
+/// How a message shows up in a dialog.
+pub trait Bubble {
+    fn view(&self, me: usize, now: u64, actions: &Actions) -> Html;
+}
+
+/// The id of a bubble's element, for jumping to it from replies.
+pub fn element_id(from: usize, id: usize) -> String {
+    format!("message-{}-{}", from, id)
+}
+
+impl Bubble for Message {
+    fn view(&self, me: usize, now: u64, actions: &Actions) -> Html {
+        let is_me = self.from == me;
+        let id = self.id;
+        let key = (self.from, self.id);
+        let state = match self.state {
+            MessageState::Sent => "",
+            MessageState::Pending => " pending",
+            MessageState::Failed => " failed"
+        };
         html! {
-            <div class={ if !is_me {format!("message mid{}", self.id)} else {format!("message me mid{}", self.id)}}>
+            <div id={element_id(self.from, self.id)} class={ if !is_me {format!("message mid{}{}", self.id, state)} else {format!("message me mid{}{}", self.id, state)}}>
               <div class="avatar"></div>
-              <p class="content">{self.content.clone()}</p>
+              if let Some(reply) = &self.reply {
+                  <button class="quote" onclick={actions.jump.reform({let target = (reply.from, reply.id); move |_| target})}>
+                      {reply.snippet.clone()}
+                  </button>
+              }
+              if let Some(attachment) = &self.attachment {
+                  { view_attachment(attachment) }
+              } else {
+                  <p class="content">{self.content.clone()}</p>
+              }
+              if let Some(expires) = self.expires {
+                  <p class="countdown">{format!("⏱ {}", remaining(expires.saturating_sub(now) / 1000))}</p>
+              }
+              if self.edited || self.stamp.is_some() {
+                  <p class="meta">
+                      { self.stamp.map(|x| time(x.timestamp)).unwrap_or_default() }
+                      { if self.edited { " edited" } else { "" } }
+                  </p>
+              }
+              if self.state == MessageState::Sent {
+                  <div class="actions">
+                      { view_picker(self, me, actions) }
+                      <button class="reply" onclick={actions.reply.reform(move |_| key)}>{"Reply"}</button>
+                      if is_me && self.attachment.is_none() {
+                          <button class="edit" onclick={actions.edit.reform(move |_| id)}>{"Edit"}</button>
+                      }
+                      if is_me {
+                          <button class="delete" onclick={actions.delete.reform(move |_| id)}>{"Delete"}</button>
+                      }
+                  </div>
+              }
+              if !self.reactions.is_empty() {
+                  { view_reactions(self, me) }
+              }
+              if self.state == MessageState::Pending {
+                  <p class="state">{"Sending…"}</p>
+              }
+              if self.state == MessageState::Failed {
+                  <button class="retry" onclick={actions.retry.reform(move |_| id)}>{"Not sent, retry"}</button>
+              }
             </div>
         }
     }
 }
 
-impl Component for Message {
-    type Message = ();
-    type Properties = ();
+fn view_picker(message: &Message, me: usize, actions: &Actions) -> Html {
+    let key = (message.from, message.id);
+    let mine = message.reactions.get(&me);
+    REACTIONS.iter().map(|emoji| {
+        let chosen = mine.is_some_and(|x| x == emoji);
+        let reaction = (!chosen).then(|| emoji.to_string());
+        html! {
+            <button class={if chosen {"react chosen"} else {"react"}} onclick={actions.react.reform(move |_| (key, reaction.clone()))}>
+                {*emoji}
+            </button>
+        }
+    }).collect()
+}
 
-    fn create(_ctx: &Context<Self>) -> Self {
-        Self::new(
-            0, 
-            0, 
-            "Hello, world!".into(), 
-        )
+fn view_reactions(message: &Message, me: usize) -> Html {
+    let mut counts: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (by, emoji) in &message.reactions {
+        let name = if *by == me { "Me".to_string() } else { format!("User#{}", by) };
+        counts.entry(emoji.as_str()).or_default().push(name);
+    }
+    html! {
+        <div class="reactions">
+            { counts.into_iter().map(|(emoji, names)| html! {
+                <span class="reaction" title={names.join(", ")}>{format!("{} {}", emoji, names.len())}</span>
+            }).collect::<Html>() }
+        </div>
     }
+}
 
-    fn view(&self, _ctx: &Context<Self>) -> Html {
-        html! {
-            <div class={format!("message mid{}", self.id)}>
-              <div class="avatar"></div>
-              <p class="content">{self.content.clone()}</p>
-            </div>
-        }
+fn view_attachment(attachment: &Attachment) -> Html {
+    let label = format!("{} ({})", attachment.name, attachment::format_size(attachment.size));
+    html! {
+        <div class="content attachment">
+            { match &attachment.preview {
+                Preview::Ready(url) => html! {
+                    <>
+                        if attachment.is_image() {
+                            <img class="preview" src={url.clone()} alt={attachment.name.clone()} />
+                        }
+                        <a href={url.clone()} download={attachment.name.clone()}>{label}</a>
+                    </>
+                },
+                Preview::Loading => html! { <p>{format!("{}, loading…", label)}</p> },
+                Preview::Unavailable => html! { <p class="unavailable">{format!("{}, no longer available", label)}</p> }
+            } }
+        </div>
     }
+}
 
-}
\ No newline at end of file
+fn date_of(timestamp: u64) -> Date {
+    Date::new(&JsValue::from_f64(timestamp as f64))
+}
+
+/// The local day `timestamp` falls on, as shown above the first message of each day.
+pub fn day(timestamp: u64) -> String {
+    String::from(date_of(timestamp).to_locale_date_string("default", &JsValue::UNDEFINED))
+}
+
+fn time(timestamp: u64) -> String {
+    let date = date_of(timestamp);
+    format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
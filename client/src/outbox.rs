@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::{message::MessageState, storage};
+
+const KEY: &str = "outbox";
+/// Failed sends retried automatically before the user has to step in.
+pub const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: u32 = 2_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    /// Who it is for, by account identity since ids are handed out again.
+    pub identity: String,
+    /// The payload sealed for ourselves, to seal again for whatever devices
+    /// the account has when it gets sent. `None` until sealed.
+    #[serde(default)]
+    pub copy: Option<String>,
+    /// Seconds the server may hold the message for, from the dialog's timer.
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    pub attempts: u32,
+    pub failed: bool
+}
+
+/// Every outgoing message until the server confirms it, mirrored to local
+/// storage so a reload doesn't lose what was never delivered. Entries
+/// from before they were kept by identity don't load and are dropped.
+#[derive(Default)]
+pub struct Outbox {
+    entries: BTreeMap<usize, Entry>
+}
+
+impl Outbox {
+    pub fn load() -> Self {
+        Self { entries: storage::get_json(KEY).unwrap_or_default() }
+    }
+    fn save(&self) {
+        storage::set_json(KEY, &self.entries);
+    }
+    pub fn push(&mut self, random_id: usize, identity: String, ttl: Option<u64>) {
+        self.entries.insert(random_id, Entry { identity, copy: None, ttl, attempts: 0, failed: false });
+        self.save();
+    }
+    /// Keeps the sealed copy of entry `random_id`.
+    pub fn keep(&mut self, random_id: usize, copy: String) {
+        if let Some(entry) = self.entries.get_mut(&random_id) {
+            entry.copy = Some(copy);
+            self.save();
+        }
+    }
+    pub fn get(&self, random_id: usize) -> Option<&Entry> {
+        self.entries.get(&random_id)
+    }
+    pub fn delivered(&mut self, random_id: usize) -> Option<Entry> {
+        let entry = self.entries.remove(&random_id);
+        self.save();
+        entry
+    }
+    /// Records a failed attempt, `true` while it may still be retried automatically.
+    pub fn failed(&mut self, random_id: usize) -> bool {
+        let retry = match self.entries.get_mut(&random_id) {
+            Some(entry) => {
+                entry.attempts += 1;
+                entry.failed = entry.attempts >= MAX_ATTEMPTS;
+                !entry.failed
+            }
+            None => false
+        };
+        self.save();
+        retry
+    }
+    pub fn retry(&mut self, random_id: usize) -> Option<Entry> {
+        let entry = self.entries.get_mut(&random_id).map(|entry| {
+            entry.attempts = 0;
+            entry.failed = false;
+            entry.clone()
+        });
+        self.save();
+        entry
+    }
+    /// The sealed copies of every entry, by random id.
+    pub fn copies(&self) -> Vec<(usize, String)> {
+        self.entries.iter()
+            .filter_map(|(id, entry)| entry.copy.clone().map(|copy| (*id, copy)))
+            .collect()
+    }
+    pub fn pending(&self) -> Vec<(usize, Entry)> {
+        self.entries.iter()
+            .filter(|(_, entry)| !entry.failed)
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect()
+    }
+    /// How long to wait before resending an entry that failed `attempts` times.
+    pub fn delay(attempts: u32) -> u32 {
+        RETRY_DELAY * attempts
+    }
+    pub fn state(&self, random_id: usize) -> MessageState {
+        match self.entries.get(&random_id) {
+            Some(entry) if entry.failed => MessageState::Failed,
+            Some(_) => MessageState::Pending,
+            None => MessageState::Sent
+        }
+    }
+}
\ No newline at end of file
@@ -1,31 +1,124 @@
+use client_core::native::Identity;
+use js_sys::{Array, Map, Object, Reflect, Uint8Array};
 use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
 use web_sys::CryptoKey;
+use crate::{crypt::{subtle, web_crypto}, idb};
+
+const EXPONENT: [u8; 3] = [1u8, 0u8, 1u8];
+const BITS: usize = 2048;
+const IDENTITY: &str = "identity";
+/// Where the pure-Rust backend keeps its key pair, apart from the WebCrypto
+/// one so switching backends loses neither.
+const NATIVE_IDENTITY: &str = "identity-native";
+
+/// The identity key pair, in the backend `crypt::web_crypto` picked.
+#[derive(Clone, Debug)]
+pub enum KeyPair {
+    Web { private_key: CryptoKey, public_key: CryptoKey },
+    Native(Box<Identity>)
+}
+
+#[derive(Clone, Debug)]
+pub enum KeyState {
+    Loading,
+    Ready(KeyPair)
+}
 
 #[derive(Clone, Debug)]
 pub struct RsaCrypto {
-    private_key: CryptoKey,
-    public_key: CryptoKey,
-    is_null: bool
+    state: KeyState
+}
+
+impl Default for RsaCrypto {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RsaCrypto {
     pub fn new() -> Self {
-        Self { private_key: JsValue::NULL.into(), public_key: JsValue::NULL.into(), is_null: true }
+        Self { state: KeyState::Loading }
     }
-    pub fn set_private(&mut self, value: CryptoKey) -> &mut Self {
-        self.is_null = false;
-        self.private_key = value;
-        self
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, KeyState::Ready { .. })
     }
-    pub fn set_public(&mut self, value: CryptoKey) -> &mut Self {
-        self.is_null = false;
-        self.public_key = value;
+    pub fn set_keys(&mut self, keys: KeyPair) -> &mut Self {
+        self.state = KeyState::Ready(keys);
         self
     }
-    pub fn get_private(&self) -> Result<&CryptoKey, ()> {
-        if self.is_null { Err(()) } else { Ok(&self.private_key) }
+    pub fn get_keys(&self) -> Option<&KeyPair> {
+        match &self.state {
+            KeyState::Ready(keys) => Some(keys),
+            KeyState::Loading => None
+        }
     }
-    pub fn get_public(&self) -> Result<&CryptoKey, ()> {
-        if self.is_null { Err(()) } else { Ok(&self.public_key) }
+    /// Restores the identity saved by `store`, `Ok(false)` if there is none yet.
+    pub async fn load(&mut self) -> Result<bool, JsValue> {
+        let name = if web_crypto() { IDENTITY } else { NATIVE_IDENTITY };
+        let db = idb::open().await?;
+        let keys = idb::get(&db, idb::KEYS, &name.into()).await;
+        db.close();
+        let keys = keys?;
+        if keys.is_undefined() {
+            return Ok(false);
+        }
+        if let Some(pem) = keys.as_string() {
+            let identity = Identity::from_pem(&pem).map_err(|e| JsValue::from_str(&e.to_string()))?;
+            self.set_keys(KeyPair::Native(Box::new(identity)));
+            return Ok(true);
+        }
+        let private_key = Reflect::get(&keys, &"privateKey".into())?;
+        let public_key = Reflect::get(&keys, &"publicKey".into())?;
+        self.set_keys(KeyPair::Web { private_key: private_key.into(), public_key: public_key.into() });
+        Ok(true)
     }
-}
\ No newline at end of file
+    pub async fn store(&self) -> Result<(), JsValue> {
+        let (name, keys) = match &self.state {
+            KeyState::Ready(KeyPair::Web { private_key, public_key }) => {
+                let keys = Object::new();
+                Reflect::set(&keys, &"privateKey".into(), private_key)?;
+                Reflect::set(&keys, &"publicKey".into(), public_key)?;
+                (IDENTITY, keys.into())
+            }
+            KeyState::Ready(KeyPair::Native(identity)) => {
+                let pem = identity.to_pem().map_err(|e| JsValue::from_str(&e.to_string()))?;
+                (NATIVE_IDENTITY, JsValue::from_str(&pem))
+            }
+            KeyState::Loading => return Err(JsValue::from_str("keys are not loaded"))
+        };
+        let db = idb::open().await?;
+        let result = idb::put(&db, idb::KEYS, &name.into(), &keys).await;
+        db.close();
+        result
+    }
+    /// Replaces the identity with a freshly generated key pair. With
+    /// WebCrypto the private key is not extractable, so it can only ever
+    /// leave the browser as an opaque `CryptoKey` stored in IndexedDB; the
+    /// pure-Rust backend has to keep it as PEM instead.
+    pub async fn rotate(&mut self) -> Result<(), JsValue> {
+        if !web_crypto() {
+            let identity = Identity::generate().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            self.set_keys(KeyPair::Native(Box::new(identity)));
+            return self.store().await;
+        }
+        let usages = ["encrypt", "decrypt"].iter()
+            .map(|x| JsValue::from_str(x))
+            .collect::<Array>();
+        let algorithm = {
+            let a = Map::new();
+            a.set(&"name".into(), &"RSA-OAEP".into());
+            a.set(&"publicExponent".into(), &Uint8Array::new(
+                &EXPONENT.map(JsValue::from).iter().collect::<Array>()));
+            a.set(&"modulusLength".into(), &BITS.into());
+            a.set(&"hash".into(), &"SHA-256".into());
+            Object::from_entries(&a)?
+        };
+        let promise_key = subtle().generate_key_with_object(&algorithm, false, &usages)?;
+        let keys = JsFuture::from(promise_key).await?;
+        let public_key = Reflect::get(&keys, &JsValue::from_str("publicKey"))?;
+        let private_key = Reflect::get(&keys, &JsValue::from_str("privateKey"))?;
+        self.set_keys(KeyPair::Web { private_key: private_key.into(), public_key: public_key.into() });
+        self.store().await
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use crate::storage;
+
+const KEY: &str = "settings";
+/// When the keys were last made, in milliseconds since the epoch.
+const ROTATED: &str = "keys-rotated";
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Preferences from the settings panel, kept in local storage.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    /// Socket URL to use instead of the page's own server, empty for none.
+    pub server: String,
+    /// Desktop notifications for messages arriving while the tab is hidden.
+    pub notifications: bool,
+    /// Whether notifications show the text or only who wrote.
+    pub previews: bool,
+    /// Days before the keys are replaced on their own, `None` for never.
+    pub rotate_days: Option<u64>,
+    /// Days of history kept, `None` for all of it.
+    pub retention_days: Option<u64>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        storage::get_json(KEY).unwrap_or_default()
+    }
+    pub fn save(&self) {
+        storage::set_json(KEY, self);
+    }
+    /// Whether the keys are due for replacing at `now`. Keys nobody noted
+    /// the age of count as new.
+    pub fn rotation_due(&self, now: u64) -> bool {
+        let rotated = match storage::get(ROTATED).and_then(|x| x.parse::<u64>().ok()) {
+            Some(rotated) => rotated,
+            None => {
+                Self::rotated(now);
+                return false;
+            }
+        };
+        self.rotate_days.is_some_and(|days| now.saturating_sub(rotated) >= days * DAY)
+    }
+    pub fn rotated(now: u64) {
+        storage::set(ROTATED, &now.to_string());
+    }
+    /// The oldest time history is kept from at `now`.
+    pub fn retained_since(&self, now: u64) -> Option<u64> {
+        self.retention_days.map(|days| now.saturating_sub(days * DAY))
+    }
+}
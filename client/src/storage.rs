@@ -0,0 +1,38 @@
+use serde::{de::DeserializeOwned, Serialize};
+use web_sys::{window, Storage};
+
+fn local_storage() -> Option<Storage> {
+    window().and_then(|x| x.local_storage().ok().flatten())
+}
+
+pub fn get(key: &str) -> Option<String> {
+    local_storage().and_then(|x| x.get_item(key).ok().flatten())
+}
+
+pub fn set(key: &str, value: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(key, value);
+    }
+}
+
+pub fn remove(key: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.remove_item(key);
+    }
+}
+
+pub fn clear() {
+    if let Some(storage) = local_storage() {
+        let _ = storage.clear();
+    }
+}
+
+pub fn get_json<T: DeserializeOwned>(key: &str) -> Option<T> {
+    get(key).and_then(|x| serde_json::from_str(&x).ok())
+}
+
+pub fn set_json<T: Serialize>(key: &str, value: &T) {
+    if let Ok(data) = serde_json::to_string(value) {
+        set(key, &data);
+    }
+}
\ No newline at end of file
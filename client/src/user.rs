@@ -1,7 +0,0 @@
-use serde::{Serialize, Deserialize};
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct SafeUser {
-    pub id: usize,
-    pub key: String
-}
\ No newline at end of file
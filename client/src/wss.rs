@@ -1,31 +1,230 @@
-use std::sync::Arc;
-use futures::{StreamExt, stream::SplitSink, lock::Mutex};
-use reqwasm::websocket::{futures::WebSocket, Message};
+use std::rc::Rc;
+use futures::{channel::mpsc, SinkExt, StreamExt, stream::SplitSink, lock::Mutex};
+use gloo_timers::future::TimeoutFuture;
+use reqwasm::{http::Request, websocket::{futures::WebSocket, Message}};
+use serde::Deserialize;
+use wasm_bindgen::{closure::Closure, JsCast};
 use wasm_bindgen_futures::spawn_local;
+use web_sys::{EventSource, MessageEvent};
 use yew::Callback;
 
+const MIN_DELAY: u32 = 500;
+const MAX_DELAY: u32 = 30_000;
+/// Failed attempts before trying the next transport.
+const ATTEMPTS: u32 = 2;
 
-pub fn run(addr: &str, callback: Callback<String>) -> Arc<Mutex<SplitSink<WebSocket, Message>>> {
-    WebSocket::open(addr)
-    .and_then(|socket| {
-        let (writer, mut reader) = socket.split();
-        spawn_local({
-            async move {
-                while let Some(msg) = reader.next().await {
-                    match msg {
-                        Ok(Message::Text(data)) => {
-                            callback.emit(data);
-                        }
-                        Ok(Message::Bytes(_)) => {
-                            break;
-                        }
-                        Err(e) => {
-                            panic!("ws: {:?}", e)
-                        }
+/// Where events go on the current connection.
+pub enum Sink {
+    Socket(SplitSink<WebSocket, Message>),
+    /// The HTTP transports post every event to this URL.
+    Http(String),
+}
+
+impl Sink {
+    pub async fn send(&mut self, text: String) -> Result<(), String> {
+        match self {
+            Sink::Socket(sink) => sink.send(Message::Text(text)).await.map_err(|e| format!("{:?}", e)),
+            Sink::Http(url) => {
+                let response = Request::post(url)
+                    .header("Content-Type", "application/json")
+                    .body(text)
+                    .send().await
+                    .map_err(|e| e.to_string())?;
+                match response.ok() {
+                    true => Ok(()),
+                    false => Err(format!("the server answered {}", response.status()))
+                }
+            }
+        }
+    }
+}
+
+/// The sink of the current connection, `None` while reconnecting.
+pub type Writer = Rc<Mutex<Option<Sink>>>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Connecting,
+    Online,
+    Reconnecting,
+    /// The browser has no network, retrying goes on regardless.
+    Offline
+}
+
+/// How to reach the server. Proxies that break WebSockets usually let
+/// Server-Sent Events through, and plain requests always.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Transport {
+    WebSocket,
+    Sse,
+    Poll
+}
+
+impl Transport {
+    /// Goes round to WebSockets again, in case the server was just down.
+    fn next(self) -> Self {
+        match self {
+            Transport::WebSocket => Transport::Sse,
+            Transport::Sse => Transport::Poll,
+            Transport::Poll => Transport::WebSocket
+        }
+    }
+}
+
+/// Exponential backoff with "equal jitter": somewhere between half and all
+/// of the capped delay, so a restarted server isn't hit by every client at once.
+fn backoff(attempt: u32) -> u32 {
+    let delay = MIN_DELAY.saturating_mul(1 << attempt.min(16)).min(MAX_DELAY);
+    let mut jitter = [0u8; 4];
+    let _ = getrandom::getrandom(&mut jitter);
+    delay / 2 + u32::from_be_bytes(jitter) % (delay / 2 + 1)
+}
+
+/// What hears from a connection: every event, and once it turns out to work.
+struct Listener {
+    callback: Callback<String>,
+    status: Callback<Status>,
+    online: bool,
+}
+
+impl Listener {
+    fn receive(&mut self, data: String) {
+        if !self.online {
+            self.online = true;
+            self.status.emit(Status::Online);
+        }
+        self.callback.emit(data);
+    }
+}
+
+async fn socket(addr: &str, writer: &Writer, listener: &mut Listener) {
+    if let Ok(socket) = WebSocket::open(addr) {
+        let (sink, mut reader) = socket.split();
+        *writer.lock().await = Some(Sink::Socket(sink));
+        while let Some(Ok(msg)) = reader.next().await {
+            if let Message::Text(data) = msg {
+                listener.receive(data);
+            }
+        }
+    }
+}
+
+enum Frame {
+    Session(String),
+    Data(String),
+    Closed,
+}
+
+/// Events come down an `EventSource`, whose first event names the session
+/// to post to. Its own reconnecting is cut short: a new stream is a new
+/// session on the server, which `Resume` has to pick up like any other.
+async fn sse(base: &str, writer: &Writer, listener: &mut Listener) {
+    let source = match EventSource::new(&format!("{}/chat/sse", base)) {
+        Ok(source) => source,
+        Err(_) => return
+    };
+    let (sender, mut frames) = mpsc::unbounded();
+    let on_session = Closure::<dyn FnMut(MessageEvent)>::new({
+        let sender = sender.clone();
+        move |event: MessageEvent| if let Some(token) = event.data().as_string() {
+            let _ = sender.unbounded_send(Frame::Session(token));
+        }
+    });
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+        let sender = sender.clone();
+        move |event: MessageEvent| if let Some(data) = event.data().as_string() {
+            let _ = sender.unbounded_send(Frame::Data(data));
+        }
+    });
+    let on_error = Closure::<dyn FnMut()>::new(move || {
+        let _ = sender.unbounded_send(Frame::Closed);
+    });
+    let _ = source.add_event_listener_with_callback("session", on_session.as_ref().unchecked_ref());
+    source.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    while let Some(frame) = frames.next().await {
+        match frame {
+            Frame::Session(token) => *writer.lock().await = Some(Sink::Http(format!("{}/chat/send/{}", base, token))),
+            Frame::Data(data) => listener.receive(data),
+            Frame::Closed => break
+        }
+    }
+    source.close();
+}
+
+#[derive(Deserialize)]
+struct PollSession {
+    token: String,
+}
+
+/// Events come as answers to requests the server holds until it has some.
+async fn poll(base: &str, writer: &Writer, listener: &mut Listener) {
+    let session = match Request::post(&format!("{}/chat/poll", base)).send().await {
+        Ok(response) if response.ok() => response.json::<PollSession>().await,
+        _ => return
+    };
+    let token = match session {
+        Ok(session) => session.token,
+        Err(_) => return
+    };
+    *writer.lock().await = Some(Sink::Http(format!("{}/chat/send/{}", base, token)));
+    let url = format!("{}/chat/poll/{}", base, token);
+    loop {
+        let events = match Request::get(&url).send().await {
+            Ok(response) if response.ok() => response.json::<Vec<serde_json::Value>>().await,
+            _ => return
+        };
+        match events {
+            Ok(events) => for event in events {
+                listener.receive(event.to_string());
+            },
+            Err(_) => return
+        }
+    }
+}
+
+/// The HTTP origin serving the socket at `addr`.
+pub fn http_base(addr: &str) -> String {
+    addr.replacen("ws", "http", 1).trim_end_matches("/chat").to_string()
+}
+
+/// Connects to the socket at `addr`, falling back to the HTTP transports
+/// at the same origin when it never comes up.
+pub fn run(addr: &str, callback: Callback<String>, status: Callback<Status>) -> Writer {
+    let writer: Writer = Rc::new(Mutex::new(None));
+    let addr = addr.to_string();
+    let base = http_base(&addr);
+    spawn_local({
+        let writer = writer.clone();
+        async move {
+            let mut attempt = 0;
+            let mut transport = Transport::WebSocket;
+            let mut failures = 0;
+            status.emit(Status::Connecting);
+            loop {
+                let mut listener = Listener { callback: callback.clone(), status: status.clone(), online: false };
+                match transport {
+                    Transport::WebSocket => socket(&addr, &writer, &mut listener).await,
+                    Transport::Sse => sse(&base, &writer, &mut listener).await,
+                    Transport::Poll => poll(&base, &writer, &mut listener).await
+                }
+                *writer.lock().await = None;
+                if listener.online {
+                    attempt = 0;
+                    failures = 0;
+                } else {
+                    failures += 1;
+                    if failures >= ATTEMPTS {
+                        transport = transport.next();
+                        failures = 0;
                     }
                 }
+                let offline = web_sys::window().is_some_and(|window| !window.navigator().on_line());
+                status.emit(if offline { Status::Offline } else { Status::Reconnecting });
+                TimeoutFuture::new(backoff(attempt)).await;
+                attempt += 1;
             }
-        });
-        Ok(Arc::new(Mutex::new(writer)))
-    }).unwrap()
-}
\ No newline at end of file
+        }
+    });
+    writer
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum Preview {
+    #[default]
+    Loading,
+    /// A URL the decrypted file can be shown from.
+    Ready(String),
+    Unavailable
+}
+
+/// A file as referenced from an encrypted message: where to fetch it, and
+/// the key and digest to check and open it with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub blob: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub key: String,
+    pub iv: String,
+    /// SHA-256 of the ciphertext, so a tampered blob is rejected before decryption.
+    pub digest: String,
+    #[serde(skip)]
+    pub preview: Preview,
+}
+
+impl Attachment {
+    pub fn is_image(&self) -> bool {
+        self.mime.starts_with("image/")
+    }
+}
+
+/// Human readable size, "1.5 MB".
+pub fn format_size(size: u64) -> String {
+    match size {
+        0..=1023 => format!("{} B", size),
+        1024..=1_048_575 => format!("{:.1} KB", size as f64 / 1024.0),
+        _ => format!("{:.1} MB", size as f64 / 1_048_576.0)
+    }
+}
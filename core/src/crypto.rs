@@ -0,0 +1,55 @@
+use std::{collections::HashMap, future::Future};
+use crate::{data::Stamp, payload::Payload, state::Input};
+
+/// What a client needs from a crypto implementation. Keys travel as base64
+/// SPKI of RSA-OAEP 2048 with SHA-256, envelopes as
+/// `wrapped key (256) || iv (12) || AES-GCM ciphertext`, so every backend
+/// reads what any other one sealed.
+pub trait Backend {
+    type Error: std::fmt::Display;
+    /// Our public key, base64 SPKI.
+    fn public_key(&self) -> impl Future<Output = Result<String, Self::Error>>;
+    /// Seals `plain` for the base64 SPKI `key`.
+    fn seal(&self, key: &str, plain: &[u8]) -> impl Future<Output = Result<Vec<u8>, Self::Error>>;
+    /// Opens an envelope sealed for our key.
+    fn open(&self, envelope: &[u8]) -> impl Future<Output = Result<Vec<u8>, Self::Error>>;
+}
+
+/// Crypto work the state machine hands out, see `Job::perform`.
+#[derive(Clone, Debug)]
+pub enum Job {
+    /// A message of dialog `dialog` addressed to this device.
+    Open { dialog: usize, from: usize, random_id: usize, stamp: Stamp, message: String },
+    /// A payload to seal for each of `keys`, by device id.
+    Seal { to: usize, random_id: usize, payload: Payload, keys: Vec<(usize, String)> },
+    /// A challenge the server sealed for our key.
+    Answer { challenge: String },
+}
+
+impl Job {
+    /// Does the work with `backend`, giving back what to feed the state next.
+    pub async fn perform<B: Backend>(self, backend: &B) -> Result<Input, String> {
+        match self {
+            Job::Open { dialog, from, random_id, stamp, message } => {
+                let envelope = base64::decode(message).map_err(|e| e.to_string())?;
+                let plain = backend.open(&envelope).await.map_err(|e| e.to_string())?;
+                let payload = Payload::parse(&plain).ok_or("message is not a payload")?;
+                Ok(Input::Opened { dialog, from, random_id, stamp, payload })
+            }
+            Job::Seal { to, random_id, payload, keys } => {
+                let plain = serde_json::to_vec(&payload).map_err(|e| e.to_string())?;
+                let mut messages = HashMap::new();
+                for (device, key) in keys {
+                    let envelope = backend.seal(&key, &plain).await.map_err(|e| e.to_string())?;
+                    messages.insert(device, base64::encode(envelope));
+                }
+                Ok(Input::Sealed { to, random_id, payload, messages })
+            }
+            Job::Answer { challenge } => {
+                let envelope = base64::decode(challenge).map_err(|e| e.to_string())?;
+                let nonce = backend.open(&envelope).await.map_err(|e| e.to_string())?;
+                Ok(Input::Answered(base64::encode(nonce)))
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::user::{DeviceInfo, SafeUser};
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum UserEvent {
+    GetUsersIds { start: usize, count: usize },
+    PublicKey(String),
+    /// `messages` holds a ciphertext for every device of the recipient and
+    /// our other devices, by device id. `ttl` tells the server how long the
+    /// message may wait for an offline recipient.
+    Message { to: usize, messages: HashMap<usize, String>, random_id: usize, ttl: Option<u64> },
+    AcceptContact(usize),
+    DeclineContact(usize),
+    Block(usize),
+    Unblock(usize),
+    Resume(String),
+    Upload { random_id: usize, size: u64 },
+    Chunk { blob: String, data: String },
+    Retract { to: usize, random_id: usize },
+    LinkCode,
+    Link(String),
+    Revoke(usize),
+    /// The nonce of a `Challenge`, base64, proving we hold our key.
+    Prove(String),
+}
+
+/// What the server stamps on every message it routes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub id: u64,
+    /// Milliseconds since the Unix epoch, by the server's clock.
+    pub timestamp: u64,
+    /// Counts up by one per message in the conversation, whichever side sent it.
+    pub seq: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemEvent {
+    YourId(usize),
+    YourDevice(usize),
+    /// `to` is only news for our own messages, synced from our other devices.
+    Message { from: usize, to: usize, message: String, random_id: usize, stamp: Stamp },
+    SetKey(String),
+    GetUsersIds(Vec<SafeUser>),
+    MessageStatus { random_id: usize, status: bool, stamp: Option<Stamp> },
+    UserIn(SafeUser),
+    UserOut(SafeUser),
+    ContactRequest(usize),
+    BlockList(Vec<usize>),
+    ResumeToken(String),
+    Upload { random_id: usize, blob: Option<String> },
+    BlobStatus { blob: String, status: bool },
+    Devices(Vec<DeviceInfo>),
+    LinkCode(String),
+    Linked(bool),
+    Revoked,
+    /// A nonce sealed for the key we announced, to send back opened.
+    Challenge(String),
+}
\ No newline at end of file
@@ -0,0 +1,240 @@
+use std::collections::BTreeSet;
+use crate::{attachment::Preview, data::Stamp, fingerprint, message::{Message, MessageState}, user::SafeDevice};
+
+/// The disappearing-messages timers on offer, in seconds.
+pub const TIMERS: [(u64, &str); 6] = [
+    (30, "30 seconds"),
+    (5 * 60, "5 minutes"),
+    (60 * 60, "1 hour"),
+    (24 * 60 * 60, "1 day"),
+    (7 * 24 * 60 * 60, "1 week"),
+    (28 * 24 * 60 * 60, "4 weeks"),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Verification {
+    Unverified,
+    Verified,
+    Changed
+}
+
+/// A conversation and everything known about the other side, without
+/// anything tied to a particular UI or crypto implementation.
+#[derive(Clone, Debug)]
+pub struct Dialog {
+    pub id: usize,
+    pub last_message: Option<Message>,
+    pub unchecked_count: usize,
+    /// The account's primary key, base64 SPKI.
+    pub key: String,
+    /// Who the account is across reconnects, see `fingerprint::identity`.
+    pub identity: String,
+    /// The key of each of the user's devices.
+    pub devices: Vec<SafeDevice>,
+    pub safety_number: Option<String>,
+    pub verification: Verification,
+    /// Whether the account has device keys nobody accepted yet. Nothing is
+    /// sealed for the dialog until they are.
+    pub key_changed: bool,
+    /// Whether the id may belong to someone else by now: we got a new id
+    /// of our own since the user last showed up, so the server may have
+    /// restarted and handed the ids out again.
+    pub stale: bool,
+    pub messages: Box<Vec<Message>>,
+    pub is_applied: bool,
+    pub is_request: bool,
+    /// Whether any of the user's devices is connected.
+    pub online: bool,
+    /// Sequence numbers that reached us since the page was opened, control
+    /// messages included, to tell which ones never arrived.
+    pub seen: BTreeSet<u64>,
+    /// Disappearing-messages timer in seconds, agreed on by both sides.
+    pub timer: Option<u64>
+}
+
+impl Dialog {
+    pub fn new(id: usize, key: String) -> Self {
+        Dialog {
+            id,
+            last_message: None,
+            unchecked_count: 0,
+            identity: fingerprint::identity(&key),
+            key,
+            devices: vec![],
+            safety_number: None,
+            verification: Verification::Unverified,
+            key_changed: false,
+            stale: false,
+            messages: Box::new(vec![]),
+            is_applied: false,
+            is_request: false,
+            online: true,
+            seen: BTreeSet::new(),
+            timer: None
+        }
+    }
+    /// Keys to encrypt for, the primary one alone for servers that don't
+    /// know about devices.
+    pub fn device_keys(&self) -> Vec<(usize, String)> {
+        if self.devices.is_empty() {
+            return vec![(self.id, self.key.clone())];
+        }
+        self.devices.iter().map(|x| (x.id, x.key.clone())).collect()
+    }
+    /// The keys of `device_keys` alone, sorted, as they are pinned and go
+    /// into the safety number.
+    pub fn key_set(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.device_keys().into_iter().map(|(_, key)| key).collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+    pub fn clear(&mut self) -> &mut Self {
+        self.unchecked_count = 0;
+        self
+    }
+    pub fn add_unchecked(&mut self) -> &mut Self {
+        self.unchecked_count += 1;
+        self
+    }
+    /// Puts `message` in order of its stamp's time, then sequence, as the
+    /// stored history is: sequences start over when a peer comes back under
+    /// a new id. Our own messages still waiting for a stamp stay at the end,
+    /// unstamped history from before them at the start.
+    pub fn add_message(&mut self, message: Message) -> &mut Self {
+        if self.messages.iter().any(|x| x.id == message.id && x.from == message.from) {
+            return self;
+        }
+        let position = match message.stamp {
+            Some(stamp) => self.messages.iter()
+                .position(|x| match x.stamp {
+                    Some(other) => (other.timestamp, other.seq) > (stamp.timestamp, stamp.seq),
+                    None => x.state != MessageState::Sent
+                })
+                .unwrap_or(self.messages.len()),
+            None => self.messages.len()
+        };
+        self.messages.insert(position, message);
+        self.last_message = self.messages.last().cloned();
+        self
+    }
+    /// Records the stamp our message `id` got on delivery, moving it into place.
+    pub fn set_stamp(&mut self, id: usize, stamp: Stamp) -> &mut Self {
+        self.see(stamp.seq);
+        if let Some(position) = self.messages.iter().position(|x| x.id == id && x.stamp.is_none()) {
+            let mut message = self.messages.remove(position);
+            message.stamp = Some(stamp);
+            self.add_message(message);
+        }
+        self
+    }
+    pub fn see(&mut self, seq: u64) -> &mut Self {
+        self.seen.insert(seq);
+        self
+    }
+    /// How many messages between the first and last one seen never arrived.
+    pub fn missing(&self) -> usize {
+        match (self.seen.first(), self.seen.last()) {
+            (Some(first), Some(last)) => (last - first + 1) as usize - self.seen.len(),
+            _ => 0
+        }
+    }
+    /// Puts a page of older messages in front of the loaded ones, skipping
+    /// anything that arrived while the page was being read.
+    pub fn prepend(&mut self, page: Vec<Message>) -> &mut Self {
+        let mut messages: Vec<Message> = page.into_iter()
+            .filter(|x| !self.messages.iter().any(|y| y.id == x.id && y.from == x.from))
+            .collect();
+        messages.extend(self.messages.drain(..));
+        self.last_message = messages.last().cloned();
+        *self.messages = messages;
+        self
+    }
+    pub fn set_state(&mut self, id: usize, state: MessageState) -> &mut Self {
+        if let Some(message) = self.messages.iter_mut().find(|x| x.id == id) {
+            message.state = state;
+        }
+        self
+    }
+    pub fn set_preview(&mut self, id: usize, preview: Preview) -> &mut Self {
+        let attachment = self.messages.iter_mut()
+            .find(|x| x.id == id)
+            .and_then(|x| x.attachment.as_mut());
+        if let Some(attachment) = attachment {
+            attachment.preview = preview;
+        }
+        self
+    }
+    /// Replaces the text of message `id`, only if `from` is who sent it.
+    pub fn edit_message(&mut self, from: usize, id: usize, text: String) -> &mut Self {
+        if let Some(message) = self.messages.iter_mut().find(|x| x.id == id && x.from == from && x.attachment.is_none()) {
+            message.content = text;
+            message.edited = true;
+        }
+        self.last_message = self.messages.last().cloned();
+        self
+    }
+    pub fn remove_message(&mut self, from: usize, id: usize) -> &mut Self {
+        self.messages.retain(|x| !(x.id == id && x.from == from));
+        self.last_message = self.messages.last().cloned();
+        self
+    }
+    /// Sets the reaction of `by` to message `id` sent by `from`. Unlike new
+    /// messages, it leaves the unread count and the dialog preview alone.
+    pub fn react(&mut self, by: usize, from: usize, id: usize, emoji: Option<String>) -> &mut Self {
+        if let Some(message) = self.messages.iter_mut().find(|x| x.id == id && x.from == from) {
+            message.react(by, emoji);
+        }
+        self
+    }
+    /// When a message sent at `sent` disappears under the current timer.
+    pub fn expiry(&self, sent: u64) -> Option<u64> {
+        self.timer.map(|seconds| sent + seconds * 1000)
+    }
+    /// Drops messages whose timer ran out by `now`, `true` if there were any.
+    pub fn expire(&mut self, now: u64) -> bool {
+        let count = self.messages.len();
+        self.messages.retain(|x| x.expires.is_none_or(|expires| expires > now));
+        if self.messages.len() == count {
+            return false;
+        }
+        self.last_message = self.messages.last().cloned();
+        true
+    }
+    pub fn clear_history(&mut self) -> &mut Self {
+        self.messages.clear();
+        self.last_message = None;
+        self
+    }
+    pub fn change_applied(&mut self) {
+        self.is_applied = !self.is_applied;
+        self.is_request = false;
+    }
+    /// Carries the conversation over from the dialog this one replaces,
+    /// when the user comes back or changes keys.
+    pub fn inherit(&mut self, old: Dialog) -> &mut Self {
+        self.last_message = old.last_message;
+        self.unchecked_count = old.unchecked_count;
+        self.messages = old.messages;
+        self.is_applied = old.is_applied;
+        self.is_request = old.is_request;
+        self.seen = old.seen;
+        self.timer = old.timer;
+        self
+    }
+    pub fn set_safety_number(&mut self, number: Option<String>, verified: Option<String>) -> &mut Self {
+        self.verification = match (&number, verified) {
+            (Some(number), Some(verified)) if *number == verified => Verification::Verified,
+            (_, Some(_)) => Verification::Changed,
+            _ => Verification::Unverified
+        };
+        self.safety_number = number;
+        self
+    }
+    pub fn mark_request(&mut self) -> &mut Self {
+        if !self.is_applied {
+            self.is_request = true;
+        }
+        self
+    }
+}
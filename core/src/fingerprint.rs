@@ -0,0 +1,62 @@
+use sha2::{Digest, Sha256, Sha512};
+
+const GROUPS: usize = 12;
+const GROUP_BYTES: usize = 5;
+
+/// Both parties get the same number: each side's device keys are hashed in
+/// a fixed order, and so are the two sides regardless of which is "mine".
+/// Linking or replacing a device on either side changes the number.
+pub fn safety_number(mine: &[String], theirs: &[String]) -> Option<String> {
+    let mut sides = [side(mine)?, side(theirs)?];
+    sides.sort();
+
+    let mut hasher = Sha512::new();
+    for side in &sides {
+        hasher.update((side.len() as u32).to_be_bytes());
+        hasher.update(side);
+    }
+    let digest = hasher.finalize();
+
+    Some(digest.chunks(GROUP_BYTES)
+        .take(GROUPS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, x| (acc << 8) | *x as u64);
+            format!("{:05}", value % 100000)
+        })
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+/// The decoded keys of one side, sorted and length-prefixed.
+fn side(keys: &[String]) -> Option<Vec<u8>> {
+    let mut keys = keys.iter().map(base64::decode).collect::<Result<Vec<_>, _>>().ok()?;
+    if keys.is_empty() {
+        return None;
+    }
+    keys.sort();
+    keys.dedup();
+    let mut side = Vec::new();
+    for key in keys {
+        side.extend((key.len() as u32).to_be_bytes());
+        side.extend(key);
+    }
+    Some(side)
+}
+
+/// What an account is known by between connections, where its id only
+/// lasts as long as the server process: the SHA-256 of its primary key, as
+/// hex. The server files block lists the same way.
+pub fn identity(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// A short fingerprint of a single key, for telling at a glance which key
+/// a client runs with.
+pub fn key_fingerprint(key: &str) -> Option<String> {
+    let digest = Sha256::digest(base64::decode(key).ok()?);
+    Some(digest.chunks(2)
+        .take(8)
+        .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+        .collect::<Vec<String>>()
+        .join(" "))
+}
@@ -0,0 +1,25 @@
+//! Everything a chat client does that isn't drawing or talking to a
+//! particular crypto API: the wire protocol, payloads, dialog bookkeeping
+//! and a state machine tying them together. With the `native` feature it
+//! also brings a pure-Rust crypto backend.
+
+pub mod attachment;
+pub mod crypto;
+pub mod data;
+pub mod dialog;
+pub mod fingerprint;
+pub mod message;
+#[cfg(feature = "native")]
+pub mod native;
+pub mod payload;
+pub mod state;
+pub mod trust;
+pub mod user;
+
+/// A fresh id for an outgoing message. Kept to 32 bits so the web client,
+/// where `usize` is 32 bits, can read it.
+pub fn random_id() -> usize {
+    let mut rand_bytes = [0u8; 4];
+    getrandom::getrandom(&mut rand_bytes).unwrap();
+    u32::from_be_bytes(rand_bytes) as usize
+}
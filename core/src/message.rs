@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::{attachment::Attachment, data::Stamp};
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum MessageState {
+    #[default]
+    Sent,
+    Pending,
+    Failed
+}
+
+/// Longest quote a reply carries of the message it answers, in characters.
+const SNIPPET: usize = 80;
+
+/// The message a reply answers, with a quote of it so the reply still reads
+/// well when the original is gone or not loaded.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Reply {
+    pub id: usize,
+    pub from: usize,
+    pub snippet: String,
+}
+
+impl Reply {
+    pub fn to(message: &Message) -> Self {
+        let mut snippet: String = message.content.chars().take(SNIPPET).collect();
+        if snippet.len() < message.content.len() {
+            snippet.push('…');
+        }
+        Self { id: message.id, from: message.from, snippet }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    pub id: usize,
+    pub from: usize,
+    pub content: String,
+    #[serde(default)]
+    pub attachment: Option<Attachment>,
+    #[serde(default)]
+    pub edited: bool,
+    #[serde(default)]
+    pub reply: Option<Reply>,
+    /// The emoji each participant reacted with, by their id.
+    #[serde(default)]
+    pub reactions: BTreeMap<usize, String>,
+    /// Set by the server once it routed the message; history from before
+    /// stamps existed has none.
+    #[serde(default)]
+    pub stamp: Option<Stamp>,
+    /// When the dialog's timer deletes the message, in milliseconds since the epoch.
+    #[serde(default)]
+    pub expires: Option<u64>,
+    /// Delivery state of our own messages; the outbox is the source of truth,
+    /// so it is never written to history.
+    #[serde(skip)]
+    pub state: MessageState,
+}
+
+impl Message {
+    pub fn new(id: usize, from: usize, content: String) -> Self {
+        Self { id, from, content, attachment: None, edited: false, reply: None, reactions: BTreeMap::new(), stamp: None, expires: None, state: MessageState::Sent }
+    }
+    pub fn react(&mut self, by: usize, emoji: Option<String>) {
+        match emoji {
+            Some(emoji) => self.reactions.insert(by, emoji),
+            None => self.reactions.remove(&by)
+        };
+    }
+}
+
+/// `seconds` as the largest one or two units that fit, like `3h 12m`.
+pub fn remaining(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600)
+    }
+}
@@ -0,0 +1,118 @@
+use aes_gcm::{aead::{Aead, KeyInit, OsRng}, Aes256Gcm, Key, Nonce};
+use rsa::{
+    Oaep, RsaPrivateKey, RsaPublicKey,
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+};
+use sha2::Sha256;
+use crate::crypto::Backend;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Same as WebCrypto's `RSA-OAEP` with `SHA-256`, a 2048 bit modulus and
+/// exponent 65537.
+const BITS: usize = 2048;
+/// Size of an RSA-OAEP 2048 ciphertext, the wrapped message key that leads
+/// every envelope. A message of exactly this size is pure RSA from older clients.
+pub const WRAPPED_KEY: usize = 256;
+pub const IV: usize = 12;
+/// Size of an AES-256 key.
+pub const KEY: usize = 32;
+
+fn oaep() -> Oaep {
+    Oaep::new::<Sha256>()
+}
+
+/// A key pair handled entirely in Rust, for where WebCrypto is missing.
+#[derive(Clone)]
+pub struct Identity {
+    private_key: RsaPrivateKey,
+    public_key: RsaPublicKey,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity").finish_non_exhaustive()
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Result<Self, Error> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, BITS)?;
+        let public_key = private_key.to_public_key();
+        Ok(Self { private_key, public_key })
+    }
+    /// Reads a private key saved by `to_pem`.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(pem)?;
+        let public_key = private_key.to_public_key();
+        Ok(Self { private_key, public_key })
+    }
+    /// The private key as PKCS#8 PEM.
+    pub fn to_pem(&self) -> Result<String, Error> {
+        Ok(self.private_key.to_pkcs8_pem(LineEnding::LF)?.to_string())
+    }
+    /// The public key the way WebCrypto exports it: base64 SPKI.
+    pub fn public_key(&self) -> String {
+        base64::encode(self.public_key.to_public_key_der().unwrap().as_bytes())
+    }
+    pub fn open(&self, envelope: &[u8]) -> Result<Vec<u8>, Error> {
+        if envelope.len() == WRAPPED_KEY {
+            return Ok(self.private_key.decrypt(oaep(), envelope)?);
+        }
+        if envelope.len() < WRAPPED_KEY + IV {
+            return Err("envelope is too short".into());
+        }
+        let (wrapped, rest) = envelope.split_at(WRAPPED_KEY);
+        let (iv, data) = rest.split_at(IV);
+        let raw = self.private_key.decrypt(oaep(), wrapped)?;
+        decrypt(&raw, iv, data)
+    }
+}
+
+pub fn import_public(key: &str) -> Result<RsaPublicKey, Error> {
+    Ok(RsaPublicKey::from_public_key_der(&base64::decode(key)?)?)
+}
+
+/// Seals `plain` under a one-off AES-GCM key wrapped with RSA-OAEP:
+/// `wrapped key (256) || iv (12) || ciphertext`.
+pub fn seal(public_key: &RsaPublicKey, plain: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = Aes256Gcm::generate_key(&mut OsRng);
+    let mut iv = [0u8; IV];
+    getrandom::getrandom(&mut iv)?;
+    let data = encrypt(&key, &iv, plain)?;
+    let mut envelope = public_key.encrypt(&mut OsRng, oaep(), key.as_slice())?;
+    envelope.extend_from_slice(&iv);
+    envelope.extend(data);
+    Ok(envelope)
+}
+
+/// AES-256-GCM with a 128 bit tag appended, as WebCrypto's `AES-GCM` does.
+pub fn encrypt(key: &[u8], iv: &[u8], plain: &[u8]) -> Result<Vec<u8>, Error> {
+    if key.len() != KEY || iv.len() != IV {
+        return Err("key or iv has the wrong size".into());
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher.encrypt(Nonce::from_slice(iv), plain).map_err(|_| "could not encrypt message".into())
+}
+
+pub fn decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    if key.len() != KEY || iv.len() != IV {
+        return Err("key or iv has the wrong size".into());
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher.decrypt(Nonce::from_slice(iv), data).map_err(|_| "could not decrypt message".into())
+}
+
+impl Backend for Identity {
+    type Error = Error;
+
+    async fn public_key(&self) -> Result<String, Error> {
+        Ok(Identity::public_key(self))
+    }
+    async fn seal(&self, key: &str, plain: &[u8]) -> Result<Vec<u8>, Error> {
+        seal(&import_public(key)?, plain)
+    }
+    async fn open(&self, envelope: &[u8]) -> Result<Vec<u8>, Error> {
+        Identity::open(self, envelope)
+    }
+}
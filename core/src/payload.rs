@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use crate::{attachment::{self, Attachment}, message::{self, Message, Reply}};
+
+/// What actually gets encrypted: structured, so a message can carry more
+/// than text without the server learning which kind it is.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Payload {
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply: Option<Reply>
+    },
+    Attachment(Attachment),
+    /// Replaces the text of our earlier message `id`.
+    Edit { id: usize, text: String },
+    /// Deletes our earlier message `id` for everyone.
+    Delete { id: usize },
+    /// Sets our reaction to message `id` sent by `from`, `None` takes it back.
+    React { id: usize, from: usize, emoji: Option<String> },
+    /// Sets the dialog's disappearing-messages timer in seconds for both
+    /// sides, `None` turns it off.
+    Timer { seconds: Option<u64> },
+    /// Kinds of payload newer clients may send.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Payload {
+    /// Older clients encrypted bare text, so anything that isn't a payload
+    /// is read as one.
+    pub fn parse(plain: &[u8]) -> Option<Self> {
+        serde_json::from_slice(plain).ok()
+            .or_else(|| String::from_utf8(plain.to_vec()).ok().map(|text| Payload::Text { text, reply: None }))
+    }
+    /// The message this payload shows up as, `None` for control payloads
+    /// that change an earlier message instead.
+    pub fn into_message(self, id: usize, from: usize) -> Option<Message> {
+        match self {
+            Payload::Text { text, reply } => {
+                let mut message = Message::new(id, from, text);
+                message.reply = reply;
+                Some(message)
+            }
+            Payload::Attachment(attachment) => {
+                let mut message = Message::new(id, from, attachment.name.clone());
+                message.attachment = Some(attachment);
+                Some(message)
+            }
+            Payload::Edit { .. } | Payload::Delete { .. } | Payload::React { .. } | Payload::Timer { .. } | Payload::Unknown => None
+        }
+    }
+    /// A one-line description of the payload as sent by `who`, for clients
+    /// that only have text to show it with.
+    pub fn describe(&self, who: &str) -> String {
+        match self {
+            Payload::Text { text, reply: Some(reply) } => format!("{} (re #{} \"{}\"): {}", who, reply.from, reply.snippet, text),
+            Payload::Text { text, reply: None } => format!("{}: {}", who, text),
+            Payload::Attachment(file) => format!("{} sent a file: {} ({})", who, file.name, attachment::format_size(file.size)),
+            Payload::Edit { text, .. } => format!("{} edited a message: {}", who, text),
+            Payload::Delete { .. } => format!("{} deleted a message", who),
+            Payload::React { emoji: Some(emoji), .. } => format!("{} reacted {}", who, emoji),
+            Payload::React { emoji: None, .. } => format!("{} took back a reaction", who),
+            Payload::Timer { seconds: Some(seconds) } => format!("{} set disappearing messages to {}", who, message::remaining(*seconds)),
+            Payload::Timer { seconds: None } => format!("{} turned disappearing messages off", who),
+            Payload::Unknown => format!("{} sent something this client can't show", who),
+        }
+    }
+}
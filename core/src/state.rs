@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::{
+    crypto::Job,
+    data::{Stamp, SystemEvent, UserEvent},
+    dialog::Dialog,
+    fingerprint,
+    message::MessageState,
+    payload::Payload,
+    trust::Trust,
+    user::SafeUser,
+};
+
+/// Everything that can happen to a client.
+#[derive(Clone, Debug)]
+pub enum Input {
+    /// Something the server sent.
+    Event(SystemEvent),
+    /// A message the crypto backend opened.
+    Opened { dialog: usize, from: usize, random_id: usize, stamp: Stamp, payload: Payload },
+    /// A payload sealed for every device it was meant for.
+    Sealed { to: usize, random_id: usize, payload: Payload, messages: HashMap<usize, String> },
+    /// The user wants `payload` sent to `to`.
+    Compose { to: usize, random_id: usize, payload: Payload },
+    /// The user opened a dialog, `None` closes it.
+    Select(Option<usize>),
+    /// The user accepts the device keys the account of dialog `.0` has now.
+    AcceptKeys(usize),
+    /// The user compared the safety number of dialog `.0` and it matched.
+    Verify(usize),
+    /// The clock moved on, in milliseconds since the epoch.
+    Tick(u64),
+    /// The opened nonce of a challenge, base64.
+    Answered(String),
+}
+
+/// What the client has to do about an input.
+#[derive(Clone, Debug)]
+pub enum Effect {
+    /// Write an event to the server.
+    Send(UserEvent),
+    /// Hand a job to the crypto backend and feed its result back.
+    Crypto(Job),
+    /// A payload took effect in `dialog`, as a new message or a change to
+    /// an earlier one. The state has it already; this is for keeping
+    /// history and telling the user.
+    Applied { dialog: usize, from: usize, random_id: usize, payload: Payload },
+    /// What the user settled about other accounts changed, for the client
+    /// to keep until next time.
+    Trust(Trust),
+}
+
+/// A client with no I/O of its own: inputs go in, effects come out, and
+/// the dialogs are there to be drawn however the UI likes.
+#[derive(Clone, Debug, Default)]
+pub struct State {
+    pub my_id: Option<usize>,
+    pub my_device: Option<usize>,
+    /// Our own public key, for safety numbers.
+    pub public_key: Option<String>,
+    pub dialogs: BTreeMap<usize, Dialog>,
+    pub selected: Option<usize>,
+    pub blocked: HashSet<usize>,
+    pub trust: Trust,
+    /// Payloads sealed before the server told us who we are, as
+    /// `(to, random_id, payload, messages)`, sent once it does.
+    pub unsent: Vec<(usize, usize, Payload, HashMap<usize, String>)>,
+    /// The time of the last tick, in milliseconds since the epoch.
+    pub now: u64,
+}
+
+impl State {
+    pub fn new(public_key: String) -> Self {
+        Self { public_key: Some(public_key), ..Self::default() }
+    }
+    pub fn handle(&mut self, input: Input) -> Vec<Effect> {
+        match input {
+            Input::Event(event) => return self.handle_event(event),
+            Input::Opened { dialog, from, random_id, stamp, payload } => return self.add_message(dialog, from, random_id, stamp, payload),
+            Input::Sealed { to, random_id, payload, messages } => return self.sent(to, random_id, payload, messages),
+            Input::Compose { to, random_id, payload } => return self.compose(to, random_id, payload),
+            Input::Select(id) => {
+                self.selected = id;
+                if let Some(dialog) = id.and_then(|id| self.dialogs.get_mut(&id)) {
+                    dialog.clear();
+                }
+            }
+            Input::AcceptKeys(id) => return self.accept_keys(id),
+            Input::Verify(id) => return self.verify(id),
+            Input::Answered(nonce) => return vec![Effect::Send(UserEvent::Prove(nonce))],
+            Input::Tick(now) => {
+                self.now = now;
+                for dialog in self.dialogs.values_mut() {
+                    dialog.expire(now);
+                }
+            }
+        }
+        vec![]
+    }
+    fn handle_event(&mut self, event: SystemEvent) -> Vec<Effect> {
+        match event {
+            SystemEvent::YourId(id) => {
+                self.my_id = Some(id);
+                for dialog in self.dialogs.values_mut() {
+                    dialog.stale = true;
+                }
+                let unsent = std::mem::take(&mut self.unsent);
+                return unsent.into_iter()
+                    .flat_map(|(to, random_id, payload, messages)| self.sent(to, random_id, payload, messages))
+                    .collect();
+            }
+            SystemEvent::YourDevice(id) => self.my_device = Some(id),
+            SystemEvent::GetUsersIds(users) => return self.add_users(users),
+            SystemEvent::UserIn(user) => return self.add_users(vec![user]),
+            SystemEvent::UserOut(user) => if let Some(dialog) = self.dialogs.get_mut(&user.id) {
+                dialog.online = false;
+            },
+            SystemEvent::Message { from, to, message, random_id, stamp } => {
+                if self.blocked.contains(&from) {
+                    return vec![];
+                }
+                let dialog = if Some(from) == self.my_id { to } else { from };
+                return vec![Effect::Crypto(Job::Open { dialog, from, random_id, stamp, message })];
+            }
+            SystemEvent::MessageStatus { random_id, status, stamp } => {
+                let me = self.my_id;
+                let dialog = self.dialogs.values_mut()
+                    .find(|dialog| dialog.messages.iter().any(|x| x.id == random_id && Some(x.from) == me && x.state != MessageState::Sent));
+                if let Some(dialog) = dialog {
+                    match (status, stamp) {
+                        (true, Some(stamp)) => dialog.set_state(random_id, MessageState::Sent).set_stamp(random_id, stamp),
+                        (true, None) => dialog.set_state(random_id, MessageState::Sent),
+                        (false, _) => dialog.set_state(random_id, MessageState::Failed)
+                    };
+                }
+            }
+            SystemEvent::ContactRequest(from) => if let Some(dialog) = self.dialogs.get_mut(&from).filter(|_| !self.blocked.contains(&from)) {
+                dialog.mark_request();
+            },
+            SystemEvent::BlockList(blocked) => self.blocked = blocked.into_iter().collect(),
+            SystemEvent::Challenge(challenge) => return vec![Effect::Crypto(Job::Answer { challenge })],
+            _ => ()
+        }
+        vec![]
+    }
+    /// Adds or refreshes the dialogs with `users`, saving the trust if any
+    /// keys got pinned.
+    fn add_users(&mut self, users: Vec<SafeUser>) -> Vec<Effect> {
+        let mut pinned = false;
+        for user in users {
+            pinned |= self.add_user(user);
+        }
+        if pinned {
+            return vec![Effect::Trust(self.trust.clone())];
+        }
+        vec![]
+    }
+    /// Adds or refreshes the dialog with `user`, keeping the conversation.
+    /// Returns whether that pinned keys.
+    fn add_user(&mut self, user: SafeUser) -> bool {
+        let mut dialog = Dialog::new(user.id, user.key);
+        dialog.devices = user.devices;
+        dialog.timer = self.trust.timers.get(&dialog.identity).copied();
+        if Some(dialog.id) == self.my_id {
+            dialog.is_applied = true;
+        }
+        // an id handed out again after a restart is someone new, not a known
+        // user with another key
+        let old = self.dialogs.remove(&dialog.id)
+            .filter(|old| !old.stale || old.identity == dialog.identity);
+        let keys = dialog.key_set();
+        let mut pinned = false;
+        match self.trust.accepts(&dialog.identity, &keys) {
+            Some(accepted) => {
+                dialog.key_changed = !accepted;
+                // devices that went away are dropped without asking
+                pinned = accepted && self.trust.pin(&dialog.identity, keys);
+            }
+            // another account under a known id, or one still waiting to be accepted
+            None if old.as_ref().is_some_and(|old| old.identity != dialog.identity || old.key_changed) => dialog.key_changed = true,
+            None => pinned = self.trust.pin(&dialog.identity, keys)
+        }
+        if let Some(old) = old {
+            dialog.inherit(old);
+        }
+        let id = dialog.id;
+        self.dialogs.insert(id, dialog);
+        // our own devices go into every safety number
+        if Some(id) == self.my_id {
+            let ids: Vec<usize> = self.dialogs.keys().copied().collect();
+            for id in ids {
+                self.refresh_safety_number(id);
+            }
+        } else {
+            self.refresh_safety_number(id);
+        }
+        pinned
+    }
+    /// Works out the safety number of dialog `id` from both sides' device keys.
+    fn refresh_safety_number(&mut self, id: usize) {
+        let mine = match self.my_id.and_then(|me| self.dialogs.get(&me)) {
+            Some(me) => me.key_set(),
+            None => self.public_key.iter().cloned().collect()
+        };
+        if let Some(dialog) = self.dialogs.get_mut(&id) {
+            let number = fingerprint::safety_number(&mine, &dialog.key_set());
+            dialog.set_safety_number(number, self.trust.verified.get(&dialog.identity).cloned());
+        }
+    }
+    fn accept_keys(&mut self, id: usize) -> Vec<Effect> {
+        let dialog = match self.dialogs.get_mut(&id) {
+            Some(dialog) => dialog,
+            None => return vec![]
+        };
+        dialog.key_changed = false;
+        self.trust.pin(&dialog.identity, dialog.key_set());
+        vec![Effect::Trust(self.trust.clone())]
+    }
+    fn verify(&mut self, id: usize) -> Vec<Effect> {
+        let dialog = match self.dialogs.get_mut(&id) {
+            Some(dialog) => dialog,
+            None => return vec![]
+        };
+        let number = match dialog.safety_number.clone() {
+            Some(number) => number,
+            None => return vec![]
+        };
+        self.trust.verified.insert(dialog.identity.clone(), number.clone());
+        dialog.set_safety_number(Some(number.clone()), Some(number));
+        vec![Effect::Trust(self.trust.clone())]
+    }
+    /// The dialog with account `identity` under the id it has now.
+    pub fn find(&self, identity: &str) -> Option<usize> {
+        self.dialogs.values()
+            .find(|dialog| dialog.identity == identity && !dialog.stale)
+            .map(|dialog| dialog.id)
+    }
+    /// The device keys a message to `to` gets sealed for, `None` while
+    /// the keys of `to` wait to be accepted.
+    pub fn recipients(&self, to: usize) -> Option<Vec<(usize, String)>> {
+        let mut keys = match self.dialogs.get(&to) {
+            Some(dialog) if !dialog.key_changed => self.trusted_keys(dialog),
+            _ => return None
+        };
+        if let Some(me) = self.my_id.filter(|x| *x != to).and_then(|x| self.dialogs.get(&x)) {
+            keys.extend(self.trusted_keys(me).into_iter().filter(|(id, _)| Some(*id) != self.my_device));
+        }
+        Some(keys)
+    }
+    /// The devices of `dialog` with accepted keys, so a device the server
+    /// slipped into an account never gets a copy.
+    fn trusted_keys(&self, dialog: &Dialog) -> Vec<(usize, String)> {
+        dialog.device_keys().into_iter()
+            .filter(|(_, key)| self.trust.accepts_key(&dialog.identity, key))
+            .collect()
+    }
+    fn add_message(&mut self, id: usize, from: usize, random_id: usize, stamp: Stamp, payload: Payload) -> Vec<Effect> {
+        let mine = Some(from) == self.my_id;
+        let selected = self.selected == Some(id);
+        let dialog = match self.dialogs.get_mut(&id) {
+            Some(dialog) => dialog,
+            None => return vec![]
+        };
+        dialog.see(stamp.seq);
+        let applied = Effect::Applied { dialog: id, from, random_id, payload: payload.clone() };
+        let mut message = match payload.clone().into_message(random_id, from) {
+            Some(message) => message,
+            None => {
+                let mut effects = self.apply_change(id, from, payload);
+                effects.push(applied);
+                return effects;
+            }
+        };
+        // a resent message we already got before the delivery report was lost
+        if dialog.messages.iter().any(|x| x.id == random_id && x.from == from) {
+            return vec![];
+        }
+        message.stamp = Some(stamp);
+        message.expires = dialog.expiry(stamp.timestamp);
+        dialog.add_message(message);
+        if !mine {
+            dialog.mark_request();
+            if !selected {
+                dialog.add_unchecked();
+            }
+        }
+        vec![applied]
+    }
+    /// Applies a control payload `from` sent in dialog `id`.
+    fn apply_change(&mut self, id: usize, from: usize, payload: Payload) -> Vec<Effect> {
+        let dialog = match self.dialogs.get_mut(&id) {
+            Some(dialog) => dialog,
+            None => return vec![]
+        };
+        match payload {
+            Payload::Edit { id, text } => {
+                dialog.edit_message(from, id, text);
+            }
+            Payload::Delete { id } => {
+                dialog.remove_message(from, id);
+            }
+            Payload::React { id, from: author, emoji } => {
+                dialog.react(from, author, id, emoji);
+            }
+            Payload::Timer { seconds } => {
+                dialog.timer = seconds;
+                match seconds {
+                    Some(seconds) => self.trust.timers.insert(dialog.identity.clone(), seconds),
+                    None => self.trust.timers.remove(&dialog.identity)
+                };
+                return vec![Effect::Trust(self.trust.clone())];
+            }
+            Payload::Text { .. } | Payload::Attachment(_) | Payload::Unknown => ()
+        }
+        vec![]
+    }
+    /// Asks for `payload` to be sealed for every device of `to`, and for our
+    /// other devices so they see what this one sent. Nothing goes to a
+    /// dialog whose keys changed until they are accepted.
+    fn compose(&self, to: usize, random_id: usize, payload: Payload) -> Vec<Effect> {
+        match self.recipients(to) {
+            Some(keys) => vec![Effect::Crypto(Job::Seal { to, random_id, payload, keys })],
+            None => vec![]
+        }
+    }
+    /// Sends a sealed payload and shows it as pending until the server
+    /// reports, or holds it until we know who we are.
+    fn sent(&mut self, to: usize, random_id: usize, payload: Payload, messages: HashMap<usize, String>) -> Vec<Effect> {
+        let (me, now) = match self.my_id {
+            Some(me) => (me, self.now),
+            None => {
+                self.unsent.push((to, random_id, payload, messages));
+                return vec![];
+            }
+        };
+        let dialog = match self.dialogs.get_mut(&to) {
+            Some(dialog) => dialog,
+            None => return vec![]
+        };
+        let mut effects = vec![];
+        if !dialog.is_applied {
+            dialog.change_applied();
+            effects.push(Effect::Send(UserEvent::AcceptContact(to)));
+        }
+        // the timer change itself has to outlive the old timer
+        let ttl = match payload {
+            Payload::Timer { .. } => None,
+            _ => dialog.timer
+        };
+        effects.push(Effect::Send(UserEvent::Message { to, messages, random_id, ttl }));
+        match payload.clone().into_message(random_id, me) {
+            Some(mut message) => {
+                message.state = MessageState::Pending;
+                message.expires = dialog.expiry(now);
+                dialog.add_message(message);
+            }
+            None => effects.extend(self.apply_change(to, me, payload.clone()))
+        }
+        effects.push(Effect::Applied { dialog: to, from: me, random_id, payload });
+        effects
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// What the user settled about other accounts, by their identity since
+/// account ids don't outlive the server's process. The state keeps it up to
+/// date; clients with somewhere to keep it hand it back on the next run.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Trust {
+    /// The device keys accepted for each account, sorted. An account seen
+    /// for the first time is taken as it is; keys it adds later wait for
+    /// the user.
+    #[serde(default)]
+    pub pinned: HashMap<String, Vec<String>>,
+    /// The safety number the user compared with each account.
+    #[serde(default)]
+    pub verified: HashMap<String, String>,
+    /// How long messages to each account last, in seconds.
+    #[serde(default)]
+    pub timers: HashMap<String, u64>,
+}
+
+impl Trust {
+    /// Whether all of `keys` were accepted for `identity`, `None` if
+    /// nothing ever was.
+    pub fn accepts(&self, identity: &str, keys: &[String]) -> Option<bool> {
+        self.pinned.get(identity).map(|pinned| keys.iter().all(|key| pinned.contains(key)))
+    }
+    /// Whether `key` is one of those accepted for `identity`.
+    pub fn accepts_key(&self, identity: &str, key: &str) -> bool {
+        self.pinned.get(identity).is_some_and(|pinned| pinned.iter().any(|x| x == key))
+    }
+    /// Accepts exactly `keys` for `identity`, `true` if that changed anything.
+    pub fn pin(&mut self, identity: &str, keys: Vec<String>) -> bool {
+        if self.pinned.get(identity) == Some(&keys) {
+            return false;
+        }
+        self.pinned.insert(identity.to_string(), keys);
+        true
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SafeDevice {
+    pub id: usize,
+    pub key: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SafeUser {
+    pub id: usize,
+    /// The account's primary key, safety numbers and pinning go by it.
+    pub key: String,
+    /// Every device of the account, each message is encrypted for all of them.
+    #[serde(default)]
+    pub devices: Vec<SafeDevice>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub id: usize,
+    pub key: Option<String>,
+    pub online: bool
+}
\ No newline at end of file
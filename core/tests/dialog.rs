@@ -0,0 +1,171 @@
+use client_core::{
+    data::Stamp,
+    dialog::{Dialog, Verification},
+    message::{Message, MessageState},
+};
+
+fn stamp(seq: u64) -> Stamp {
+    Stamp { id: seq, timestamp: 1_000 * seq, seq }
+}
+
+fn message(id: usize, from: usize, seq: Option<u64>) -> Message {
+    let mut message = Message::new(id, from, format!("message {}", id));
+    message.stamp = seq.map(stamp);
+    message
+}
+
+fn ids(dialog: &Dialog) -> Vec<usize> {
+    dialog.messages.iter().map(|x| x.id).collect()
+}
+
+#[test]
+fn orders_messages_by_seq() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.add_message(message(30, 2, Some(3)));
+    dialog.add_message(message(10, 2, Some(1)));
+    dialog.add_message(message(20, 1, Some(2)));
+    assert_eq!(ids(&dialog), [10, 20, 30]);
+    assert_eq!(dialog.last_message.as_ref().map(|x| x.id), Some(30));
+}
+
+#[test]
+fn orders_new_messages_after_history_when_seq_starts_over() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.prepend(vec![message(50, 2, Some(5)), message(60, 1, Some(6))]);
+    let mut restarted = message(70, 2, None);
+    restarted.stamp = Some(Stamp { id: 70, timestamp: 10_000, seq: 1 });
+    dialog.add_message(restarted);
+    assert_eq!(ids(&dialog), [50, 60, 70]);
+}
+
+#[test]
+fn prepend_skips_only_the_same_senders_messages() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.add_message(message(10, 2, Some(2)));
+    dialog.prepend(vec![message(10, 1, Some(1)), message(10, 2, Some(2))]);
+    assert_eq!(dialog.messages.iter().map(|x| (x.id, x.from)).collect::<Vec<_>>(), [(10, 1), (10, 2)]);
+}
+
+#[test]
+fn keeps_pending_messages_last() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    let mut pending = message(99, 1, None);
+    pending.state = MessageState::Pending;
+    dialog.add_message(message(10, 2, Some(1)));
+    dialog.add_message(pending);
+    dialog.add_message(message(20, 2, Some(2)));
+    assert_eq!(ids(&dialog), [10, 20, 99]);
+    dialog.set_stamp(99, stamp(3));
+    assert_eq!(ids(&dialog), [10, 20, 99]);
+    assert_eq!(dialog.messages[2].stamp, Some(stamp(3)));
+}
+
+#[test]
+fn set_stamp_moves_message_into_place() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    let mut pending = message(99, 1, None);
+    pending.state = MessageState::Pending;
+    dialog.add_message(pending);
+    dialog.add_message(message(10, 2, Some(1)));
+    dialog.add_message(message(30, 2, Some(3)));
+    dialog.set_stamp(99, stamp(2));
+    assert_eq!(ids(&dialog), [10, 99, 30]);
+}
+
+#[test]
+fn skips_duplicates() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.add_message(message(10, 2, Some(1)));
+    dialog.add_message(message(10, 2, Some(1)));
+    // the same random id from someone else is another message
+    dialog.add_message(message(10, 1, Some(2)));
+    assert_eq!(dialog.messages.len(), 2);
+}
+
+#[test]
+fn counts_missing_messages() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    assert_eq!(dialog.missing(), 0);
+    dialog.see(4);
+    assert_eq!(dialog.missing(), 0);
+    dialog.see(7).see(5);
+    assert_eq!(dialog.missing(), 1);
+    dialog.see(6);
+    assert_eq!(dialog.missing(), 0);
+}
+
+#[test]
+fn reactions_leave_the_dialog_alone() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.add_message(message(10, 1, Some(1)));
+    dialog.add_unchecked();
+    dialog.react(2, 1, 10, Some("👍".to_string()));
+    assert_eq!(dialog.unchecked_count, 1);
+    assert_eq!(dialog.messages[0].reactions.get(&2).map(String::as_str), Some("👍"));
+    assert!(dialog.last_message.as_ref().is_some_and(|x| x.reactions.is_empty()));
+    dialog.react(2, 1, 10, None);
+    assert!(dialog.messages[0].reactions.is_empty());
+}
+
+#[test]
+fn edits_and_deletes_only_the_senders_messages() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.add_message(message(10, 1, Some(1)));
+    dialog.edit_message(2, 10, "forged".to_string());
+    dialog.remove_message(2, 10);
+    assert_eq!(dialog.messages[0].content, "message 10");
+    dialog.edit_message(1, 10, "fixed".to_string());
+    assert!(dialog.messages[0].edited);
+    assert_eq!(dialog.last_message.as_ref().map(|x| x.content.as_str()), Some("fixed"));
+    dialog.remove_message(1, 10);
+    assert!(dialog.messages.is_empty());
+    assert!(dialog.last_message.is_none());
+}
+
+#[test]
+fn inherits_the_conversation() {
+    let mut old = Dialog::new(2, "b2xk".to_string());
+    old.add_message(message(10, 2, Some(1)));
+    old.add_unchecked();
+    old.see(1);
+    old.timer = Some(30);
+    old.is_applied = true;
+    old.online = true;
+    let mut dialog = Dialog::new(2, "bmV3".to_string());
+    dialog.online = false;
+    dialog.inherit(old);
+    assert_eq!(ids(&dialog), [10]);
+    assert_eq!(dialog.unchecked_count, 1);
+    assert_eq!(dialog.timer, Some(30));
+    assert!(dialog.is_applied);
+    assert_eq!(dialog.missing(), 0);
+    assert!(dialog.seen.contains(&1));
+    // who the user is now comes from the new dialog
+    assert_eq!(dialog.key, "bmV3");
+    assert!(!dialog.online);
+}
+
+#[test]
+fn expires_messages() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.timer = Some(10);
+    let mut first = message(10, 2, Some(1));
+    first.expires = dialog.expiry(1_000);
+    let mut second = message(20, 2, Some(2));
+    second.expires = dialog.expiry(5_000);
+    dialog.add_message(first).add_message(second);
+    assert!(!dialog.expire(10_999));
+    assert!(dialog.expire(11_000));
+    assert_eq!(ids(&dialog), [20]);
+}
+
+#[test]
+fn verification_follows_the_safety_number() {
+    let mut dialog = Dialog::new(2, "a2V5".to_string());
+    dialog.set_safety_number(Some("1".to_string()), None);
+    assert_eq!(dialog.verification, Verification::Unverified);
+    dialog.set_safety_number(Some("1".to_string()), Some("1".to_string()));
+    assert_eq!(dialog.verification, Verification::Verified);
+    dialog.set_safety_number(Some("2".to_string()), Some("1".to_string()));
+    assert_eq!(dialog.verification, Verification::Changed);
+}
@@ -0,0 +1,38 @@
+use client_core::fingerprint::{identity, safety_number};
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn both_sides_get_the_same_number() {
+    let mine = keys(&["bXkga2V5", "b3RoZXIgZGV2aWNl"]);
+    let theirs = keys(&["YWxpY2Uga2V5"]);
+    let number = safety_number(&mine, &theirs).unwrap();
+    assert_eq!(safety_number(&theirs, &mine).as_ref(), Some(&number));
+    assert_eq!(number.split(' ').count(), 12);
+    // devices in any order
+    assert_eq!(safety_number(&keys(&["b3RoZXIgZGV2aWNl", "bXkga2V5"]), &theirs).as_ref(), Some(&number));
+}
+
+#[test]
+fn every_device_counts() {
+    let mine = keys(&["bXkga2V5"]);
+    let theirs = keys(&["YWxpY2Uga2V5"]);
+    let number = safety_number(&mine, &theirs);
+    assert_ne!(safety_number(&mine, &keys(&["YWxpY2Uga2V5", "bGFwdG9w"])), number);
+    assert_ne!(safety_number(&keys(&["bXkga2V5", "bGFwdG9w"]), &theirs), number);
+    // moving a device to the other side is a different pair of accounts
+    assert_ne!(safety_number(&keys(&["bXkga2V5", "YWxpY2Uga2V5"]), &keys(&["bGFwdG9w"])), safety_number(&mine, &keys(&["YWxpY2Uga2V5", "bGFwdG9w"])));
+}
+
+#[test]
+fn needs_keys() {
+    assert_eq!(safety_number(&[], &keys(&["YWxpY2Uga2V5"])), None);
+    assert_eq!(safety_number(&keys(&["not base64!"]), &keys(&["YWxpY2Uga2V5"])), None);
+}
+
+#[test]
+fn identity_matches_the_server() {
+    assert_eq!(identity("key"), "2c70e12b7a0646f92279f427c7b38e7334d8e5389cff167a1dc30e73f826b683");
+}
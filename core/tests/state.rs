@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use client_core::{
+    crypto::Job,
+    data::{Stamp, SystemEvent, UserEvent},
+    dialog::Verification,
+    message::MessageState,
+    payload::Payload,
+    state::{Effect, Input, State},
+    user::{SafeDevice, SafeUser},
+};
+
+const ME: usize = 1;
+const ALICE: usize = 2;
+const MY_KEY: &str = "bXkga2V5";
+const ALICE_KEY: &str = "YWxpY2Uga2V5";
+
+fn stamp(seq: u64) -> Stamp {
+    Stamp { id: seq, timestamp: 1_000 * seq, seq }
+}
+
+fn user(id: usize, key: &str, devices: &[(usize, &str)]) -> SafeUser {
+    SafeUser {
+        id,
+        key: key.to_string(),
+        devices: devices.iter().map(|(id, key)| SafeDevice { id: *id, key: key.to_string() }).collect()
+    }
+}
+
+fn text(text: &str) -> Payload {
+    Payload::Text { text: text.to_string(), reply: None }
+}
+
+/// Connected as device 11 of account `ME`, with device 12 linked, and
+/// Alice around on devices 21 and 22.
+fn state() -> State {
+    let mut state = State::new(MY_KEY.to_string());
+    state.handle(Input::Event(SystemEvent::YourDevice(11)));
+    state.handle(Input::Event(SystemEvent::YourId(ME)));
+    state.handle(Input::Event(SystemEvent::GetUsersIds(vec![
+        user(ME, MY_KEY, &[(11, MY_KEY), (12, "b3RoZXIgZGV2aWNl")]),
+        user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]),
+    ])));
+    state
+}
+
+fn receive(state: &mut State, from: usize, random_id: usize, seq: u64, payload: Payload) -> Vec<Effect> {
+    state.handle(Input::Opened { dialog: from, from, random_id, stamp: stamp(seq), payload })
+}
+
+/// Composes `payload` for Alice and feeds back what the backend would seal.
+fn send(state: &mut State, random_id: usize, payload: Payload) -> Vec<Effect> {
+    let effects = state.handle(Input::Compose { to: ALICE, random_id, payload });
+    let (payload, keys) = match effects.into_iter().next() {
+        Some(Effect::Crypto(Job::Seal { payload, keys, .. })) => (payload, keys),
+        other => panic!("expected a seal job, got {:?}", other)
+    };
+    let messages = keys.into_iter().map(|(device, _)| (device, "sealed".to_string())).collect();
+    state.handle(Input::Sealed { to: ALICE, random_id, payload, messages })
+}
+
+#[test]
+fn opens_messages_in_the_right_dialog() {
+    let mut state = state();
+    let effects = state.handle(Input::Event(SystemEvent::Message { from: ALICE, to: ME, message: "x".to_string(), random_id: 5, stamp: stamp(1) }));
+    assert!(matches!(effects.as_slice(), [Effect::Crypto(Job::Open { dialog: ALICE, from: ALICE, random_id: 5, .. })]));
+    // our own message from another device belongs with whoever it went to
+    let effects = state.handle(Input::Event(SystemEvent::Message { from: ME, to: ALICE, message: "x".to_string(), random_id: 6, stamp: stamp(2) }));
+    assert!(matches!(effects.as_slice(), [Effect::Crypto(Job::Open { dialog: ALICE, from: ME, .. })]));
+}
+
+#[test]
+fn ignores_blocked_senders() {
+    let mut state = state();
+    state.handle(Input::Event(SystemEvent::BlockList(vec![ALICE])));
+    let effects = state.handle(Input::Event(SystemEvent::Message { from: ALICE, to: ME, message: "x".to_string(), random_id: 5, stamp: stamp(1) }));
+    assert!(effects.is_empty());
+    state.handle(Input::Event(SystemEvent::ContactRequest(ALICE)));
+    assert!(!state.dialogs[&ALICE].is_request);
+}
+
+#[test]
+fn counts_unread_messages() {
+    let mut state = state();
+    let effects = receive(&mut state, ALICE, 5, 1, text("hi"));
+    assert!(matches!(effects.as_slice(), [Effect::Applied { dialog: ALICE, from: ALICE, random_id: 5, .. }]));
+    receive(&mut state, ALICE, 6, 2, text("there"));
+    let dialog = &state.dialogs[&ALICE];
+    assert_eq!(dialog.unchecked_count, 2);
+    assert!(dialog.is_request);
+    assert_eq!(dialog.last_message.as_ref().map(|x| x.content.as_str()), Some("there"));
+    state.handle(Input::Select(Some(ALICE)));
+    assert_eq!(state.dialogs[&ALICE].unchecked_count, 0);
+    receive(&mut state, ALICE, 7, 3, text("still here"));
+    assert_eq!(state.dialogs[&ALICE].unchecked_count, 0);
+}
+
+#[test]
+fn reactions_are_not_unread() {
+    let mut state = state();
+    receive(&mut state, ALICE, 5, 1, text("hi"));
+    let effects = receive(&mut state, ALICE, 6, 2, Payload::React { id: 5, from: ALICE, emoji: Some("👍".to_string()) });
+    assert!(matches!(effects.as_slice(), [Effect::Applied { payload: Payload::React { .. }, .. }]));
+    let dialog = &state.dialogs[&ALICE];
+    assert_eq!(dialog.unchecked_count, 1);
+    assert_eq!(dialog.messages.len(), 1);
+    assert_eq!(dialog.messages[0].reactions.get(&ALICE).map(String::as_str), Some("👍"));
+    // the control message still takes up its sequence number
+    assert_eq!(dialog.missing(), 0);
+}
+
+#[test]
+fn skips_resent_messages() {
+    let mut state = state();
+    receive(&mut state, ALICE, 5, 1, text("hi"));
+    let effects = receive(&mut state, ALICE, 5, 2, text("hi"));
+    assert!(effects.is_empty());
+    assert_eq!(state.dialogs[&ALICE].messages.len(), 1);
+    assert_eq!(state.dialogs[&ALICE].unchecked_count, 1);
+}
+
+#[test]
+fn orders_by_seq_and_notices_gaps() {
+    let mut state = state();
+    receive(&mut state, ALICE, 7, 4, text("last"));
+    receive(&mut state, ALICE, 5, 1, text("first"));
+    receive(&mut state, ALICE, 6, 2, text("second"));
+    let dialog = &state.dialogs[&ALICE];
+    let ids: Vec<usize> = dialog.messages.iter().map(|x| x.id).collect();
+    assert_eq!(ids, [5, 6, 7]);
+    assert_eq!(dialog.missing(), 1);
+}
+
+#[test]
+fn seals_for_their_devices_and_our_other_ones() {
+    let mut state = state();
+    let effects = state.handle(Input::Compose { to: ALICE, random_id: 5, payload: text("hi") });
+    let mut keys = match effects.as_slice() {
+        [Effect::Crypto(Job::Seal { to: ALICE, random_id: 5, keys, .. })] => keys.clone(),
+        other => panic!("expected a seal job, got {:?}", other)
+    };
+    keys.sort();
+    let devices: Vec<usize> = keys.iter().map(|(device, _)| *device).collect();
+    assert_eq!(devices, [12, 21, 22]);
+    assert!(state.handle(Input::Compose { to: 99, random_id: 6, payload: text("hi") }).is_empty());
+}
+
+#[test]
+fn sends_what_was_sealed() {
+    let mut state = state();
+    state.handle(Input::Tick(10_000));
+    let effects = send(&mut state, 5, text("hi"));
+    assert!(matches!(effects.as_slice(), [
+        Effect::Send(UserEvent::AcceptContact(ALICE)),
+        Effect::Send(UserEvent::Message { to: ALICE, random_id: 5, ttl: None, .. }),
+        Effect::Applied { dialog: ALICE, from: ME, random_id: 5, .. }
+    ]));
+    let dialog = &state.dialogs[&ALICE];
+    assert!(dialog.is_applied);
+    assert_eq!(dialog.messages[0].state, MessageState::Pending);
+    assert_eq!(dialog.unchecked_count, 0);
+    // accepted once, the contact isn't accepted again
+    let effects = send(&mut state, 6, text("again"));
+    assert!(matches!(effects.as_slice(), [Effect::Send(UserEvent::Message { .. }), Effect::Applied { .. }]));
+}
+
+#[test]
+fn timers_set_the_ttl() {
+    let mut state = state();
+    state.handle(Input::Tick(10_000));
+    send(&mut state, 5, Payload::Timer { seconds: Some(60) });
+    assert_eq!(state.dialogs[&ALICE].timer, Some(60));
+    let effects = send(&mut state, 6, text("hi"));
+    assert!(effects.iter().any(|x| matches!(x, Effect::Send(UserEvent::Message { ttl: Some(60), .. }))));
+    assert_eq!(state.dialogs[&ALICE].messages[0].expires, Some(70_000));
+    state.handle(Input::Tick(70_000));
+    assert!(state.dialogs[&ALICE].messages.is_empty());
+}
+
+#[test]
+fn delivery_reports_settle_pending_messages() {
+    let mut state = state();
+    receive(&mut state, ALICE, 4, 1, text("hi"));
+    send(&mut state, 5, text("one"));
+    send(&mut state, 6, text("two"));
+    state.handle(Input::Event(SystemEvent::MessageStatus { random_id: 6, status: true, stamp: Some(stamp(2)) }));
+    state.handle(Input::Event(SystemEvent::MessageStatus { random_id: 5, status: false, stamp: None }));
+    let dialog = &state.dialogs[&ALICE];
+    let states: Vec<(usize, MessageState)> = dialog.messages.iter().map(|x| (x.id, x.state)).collect();
+    assert_eq!(states, [(4, MessageState::Sent), (6, MessageState::Sent), (5, MessageState::Failed)]);
+    assert_eq!(dialog.messages[1].stamp, Some(stamp(2)));
+}
+
+#[test]
+fn sends_nothing_before_knowing_who_we_are() {
+    let mut state = State::new(MY_KEY.to_string());
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[]))));
+    let effects = state.handle(Input::Sealed { to: ALICE, random_id: 5, payload: text("hi"), messages: HashMap::new() });
+    assert!(effects.is_empty());
+    assert!(state.dialogs[&ALICE].messages.is_empty());
+    // and sends it once the server says
+    let effects = state.handle(Input::Event(SystemEvent::YourId(ME)));
+    assert!(effects.iter().any(|x| matches!(x, Effect::Send(UserEvent::Message { to: ALICE, random_id: 5, .. }))));
+    let dialog = &state.dialogs[&ALICE];
+    assert_eq!(dialog.messages.len(), 1);
+    assert_eq!((dialog.messages[0].from, dialog.messages[0].state), (ME, MessageState::Pending));
+    assert!(state.unsent.is_empty());
+}
+
+#[test]
+fn keeps_the_conversation_when_users_come_back() {
+    let mut state = state();
+    receive(&mut state, ALICE, 5, 1, text("hi"));
+    state.handle(Input::Event(SystemEvent::UserOut(user(ALICE, ALICE_KEY, &[]))));
+    assert!(!state.dialogs[&ALICE].online);
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY)]))));
+    let dialog = &state.dialogs[&ALICE];
+    assert!(dialog.online);
+    assert_eq!(dialog.messages.len(), 1);
+    assert_eq!(dialog.unchecked_count, 1);
+    assert!(!dialog.key_changed);
+    assert!(dialog.safety_number.is_some());
+}
+
+#[test]
+fn notices_a_new_key() {
+    let mut state = state();
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, "b3RoZXIga2V5", &[]))));
+    assert!(state.dialogs[&ALICE].key_changed);
+}
+
+/// The devices a compose would seal for, sorted.
+fn devices(effects: &[Effect]) -> Vec<usize> {
+    let mut devices: Vec<usize> = match effects {
+        [Effect::Crypto(Job::Seal { keys, .. })] => keys.iter().map(|(device, _)| *device).collect(),
+        _ => vec![]
+    };
+    devices.sort();
+    devices
+}
+
+#[test]
+fn pins_the_devices_first_seen() {
+    let mut state = State::new(MY_KEY.to_string());
+    state.handle(Input::Event(SystemEvent::YourId(ME)));
+    let effects = state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(22, "cGhvbmU="), (21, ALICE_KEY)]))));
+    let trust = match effects.as_slice() {
+        [Effect::Trust(trust)] => trust.clone(),
+        other => panic!("expected the trust to be saved, got {:?}", other)
+    };
+    let identity = &state.dialogs[&ALICE].identity;
+    assert_eq!(trust.pinned[identity], ["YWxpY2Uga2V5", "cGhvbmU="]);
+    // coming back as they were changes nothing
+    let effects = state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]))));
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn new_devices_wait_for_acceptance() {
+    let mut state = state();
+    let before = state.dialogs[&ALICE].safety_number.clone();
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU="), (23, "bGFwdG9w")]))));
+    let dialog = &state.dialogs[&ALICE];
+    assert!(dialog.key_changed);
+    assert_ne!(dialog.safety_number, before);
+    assert!(state.handle(Input::Compose { to: ALICE, random_id: 5, payload: text("hi") }).is_empty());
+    let effects = state.handle(Input::AcceptKeys(ALICE));
+    assert!(matches!(effects.as_slice(), [Effect::Trust(_)]));
+    assert!(!state.dialogs[&ALICE].key_changed);
+    let effects = state.handle(Input::Compose { to: ALICE, random_id: 5, payload: text("hi") });
+    assert_eq!(devices(&effects), [12, 21, 22, 23]);
+}
+
+#[test]
+fn removed_devices_are_forgotten() {
+    let mut state = state();
+    let effects = state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY)]))));
+    assert!(matches!(effects.as_slice(), [Effect::Trust(_)]));
+    assert!(!state.dialogs[&ALICE].key_changed);
+    // so the device coming back has to be accepted again
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]))));
+    assert!(state.dialogs[&ALICE].key_changed);
+}
+
+#[test]
+fn our_new_devices_get_nothing_until_accepted() {
+    let mut state = state();
+    let before = state.dialogs[&ALICE].safety_number.clone();
+    state.handle(Input::Event(SystemEvent::UserIn(user(ME, MY_KEY, &[(11, MY_KEY), (12, "b3RoZXIgZGV2aWNl"), (13, "c3RyYW5nZXI=")]))));
+    assert!(state.dialogs[&ME].key_changed);
+    // every safety number covers our devices too
+    assert_ne!(state.dialogs[&ALICE].safety_number, before);
+    let effects = state.handle(Input::Compose { to: ALICE, random_id: 5, payload: text("hi") });
+    assert_eq!(devices(&effects), [12, 21, 22]);
+    state.handle(Input::AcceptKeys(ME));
+    let effects = state.handle(Input::Compose { to: ALICE, random_id: 6, payload: text("hi") });
+    assert_eq!(devices(&effects), [12, 13, 21, 22]);
+}
+
+#[test]
+fn keys_stay_pinned_across_runs() {
+    let trust = state().trust;
+    let mut state = State { trust, ..State::new(MY_KEY.to_string()) };
+    state.handle(Input::Event(SystemEvent::YourId(7)));
+    // Alice under another id, with a device we never saw
+    state.handle(Input::Event(SystemEvent::UserIn(user(9, ALICE_KEY, &[(21, ALICE_KEY), (23, "bGFwdG9w")]))));
+    assert!(state.dialogs[&9].key_changed);
+}
+
+#[test]
+fn ids_handed_out_again_are_strangers() {
+    let mut state = state();
+    receive(&mut state, ALICE, 5, 1, text("hi"));
+    // the server restarted and gave Alice's old id to someone else
+    state.handle(Input::Event(SystemEvent::YourId(ME)));
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, "b3RoZXIga2V5", &[]))));
+    let dialog = &state.dialogs[&ALICE];
+    assert!(!dialog.key_changed);
+    assert!(dialog.messages.is_empty());
+    assert!(!dialog.stale);
+}
+
+#[test]
+fn known_users_keep_their_dialog_after_a_reconnect() {
+    let mut state = state();
+    receive(&mut state, ALICE, 5, 1, text("hi"));
+    state.handle(Input::Event(SystemEvent::YourId(ME)));
+    assert!(state.dialogs[&ALICE].stale);
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]))));
+    let dialog = &state.dialogs[&ALICE];
+    assert!(!dialog.stale);
+    assert_eq!(dialog.messages.len(), 1);
+}
+
+#[test]
+fn verification_and_timers_follow_the_account() {
+    let mut state = state();
+    let effects = state.handle(Input::Verify(ALICE));
+    assert!(matches!(effects.as_slice(), [Effect::Trust(_)]));
+    let effects = receive(&mut state, ALICE, 5, 1, Payload::Timer { seconds: Some(60) });
+    assert!(matches!(effects.as_slice(), [Effect::Trust(trust), Effect::Applied { .. }] if trust.timers.values().eq([&60])));
+    // next run, with Alice under another id
+    let mut state = State { trust: state.trust, ..State::new(MY_KEY.to_string()) };
+    state.handle(Input::Event(SystemEvent::YourId(7)));
+    state.handle(Input::Event(SystemEvent::GetUsersIds(vec![
+        user(7, MY_KEY, &[(11, MY_KEY), (12, "b3RoZXIgZGV2aWNl")]),
+        user(9, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]),
+    ])));
+    let dialog = &state.dialogs[&9];
+    assert_eq!(dialog.verification, Verification::Verified);
+    assert_eq!(dialog.timer, Some(60));
+    // and someone else on Alice's old id gets neither
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, "b3RoZXIga2V5", &[]))));
+    let dialog = &state.dialogs[&ALICE];
+    assert_eq!(dialog.verification, Verification::Unverified);
+    assert_eq!(dialog.timer, None);
+}
+
+#[test]
+fn new_devices_unverify() {
+    let mut state = state();
+    state.handle(Input::Verify(ALICE));
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (23, "bGFwdG9w")]))));
+    assert_eq!(state.dialogs[&ALICE].verification, Verification::Changed);
+}
+
+#[test]
+fn finds_accounts_under_their_new_id() {
+    let mut state = state();
+    let identity = state.dialogs[&ALICE].identity.clone();
+    assert_eq!(state.find(&identity), Some(ALICE));
+    state.handle(Input::Event(SystemEvent::YourId(ME)));
+    // not until they're back, the id may be someone else's by now
+    assert_eq!(state.find(&identity), None);
+    state.handle(Input::Event(SystemEvent::UserIn(user(9, ALICE_KEY, &[(21, ALICE_KEY), (22, "cGhvbmU=")]))));
+    assert_eq!(state.find(&identity), Some(9));
+    let mut keys: Vec<usize> = state.recipients(9).unwrap().into_iter().map(|(device, _)| device).collect();
+    keys.sort();
+    assert_eq!(keys, [12, 21, 22]);
+}
+
+#[test]
+fn nobody_gets_sealed_for_while_keys_wait() {
+    let mut state = state();
+    state.handle(Input::Event(SystemEvent::UserIn(user(ALICE, ALICE_KEY, &[(21, ALICE_KEY), (23, "bGFwdG9w")]))));
+    assert_eq!(state.recipients(ALICE), None);
+}
+
+#[test]
+fn answers_key_challenges() {
+    let mut state = state();
+    let effects = state.handle(Input::Event(SystemEvent::Challenge("c2VhbGVk".to_string())));
+    assert!(matches!(effects.as_slice(), [Effect::Crypto(Job::Answer { challenge })] if challenge == "c2VhbGVk"));
+    let effects = state.handle(Input::Answered("bm9uY2U=".to_string()));
+    assert!(matches!(effects.as_slice(), [Effect::Send(UserEvent::Prove(nonce))] if nonce == "bm9uY2U="));
+}
@@ -0,0 +1,48 @@
+//! The native backend against keys and envelopes made by WebCrypto, which
+//! `vectors/generate.mjs` produces the way the web client does.
+#![cfg(feature = "native")]
+
+use client_core::native::{import_public, seal, Identity};
+
+const SPKI: &str = include_str!("vectors/spki.b64");
+const PKCS8: &str = include_str!("vectors/pkcs8.pem");
+const PLAIN: &str = include_str!("vectors/plain.txt");
+const ENVELOPE: &str = include_str!("vectors/envelope.b64");
+const LEGACY: &str = include_str!("vectors/legacy.b64");
+
+fn identity() -> Identity {
+    Identity::from_pem(PKCS8).unwrap()
+}
+
+#[test]
+fn exports_the_same_spki() {
+    assert_eq!(identity().public_key(), SPKI.trim());
+}
+
+#[test]
+fn opens_webcrypto_envelope() {
+    let envelope = base64::decode(ENVELOPE.trim()).unwrap();
+    assert_eq!(identity().open(&envelope).unwrap(), PLAIN.as_bytes());
+}
+
+#[test]
+fn opens_pure_rsa_message() {
+    let message = base64::decode(LEGACY.trim()).unwrap();
+    assert_eq!(message.len(), 256);
+    assert_eq!(identity().open(&message).unwrap(), PLAIN.as_bytes());
+}
+
+#[test]
+fn seals_for_webcrypto_key() {
+    let envelope = seal(&import_public(SPKI.trim()).unwrap(), PLAIN.as_bytes()).unwrap();
+    assert_eq!(envelope.len(), 256 + 12 + PLAIN.len() + 16);
+    assert_eq!(identity().open(&envelope).unwrap(), PLAIN.as_bytes());
+}
+
+#[test]
+fn rejects_tampered_envelope() {
+    let mut envelope = base64::decode(ENVELOPE.trim()).unwrap();
+    *envelope.last_mut().unwrap() ^= 1;
+    assert!(identity().open(&envelope).is_err());
+    assert!(identity().open(&envelope[..200]).is_err());
+}
@@ -0,0 +1,40 @@
+//! Answers every message with the same text and logs who comes and goes.
+//!
+//! ```text
+//! CHAT_SERVER=ws://127.0.0.1:8081/chat cargo run --example echo
+//! ```
+
+use std::{env, path::PathBuf};
+use chat_sdk::{keys, payload::Payload, Bot, Client, Error, Handler, Incoming};
+
+struct Echo;
+
+impl Handler for Echo {
+    async fn on_message(&mut self, client: &mut Client, message: Incoming) -> Result<(), Error> {
+        // a device added to their account could be anyone's, so keep quiet
+        if client.state.dialogs.get(&message.from).is_some_and(|x| x.key_changed) {
+            eprintln!("#{} has new keys, not answering", message.from);
+            return Ok(());
+        }
+        if let Some(text) = message.text() {
+            client.send_payload(message.from, Payload::Text { text: text.to_string(), reply: None }).await?;
+        }
+        Ok(())
+    }
+    async fn on_user_in(&mut self, _client: &mut Client, user: usize) -> Result<(), Error> {
+        eprintln!("#{} is online", user);
+        Ok(())
+    }
+    async fn on_user_out(&mut self, _client: &mut Client, user: usize) -> Result<(), Error> {
+        eprintln!("#{} went offline", user);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let url = env::var("CHAT_SERVER").unwrap_or_else(|_| "ws://127.0.0.1:8081/chat".to_string());
+    let key = env::var("CHAT_KEY").unwrap_or_else(|_| "echo-key.pem".to_string());
+    let identity = keys::load_or_create(&PathBuf::from(key))?;
+    Bot::new(&url, identity).run(&mut Echo).await
+}
@@ -0,0 +1,86 @@
+use std::{future::Future, time::Duration};
+use client_core::{data::{SystemEvent, UserEvent}, payload::Payload};
+use crate::{client::{Client, Update}, Error, Identity};
+
+/// How long to wait before trying a dropped connection again.
+const RETRY: Duration = Duration::from_secs(3);
+
+/// A message someone else sent the bot, already opened.
+#[derive(Clone, Debug)]
+pub struct Incoming {
+    pub from: usize,
+    pub random_id: usize,
+    pub payload: Payload,
+}
+
+impl Incoming {
+    /// The text of a plain message, `None` for files, edits and the like.
+    pub fn text(&self) -> Option<&str> {
+        match &self.payload {
+            Payload::Text { text, .. } => Some(text),
+            _ => None
+        }
+    }
+}
+
+/// What a bot does about things happening on the server. Every call gets
+/// the connection to answer through; an error ends `Bot::run`.
+pub trait Handler {
+    fn on_message(&mut self, client: &mut Client, message: Incoming) -> impl Future<Output = Result<(), Error>>;
+    /// `user` connected, or came back with another key.
+    fn on_user_in(&mut self, client: &mut Client, user: usize) -> impl Future<Output = Result<(), Error>> {
+        let _ = (client, user);
+        async { Ok(()) }
+    }
+    fn on_user_out(&mut self, client: &mut Client, user: usize) -> impl Future<Output = Result<(), Error>> {
+        let _ = (client, user);
+        async { Ok(()) }
+    }
+}
+
+/// Runs a `Handler` as a participant of its own, reconnecting whenever the
+/// connection drops.
+pub struct Bot {
+    url: String,
+    identity: Identity,
+    accept_requests: bool,
+}
+
+impl Bot {
+    pub fn new(url: &str, identity: Identity) -> Self {
+        Self { url: url.to_string(), identity, accept_requests: true }
+    }
+    /// Whether to accept contact requests right away, on by default. Off,
+    /// a bot only hears from people it wrote to first.
+    pub fn accept_requests(mut self, accept: bool) -> Self {
+        self.accept_requests = accept;
+        self
+    }
+    pub async fn run<H: Handler>(&self, handler: &mut H) -> Result<(), Error> {
+        loop {
+            let mut client = match Client::connect(&self.url, self.identity.clone()).await {
+                Ok(client) => client,
+                Err(_) => {
+                    tokio::time::sleep(RETRY).await;
+                    continue;
+                }
+            };
+            while let Ok(Some(update)) = client.recv().await {
+                self.dispatch(&mut client, handler, update).await?;
+            }
+            tokio::time::sleep(RETRY).await;
+        }
+    }
+    async fn dispatch<H: Handler>(&self, client: &mut Client, handler: &mut H, update: Update) -> Result<(), Error> {
+        let me = client.my_id();
+        match update {
+            // our own messages, from other devices of the bot's account
+            Update::Message { from, .. } if Some(from) == me => Ok(()),
+            Update::Message { from, random_id, payload, .. } => handler.on_message(client, Incoming { from, random_id, payload }).await,
+            Update::Event(SystemEvent::ContactRequest(from)) if self.accept_requests => client.send(UserEvent::AcceptContact(from)).await,
+            Update::Event(SystemEvent::UserIn(user)) if Some(user.id) != me => handler.on_user_in(client, user.id).await,
+            Update::Event(SystemEvent::UserOut(user)) if Some(user.id) != me => handler.on_user_out(client, user.id).await,
+            Update::Event(_) => Ok(())
+        }
+    }
+}
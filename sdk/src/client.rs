@@ -0,0 +1,165 @@
+use std::{collections::VecDeque, time::{SystemTime, UNIX_EPOCH}};
+use client_core::{
+    data::{SystemEvent, UserEvent},
+    payload::Payload,
+    state::{Effect, Input, State},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use crate::{Error, Identity};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How many users to ask for when connecting; the server has no paging cursor.
+const DIRECTORY: usize = 1000;
+
+/// What came out of the connection, already applied to the state.
+pub enum Update {
+    Event(SystemEvent),
+    /// A message of `dialog`, opened for this device.
+    Message { dialog: usize, from: usize, random_id: usize, payload: Payload },
+}
+
+/// A connection to the server driving a `client_core` state machine.
+pub struct Client {
+    pub identity: Identity,
+    pub state: State,
+    /// Events that came in while connecting, for `recv` to hand out.
+    pending: VecDeque<SystemEvent>,
+    socket: Socket,
+}
+
+impl Client {
+    /// Connects, announces our key and waits for the list of users.
+    pub async fn connect(url: &str, identity: Identity) -> Result<Self, Error> {
+        let state = State::new(identity.public_key());
+        Self::resume(url, identity, state).await
+    }
+    /// Like `connect`, picking up the dialogs of an earlier connection.
+    pub async fn resume(url: &str, identity: Identity, state: State) -> Result<Self, Error> {
+        let (socket, _) = connect_async(url).await?;
+        let mut client = Self {
+            state,
+            identity,
+            pending: VecDeque::new(),
+            socket,
+        };
+        client.send(UserEvent::PublicKey(client.identity.public_key())).await?;
+        client.send(UserEvent::GetUsersIds { start: 0, count: DIRECTORY }).await?;
+        loop {
+            let event = match client.read().await? {
+                Some(event) => event,
+                None => return Err("the server closed the connection".into())
+            };
+            if let SystemEvent::Message { .. } | SystemEvent::ContactRequest(_) = event {
+                client.pending.push_back(event);
+                continue;
+            }
+            let ready = matches!(event, SystemEvent::GetUsersIds(_));
+            let effects = client.state.handle(Input::Event(event));
+            client.apply(effects).await?;
+            if ready && client.state.my_id.is_some() {
+                break;
+            }
+        }
+        Ok(client)
+    }
+    pub fn my_id(&self) -> Option<usize> {
+        self.state.my_id
+    }
+    pub async fn send(&mut self, event: UserEvent) -> Result<(), Error> {
+        self.socket.send(Message::Text(json!(event).to_string())).await?;
+        Ok(())
+    }
+    /// The next event from the server, without applying it.
+    async fn read(&mut self) -> Result<Option<SystemEvent>, Error> {
+        loop {
+            let text = match self.socket.next().await {
+                Some(message) => match message? {
+                    Message::Text(text) => text,
+                    Message::Close(_) => return Ok(None),
+                    _ => continue
+                },
+                None => return Ok(None)
+            };
+            if let Ok(event) = serde_json::from_str::<SystemEvent>(&text) {
+                return Ok(Some(event));
+            }
+        }
+    }
+    /// The next update, `None` once the connection is gone. Safe to cancel,
+    /// nothing is lost if another branch of a `select!` wins.
+    pub async fn recv(&mut self) -> Result<Option<Update>, Error> {
+        loop {
+            self.tick();
+            let event = match self.pending.pop_front() {
+                Some(event) => event,
+                None => match self.read().await? {
+                    Some(event) => event,
+                    None => return Ok(None)
+                }
+            };
+            let effects = self.state.handle(Input::Event(event.clone()));
+            if !matches!(event, SystemEvent::Message { .. }) {
+                self.apply(effects).await?;
+                return Ok(Some(Update::Event(event)));
+            }
+            for effect in effects {
+                let opened = match effect {
+                    Effect::Crypto(job) => job.perform(&self.identity).await,
+                    Effect::Send(_) | Effect::Applied { .. } | Effect::Trust(_) => continue
+                };
+                if let Ok(Input::Opened { dialog, from, random_id, stamp, payload }) = opened {
+                    let effects = self.state.handle(Input::Opened { dialog, from, random_id, stamp, payload: payload.clone() });
+                    self.apply(effects).await?;
+                    return Ok(Some(Update::Message { dialog, from, random_id, payload }));
+                }
+            }
+        }
+    }
+    /// Carries out effects, and whatever effects they lead to in turn.
+    async fn apply(&mut self, effects: Vec<Effect>) -> Result<(), Error> {
+        let mut queue = VecDeque::from(effects);
+        while let Some(effect) = queue.pop_front() {
+            match effect {
+                Effect::Send(event) => self.send(event).await?,
+                Effect::Crypto(job) => {
+                    let input = job.perform(&self.identity).await?;
+                    queue.extend(self.state.handle(input));
+                }
+                Effect::Applied { .. } | Effect::Trust(_) => ()
+            }
+        }
+        Ok(())
+    }
+    /// Lets the state know the time, for disappearing messages.
+    fn tick(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis() as u64);
+        self.state.handle(Input::Tick(now));
+    }
+    pub async fn close(mut self) {
+        let _ = self.socket.close(None).await;
+    }
+    /// Accepts the device keys `user` has now. Until then, once they
+    /// changed, `send_payload` refuses to write to them.
+    pub fn accept_keys(&mut self, user: usize) {
+        self.state.handle(Input::AcceptKeys(user));
+    }
+    /// Seals `payload` for every device of `to` and our other devices, and
+    /// sends it. Returns the random id the delivery report will carry.
+    pub async fn send_payload(&mut self, to: usize, payload: Payload) -> Result<usize, Error> {
+        match self.state.dialogs.get(&to) {
+            None => return Err(format!("user #{} is not around", to).into()),
+            Some(dialog) if dialog.key_changed => return Err(format!("the keys of #{} changed, accept them first", to).into()),
+            Some(_) => ()
+        }
+        self.tick();
+        let random_id = client_core::random_id();
+        let effects = self.state.handle(Input::Compose { to, random_id, payload });
+        self.apply(effects).await?;
+        Ok(random_id)
+    }
+}
+
@@ -0,0 +1,34 @@
+use std::{fs::{self, OpenOptions}, io::{self, Write}, path::Path};
+use crate::{Error, Identity};
+
+/// Loads the identity kept as PKCS#8 PEM at `path`, creating it on first use.
+///
+/// A new file is readable by its owner only, and is never written over:
+/// if another process created it first, this fails rather than replace it.
+/// It is written aside and linked into place whole, so a failed write
+/// can't leave a broken key behind.
+pub fn load_or_create(path: &Path) -> Result<Identity, Error> {
+    if path.exists() {
+        return Identity::from_pem(&fs::read_to_string(path)?);
+    }
+    let identity = Identity::generate()?;
+    let temp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let created = write_new(&temp, identity.to_pem()?.as_bytes())
+        .and_then(|_| fs::hard_link(&temp, path));
+    let _ = fs::remove_file(&temp);
+    created?;
+    Ok(identity)
+}
+
+fn write_new(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
@@ -0,0 +1,12 @@
+//! Native clients and bots for the chat server: a connection that keeps a
+//! `client_core` state and does the crypto with the pure-Rust backend, key
+//! files, and a runner that drives a `Handler` for automated participants.
+
+pub mod bot;
+pub mod client;
+pub mod keys;
+
+pub use bot::{Bot, Handler, Incoming};
+pub use client::{Client, Update};
+pub use client_core::native::{Error, Identity};
+pub use client_core::{data, payload};
@@ -0,0 +1,101 @@
+use std::{net::TcpListener, path::PathBuf, sync::mpsc, thread, time::Duration};
+use chat_sdk::{data::SystemEvent, payload::Payload, Bot, Client, Error, Handler, Identity, Incoming, Update};
+use chat_server::config::Config;
+
+const ALICE: &str = include_str!("keys/alice.pem");
+const BOT: &str = include_str!("keys/bot.pem");
+
+/// Answers every text with the same text.
+struct Echo;
+
+impl Handler for Echo {
+    async fn on_message(&mut self, client: &mut Client, message: Incoming) -> Result<(), Error> {
+        if let Some(text) = message.text() {
+            client.send_payload(message.from, Payload::Text { text: text.to_string(), reply: None }).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Starts a server on a free port with its files under a scratch directory,
+/// returning its socket URL. It runs until the test process ends.
+fn serve(name: &str) -> String {
+    let dir: PathBuf = std::env::temp_dir().join(format!("chat-sdk-{}-{}", name, std::process::id()));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/chat", listener.local_addr().unwrap());
+    // not from the environment, whose CHAT_* variables would change the test
+    let config = Config {
+        address: listener.local_addr().unwrap().to_string(),
+        contacts_only: false,
+        resume_grace: Duration::from_secs(300),
+        link_ttl: Duration::from_secs(300),
+        blob_dir: dir.join("blobs"),
+        data_dir: dir.join("data"),
+        blob_max: 1024 * 1024,
+        blob_quota: 4 * 1024 * 1024,
+        blob_ttl: Duration::from_secs(60),
+        api_idle: Duration::from_secs(60),
+        api_queue: 100,
+        web_dir: None,
+        socket_url: None,
+    };
+    let (started, wait) = mpsc::channel();
+    thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            let server = chat_server::run(config, listener).unwrap();
+            started.send(()).unwrap();
+            server.await
+        })
+    });
+    wait.recv().unwrap();
+    url
+}
+
+/// Reads updates until `found` picks one.
+async fn wait_for<T>(client: &mut Client, mut found: impl FnMut(Update) -> Option<T>) -> T {
+    loop {
+        match client.recv().await.unwrap() {
+            Some(update) => if let Some(x) = found(update) {
+                return x;
+            },
+            None => panic!("the server closed the connection")
+        }
+    }
+}
+
+#[tokio::test]
+async fn bots_answer_through_the_server() {
+    let url = serve("echo");
+    let bot = Identity::from_pem(BOT).unwrap();
+    let bot_key = bot.public_key();
+    let mut alice = Client::connect(&url, Identity::from_pem(ALICE).unwrap()).await.unwrap();
+    let conversation = async {
+        let bot = wait_for(&mut alice, |update| match update {
+            Update::Event(SystemEvent::UserIn(user)) if user.key == bot_key => Some(user.id),
+            _ => None
+        }).await;
+        let sent = alice.send_payload(bot, Payload::Text { text: "ping".to_string(), reply: None }).await.unwrap();
+        let delivered = wait_for(&mut alice, |update| match update {
+            Update::Event(SystemEvent::MessageStatus { random_id, status, .. }) if random_id == sent => Some(status),
+            _ => None
+        }).await;
+        assert!(delivered);
+        wait_for(&mut alice, |update| match update {
+            Update::Message { from, payload: Payload::Text { text, .. }, .. } if from == bot => Some(text),
+            _ => None
+        }).await
+    };
+    let bot = Bot::new(&url, bot);
+    let mut echo = Echo;
+    let answer = tokio::time::timeout(Duration::from_secs(30), async {
+        tokio::select! {
+            result = bot.run(&mut echo) => panic!("the bot stopped: {:?}", result.err().map(|e| e.to_string())),
+            answer = conversation => answer
+        }
+    }).await.expect("no answer in time");
+    assert_eq!(answer, "ping");
+    let me = alice.my_id();
+    let dialog = alice.state.dialogs.values().find(|x| x.key == bot_key).unwrap();
+    let messages: Vec<(bool, &str)> = dialog.messages.iter().map(|x| (Some(x.from) == me, x.content.as_str())).collect();
+    assert_eq!(messages, [(true, "ping"), (false, "ping")]);
+}
@@ -0,0 +1,260 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message, MessageResult, Running};
+use actix_web::{delete, get, post, web::{Data, Json, Path, Query, ServiceConfig}, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use crate::{config::Config, data::{Drained, IConnect, IDisconnect, Lookup, Post, RawUserEvent, SystemEvent}, server::Server, transport::{self, next_id}};
+
+const IDLE_CHECK: Duration = Duration::from_secs(30);
+/// How many users `GET /api/users` lists when not told otherwise.
+const USERS: usize = 1000;
+
+/// REST identities, by the bearer token that acts as them.
+pub type Tokens = Arc<Mutex<HashMap<String, Addr<Mailbox>>>>;
+
+/// A device without a connection. It registers with `Server` like a socket
+/// `Session` does, and keeps what the server sends until the REST client
+/// fetches it.
+pub struct Mailbox {
+    pub id: usize,
+    pub addr: Addr<Server>,
+    token: String,
+    tokens: Tokens,
+    queue: VecDeque<SystemEvent>,
+    limit: usize,
+    idle: Duration,
+    last_poll: Instant,
+}
+
+impl Actor for Mailbox {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(IDLE_CHECK, |act, ctx| {
+            if act.last_poll.elapsed() > act.idle {
+                ctx.stop();
+            }
+        });
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.tokens.lock().unwrap().remove(&self.token);
+        self.addr.do_send(IDisconnect { id: self.id, addr: ctx.address().recipient() });
+        Running::Stop
+    }
+}
+
+impl Handler<SystemEvent> for Mailbox {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, _: &mut Self::Context) {
+        match msg {
+            SystemEvent::SetKey(_) => return,
+            SystemEvent::YourDevice(id) => self.id = id,
+            _ => ()
+        }
+        transport::enqueue(&mut self.queue, msg, self.limit);
+    }
+}
+
+/// Everything waiting, oldest first.
+#[derive(Message)]
+#[rtype(result = "Vec<SystemEvent>")]
+struct Drain;
+
+impl Handler<Drain> for Mailbox {
+    type Result = MessageResult<Drain>;
+
+    fn handle(&mut self, _: Drain, ctx: &mut Self::Context) -> Self::Result {
+        self.last_poll = Instant::now();
+        self.addr.do_send(Drained { id: self.id, addr: ctx.address().recipient() });
+        MessageResult(self.queue.drain(..).collect())
+    }
+}
+
+/// The device the mailbox stands for, which a `Resume` can change.
+#[derive(Message)]
+#[rtype(result = "usize")]
+struct DeviceId;
+
+impl Handler<DeviceId> for Mailbox {
+    type Result = usize;
+
+    fn handle(&mut self, _: DeviceId, _: &mut Self::Context) -> usize {
+        self.last_poll = Instant::now();
+        self.id
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Close;
+
+impl Handler<Close> for Mailbox {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        ctx.stop();
+    }
+}
+
+fn mailbox(req: &HttpRequest, tokens: &Tokens) -> Option<Addr<Mailbox>> {
+    let token = req.headers().get("Authorization")?.to_str().ok()?.strip_prefix("Bearer ")?;
+    tokens.lock().unwrap().get(token).cloned()
+}
+
+#[derive(Deserialize)]
+struct NewIdentity {
+    /// Base64 SPKI, as a socket client sends it in `PublicKey`.
+    key: String,
+}
+
+/// Creates an identity holding `key` and answers with the bearer token for
+/// the other endpoints. It counts as online until it goes unpolled for
+/// `CHAT_API_IDLE` or is deleted.
+#[post("/api/identities")]
+async fn create_identity(body: Json<NewIdentity>, srv: Data<Addr<Server>>, counter: Data<Arc<Mutex<usize>>>, tokens: Data<Tokens>, config: Data<Config>) -> HttpResponse {
+    if body.key.is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    let id = next_id(&counter);
+    let token = Uuid::new_v4().to_string();
+    let addr = Mailbox {
+        id,
+        addr: srv.get_ref().clone(),
+        token: token.clone(),
+        tokens: tokens.get_ref().clone(),
+        queue: VecDeque::new(),
+        limit: config.api_queue,
+        idle: config.api_idle,
+        last_poll: Instant::now(),
+    }.start();
+    if srv.send(IConnect { id, addr: addr.clone().recipient(), limit: Some(config.api_queue) }).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    srv.do_send(RawUserEvent::PublicKey(body.key.clone()).collect(id));
+    tokens.lock().unwrap().insert(token.clone(), addr);
+    HttpResponse::Created().json(json!({ "id": id, "token": token }))
+}
+
+#[delete("/api/identities")]
+async fn delete_identity(req: HttpRequest, tokens: Data<Tokens>) -> HttpResponse {
+    match mailbox(&req, &tokens) {
+        Some(mailbox) => {
+            mailbox.do_send(Close);
+            HttpResponse::NoContent().finish()
+        }
+        None => HttpResponse::Unauthorized().finish()
+    }
+}
+
+/// Everything the server sent since the last call, in the socket's format.
+#[get("/api/events")]
+async fn get_events(req: HttpRequest, tokens: Data<Tokens>) -> HttpResponse {
+    let mailbox = match mailbox(&req, &tokens) {
+        Some(mailbox) => mailbox,
+        None => return HttpResponse::Unauthorized().finish()
+    };
+    match mailbox.send(Drain).await {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(_) => HttpResponse::Unauthorized().finish()
+    }
+}
+
+/// Any event a socket client may send; answers arrive in `/api/events`.
+#[post("/api/events")]
+async fn post_event(req: HttpRequest, body: Json<RawUserEvent>, srv: Data<Addr<Server>>, tokens: Data<Tokens>) -> HttpResponse {
+    let device = match mailbox(&req, &tokens) {
+        Some(mailbox) => mailbox.send(DeviceId).await,
+        None => return HttpResponse::Unauthorized().finish()
+    };
+    match device {
+        Ok(device) => {
+            srv.do_send(body.into_inner().collect(device));
+            HttpResponse::Accepted().finish()
+        }
+        Err(_) => HttpResponse::Unauthorized().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct Outgoing {
+    to: usize,
+    /// A ciphertext for each device of `to`, by its id.
+    messages: HashMap<usize, String>,
+    random_id: usize,
+    #[serde(default)]
+    ttl: Option<u64>,
+}
+
+/// Sends a message and answers with its `message_status` right away,
+/// instead of leaving it for `/api/events`.
+#[post("/api/messages")]
+async fn post_message(req: HttpRequest, body: Json<Outgoing>, srv: Data<Addr<Server>>, tokens: Data<Tokens>) -> HttpResponse {
+    let mailbox = match mailbox(&req, &tokens) {
+        Some(mailbox) => mailbox,
+        None => return HttpResponse::Unauthorized().finish()
+    };
+    let device = match mailbox.send(DeviceId).await {
+        Ok(device) => device,
+        Err(_) => return HttpResponse::Unauthorized().finish()
+    };
+    let Outgoing { to, messages, random_id, ttl } = body.into_inner();
+    match srv.send(Post { from_id: device, to_id: to, messages, random_id, ttl }).await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(_) => HttpResponse::InternalServerError().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct Page {
+    #[serde(default)]
+    start: usize,
+    count: Option<usize>,
+}
+
+/// The users this identity may write to, with their keys.
+#[get("/api/users")]
+async fn get_users(req: HttpRequest, page: Query<Page>, srv: Data<Addr<Server>>, tokens: Data<Tokens>) -> HttpResponse {
+    let device = match mailbox(&req, &tokens) {
+        Some(mailbox) => mailbox.send(DeviceId).await,
+        None => return HttpResponse::Unauthorized().finish()
+    };
+    let lookup = match device {
+        Ok(device) => Lookup { device, start: page.start, count: page.count.unwrap_or(USERS) },
+        Err(_) => return HttpResponse::Unauthorized().finish()
+    };
+    match srv.send(lookup).await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(_) => HttpResponse::InternalServerError().finish()
+    }
+}
+
+#[get("/api/users/{id}")]
+async fn get_user(req: HttpRequest, id: Path<usize>, srv: Data<Addr<Server>>, tokens: Data<Tokens>) -> HttpResponse {
+    let device = match mailbox(&req, &tokens) {
+        Some(mailbox) => mailbox.send(DeviceId).await,
+        None => return HttpResponse::Unauthorized().finish()
+    };
+    let lookup = match device {
+        Ok(device) => Lookup { device, start: 0, count: usize::MAX },
+        Err(_) => return HttpResponse::Unauthorized().finish()
+    };
+    match srv.send(lookup).await.map(|users| users.into_iter().find(|x| x.id == *id)) {
+        Ok(Some(user)) => HttpResponse::Ok().json(user),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish()
+    }
+}
+
+pub fn routes(config: &mut ServiceConfig) {
+    config
+        .service(create_identity)
+        .service(delete_identity)
+        .service(get_events)
+        .service(post_event)
+        .service(post_message)
+        .service(get_users)
+        .service(get_user);
+}
@@ -0,0 +1,163 @@
+use std::path::{Component, Path, PathBuf};
+use actix_web::{get, web::{self, Data, ServiceConfig}, HttpRequest, HttpResponse};
+use crate::config::Config;
+
+/// The client as `trunk build` left it when the server was compiled.
+#[cfg(feature = "embed")]
+static DIST: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/../client/dist");
+
+/// Where the web client's files come from.
+#[derive(Clone, Debug)]
+pub enum Assets {
+    Dir(PathBuf),
+    #[cfg(feature = "embed")]
+    Embedded,
+}
+
+impl Assets {
+    /// `CHAT_WEB_DIR` when set, otherwise the embedded client if there is
+    /// one. `None` leaves hosting the client to someone else.
+    pub fn from_config(config: &Config) -> Option<Assets> {
+        match &config.web_dir {
+            Some(dir) => Some(Assets::Dir(dir.clone())),
+            #[cfg(feature = "embed")]
+            None => Some(Assets::Embedded),
+            #[cfg(not(feature = "embed"))]
+            None => None,
+        }
+    }
+    async fn read(&self, name: &str) -> Option<Vec<u8>> {
+        match self {
+            Assets::Dir(dir) => {
+                let path = dir.join(name);
+                web::block(move || std::fs::read(path)).await.ok()?.ok()
+            }
+            #[cfg(feature = "embed")]
+            Assets::Embedded => DIST.get_file(name).map(|file| file.contents().to_vec()),
+        }
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match Path::new(name).extension().and_then(|x| x.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "application/javascript",
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Trunk puts a content hash in every file name but `index.html`, so those
+/// never change under the same name.
+fn cache_control(name: &str) -> &'static str {
+    let hashed = Path::new(name).file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(|stem| stem.trim_end_matches("_bg").rsplit('-').next())
+        .is_some_and(|hash| hash.len() == 16 && hash.chars().all(|x| x.is_ascii_hexdigit()));
+    match hashed {
+        true => "public, max-age=31536000, immutable",
+        false => "no-cache"
+    }
+}
+
+/// The socket URL the page should connect to: `CHAT_SOCKET_URL`, or the
+/// address the browser used to reach us.
+fn socket_url(req: &HttpRequest, config: &Config) -> String {
+    if let Some(url) = &config.socket_url {
+        return url.clone();
+    }
+    let info = req.connection_info();
+    let scheme = match info.scheme() {
+        "https" => "wss",
+        _ => "ws"
+    };
+    format!("{}://{}/chat", scheme, info.host())
+}
+
+/// Tells the client where the socket is with a `chat-server` meta tag.
+fn inject(html: &[u8], socket: &str) -> Vec<u8> {
+    let html = String::from_utf8_lossy(html);
+    let socket = socket.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+    let meta = format!("<meta name=\"chat-server\" content=\"{}\">", socket);
+    match html.find("</head>") {
+        Some(i) => format!("{}{}{}", &html[..i], meta, &html[i..]).into_bytes(),
+        None => format!("{}{}", meta, html).into_bytes()
+    }
+}
+
+#[get("/{path:.*}")]
+async fn asset(req: HttpRequest, path: web::Path<String>, assets: Data<Assets>, config: Data<Config>) -> HttpResponse {
+    let name = match path.as_str() {
+        "" => "index.html",
+        name => name
+    };
+    if !Path::new(name).components().all(|x| matches!(x, Component::Normal(_))) {
+        return HttpResponse::NotFound().finish();
+    }
+    let data = match assets.read(name).await {
+        Some(data) => data,
+        None => return HttpResponse::NotFound().finish()
+    };
+    let data = match name {
+        "index.html" => inject(&data, &socket_url(&req, &config)),
+        _ => data
+    };
+    HttpResponse::Ok()
+        .content_type(content_type(name))
+        .insert_header(("Cache-Control", cache_control(name)))
+        .body(data)
+}
+
+/// Serves the client at `/`, if there is one to serve. It matches every
+/// path, so it has to be registered after everything else.
+pub fn routes(config: &mut ServiceConfig, assets: Option<Assets>) {
+    if let Some(assets) = assets {
+        config.app_data(Data::new(assets)).service(asset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_files_by_extension() {
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("client-0123456789abcdef_bg.wasm"), "application/wasm");
+        assert_eq!(content_type("css/index.css"), "text/css");
+        assert_eq!(content_type("LICENSE"), "application/octet-stream");
+    }
+
+    #[test]
+    fn caches_only_hashed_names_for_good() {
+        let immutable = "public, max-age=31536000, immutable";
+        assert_eq!(cache_control("client-0123456789abcdef.js"), immutable);
+        assert_eq!(cache_control("client-0123456789abcdef_bg.wasm"), immutable);
+        assert_eq!(cache_control("index.html"), "no-cache");
+        assert_eq!(cache_control("client-latest.js"), "no-cache");
+        assert_eq!(cache_control("client-0123456789abcdeg.js"), "no-cache");
+    }
+
+    #[test]
+    fn puts_the_socket_in_the_head() {
+        let html = inject(b"<html><head><title>chat</title></head></html>", "wss://example.com/chat");
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            "<html><head><title>chat</title><meta name=\"chat-server\" content=\"wss://example.com/chat\"></head></html>"
+        );
+    }
+
+    #[test]
+    fn escapes_the_socket() {
+        let html = inject(b"<p>no head</p>", "ws://a/\"><script>&");
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            "<meta name=\"chat-server\" content=\"ws://a/&quot;>&lt;script>&amp;\"><p>no head</p>"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fs::{self, OpenOptions}, io::{Seek, SeekFrom, Write}, path::{Path, PathBuf}, time::{Duration, Instant}};
+use actix_web::{rt, web};
+use uuid::Uuid;
+use crate::config::Config;
+
+#[derive(Clone, Debug)]
+struct Blob {
+    owner: usize,
+    size: u64,
+    /// Bytes handed out to be written, ahead of `written` while chunks
+    /// are still on their way to disk.
+    received: u64,
+    written: u64,
+    created: Instant,
+}
+
+/// Encrypted attachments on disk. The server only ever sees ciphertext; the
+/// key travels inside the end-to-end encrypted message that references it.
+///
+/// Only the bookkeeping lives here, so it can sit in the `Server` actor: the
+/// files themselves are written and removed on the blocking thread pool.
+#[derive(Clone)]
+pub struct Blobs {
+    dir: PathBuf,
+    max: u64,
+    quota: u64,
+    ttl: Duration,
+    blobs: HashMap<String, Blob>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Chunk {
+    Accepted,
+    Complete,
+    Rejected,
+}
+
+/// Where blob `id` lives under `dir`, `None` unless `id` is a well-formed id.
+pub fn path(dir: &Path, id: &str) -> Option<PathBuf> {
+    Uuid::parse_str(id).ok().map(|id| dir.join(id.to_string()))
+}
+
+impl Blobs {
+    /// Opens the store, dropping whatever an earlier run left behind: the
+    /// messages referencing those blobs are gone with the old process anyway.
+    pub fn new(config: &Config) -> Blobs {
+        if let Err(e) = fs::create_dir_all(&config.blob_dir) {
+            log::error!("could not create {}: {}", config.blob_dir.display(), e);
+        }
+        for entry in fs::read_dir(&config.blob_dir).into_iter().flatten().flatten() {
+            if entry.file_name().to_str().is_some_and(|name| Uuid::parse_str(name).is_ok()) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        Blobs {
+            dir: config.blob_dir.clone(),
+            max: config.blob_max,
+            quota: config.blob_quota,
+            ttl: config.blob_ttl,
+            blobs: HashMap::new(),
+        }
+    }
+    fn usage(&self, owner: usize) -> u64 {
+        self.blobs.values().filter(|x| x.owner == owner).map(|x| x.size).sum()
+    }
+    /// Reserves room for an upload of `size` bytes, `None` if it's over a quota.
+    pub fn start(&mut self, owner: usize, size: u64) -> Option<String> {
+        if size == 0 || size > self.max || self.usage(owner) + size > self.quota {
+            return None;
+        }
+        let id = Uuid::new_v4().to_string();
+        self.blobs.insert(id.clone(), Blob { owner, size, received: 0, written: 0, created: Instant::now() });
+        Some(id)
+    }
+    /// Books the next `len` bytes of blob `id`, answering where they go in
+    /// which file, or `None` when `owner` may not write them.
+    pub fn reserve(&mut self, owner: usize, id: &str, len: u64) -> Option<(PathBuf, u64)> {
+        let blob = match self.blobs.get_mut(id) {
+            Some(blob) if blob.owner == owner && blob.received < blob.size => blob,
+            _ => return None
+        };
+        if blob.received + len > blob.size {
+            self.remove(id);
+            return None;
+        }
+        let offset = blob.received;
+        blob.received += len;
+        Some((self.dir.join(id), offset))
+    }
+    /// Records how writing `len` bytes of blob `id` went, `None` when the
+    /// blob was dropped in the meantime.
+    pub fn written(&mut self, id: &str, len: u64, ok: bool) -> Option<Chunk> {
+        let blob = match self.blobs.get_mut(id) {
+            Some(blob) => blob,
+            None => {
+                // The write may have created the file after it was removed.
+                remove_file(self.dir.join(id));
+                return None;
+            }
+        };
+        if !ok {
+            self.remove(id);
+            return Some(Chunk::Rejected);
+        }
+        blob.written += len;
+        Some(if blob.written == blob.size { Chunk::Complete } else { Chunk::Accepted })
+    }
+    pub fn is_complete(&self, id: &str) -> bool {
+        self.blobs.get(id).is_some_and(|x| x.written == x.size)
+    }
+    fn remove(&mut self, id: &str) {
+        self.blobs.remove(id);
+        remove_file(self.dir.join(id));
+    }
+    pub fn prune(&mut self) {
+        let expired: Vec<String> = self.blobs.iter()
+            .filter(|(_, blob)| blob.created.elapsed() > self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.remove(&id);
+        }
+    }
+}
+/// Writes `data` at `offset` of the file at `path`, so chunks may land in
+/// any order.
+pub async fn write(path: PathBuf, offset: u64, data: Vec<u8>) -> bool {
+    let written = web::block(move || {
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&data)
+    }).await;
+    matches!(written, Ok(Ok(())))
+}
+
+fn remove_file(path: PathBuf) {
+    rt::spawn(web::block(move || fs::remove_file(path)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a directory of its own, holding blobs of up to 10 bytes
+    /// and 16 per owner.
+    fn blobs(name: &str) -> Blobs {
+        let dir = std::env::temp_dir().join(format!("chat-blobs-{}-{}", name, std::process::id()));
+        Blobs { dir, max: 10, quota: 16, ttl: Duration::from_secs(60), blobs: HashMap::new() }
+    }
+
+    #[test]
+    fn keeps_owners_to_their_quota() {
+        let mut blobs = blobs("quota");
+        assert!(blobs.start(1, 0).is_none());
+        assert!(blobs.start(1, 11).is_none());
+        assert!(blobs.start(1, 10).is_some());
+        assert!(blobs.start(1, 7).is_none());
+        assert!(blobs.start(1, 6).is_some());
+        assert!(blobs.start(2, 10).is_some());
+    }
+
+    #[actix_web::test]
+    async fn books_chunks_in_order_for_their_owner() {
+        let mut blobs = blobs("reserve");
+        let id = blobs.start(1, 10).unwrap();
+        assert!(blobs.reserve(2, &id, 4).is_none());
+        assert_eq!(blobs.reserve(1, &id, 4).map(|(_, offset)| offset), Some(0));
+        assert_eq!(blobs.reserve(1, &id, 4).map(|(_, offset)| offset), Some(4));
+        // past the announced size drops the upload
+        assert!(blobs.reserve(1, &id, 4).is_none());
+        assert!(blobs.reserve(1, &id, 2).is_none());
+        assert_eq!(blobs.usage(1), 0);
+    }
+
+    #[actix_web::test]
+    async fn completes_once_everything_is_written() {
+        let mut blobs = blobs("written");
+        let id = blobs.start(1, 10).unwrap();
+        blobs.reserve(1, &id, 6).unwrap();
+        blobs.reserve(1, &id, 4).unwrap();
+        assert_eq!(blobs.written(&id, 4, true), Some(Chunk::Accepted));
+        assert!(!blobs.is_complete(&id));
+        assert_eq!(blobs.written(&id, 6, true), Some(Chunk::Complete));
+        assert!(blobs.is_complete(&id));
+    }
+
+    #[actix_web::test]
+    async fn failed_writes_drop_the_upload() {
+        let mut blobs = blobs("failed");
+        let id = blobs.start(1, 10).unwrap();
+        blobs.reserve(1, &id, 10).unwrap();
+        assert_eq!(blobs.written(&id, 10, false), Some(Chunk::Rejected));
+        assert_eq!(blobs.written(&id, 10, true), None);
+        assert_eq!(blobs.usage(1), 0);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{env, path::PathBuf, time::Duration};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub address: String,
+    pub contacts_only: bool,
+    pub resume_grace: Duration,
+    /// How long a device linking code stays valid.
+    pub link_ttl: Duration,
+    pub blob_dir: PathBuf,
+    /// Where state that has to outlive a restart is kept, such as block lists.
+    pub data_dir: PathBuf,
+    /// Largest single attachment, in bytes.
+    pub blob_max: u64,
+    /// Bytes of attachments a user may keep stored at once.
+    pub blob_quota: u64,
+    pub blob_ttl: Duration,
+    /// How long a REST identity lives without being polled.
+    pub api_idle: Duration,
+    /// Most events a REST identity or a long-polling session keeps waiting.
+    /// Messages past it fail, other events push out the oldest.
+    pub api_queue: usize,
+    /// Where the built web client is, if this server hosts it.
+    pub web_dir: Option<PathBuf>,
+    /// The socket URL handed to the web client, when the one it reached us
+    /// by is wrong, as behind a proxy.
+    pub socket_url: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            address: env::var("CHAT_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8081".to_string()),
+            contacts_only: flag("CHAT_CONTACTS_ONLY"),
+            resume_grace: Duration::from_secs(number("CHAT_RESUME_GRACE").unwrap_or(300)),
+            link_ttl: Duration::from_secs(number("CHAT_LINK_TTL").unwrap_or(300)),
+            blob_dir: env::var("CHAT_BLOB_DIR").unwrap_or_else(|_| "blobs".to_string()).into(),
+            data_dir: env::var("CHAT_DATA_DIR").unwrap_or_else(|_| "data".to_string()).into(),
+            blob_max: number("CHAT_BLOB_MAX").unwrap_or(25 * 1024 * 1024),
+            blob_quota: number("CHAT_BLOB_QUOTA").unwrap_or(100 * 1024 * 1024),
+            blob_ttl: Duration::from_secs(number("CHAT_BLOB_TTL").unwrap_or(7 * 24 * 60 * 60)),
+            api_idle: Duration::from_secs(number("CHAT_API_IDLE").unwrap_or(24 * 60 * 60)),
+            api_queue: number("CHAT_API_QUEUE").unwrap_or(1000) as usize,
+            web_dir: env::var("CHAT_WEB_DIR").ok().map(PathBuf::from),
+            socket_url: env::var("CHAT_SOCKET_URL").ok(),
+        }
+    }
+}
+
+fn flag(name: &str) -> bool {
+    env::var(name)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+fn number(name: &str) -> Option<u64> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
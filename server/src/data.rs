@@ -1,6 +1,7 @@
+use std::collections::HashMap;
 use actix::{Message, Recipient};
 use serde::{Deserialize, Serialize};
-use crate::user::SafeUser;
+use crate::user::{DeviceInfo, SafeUser};
 
 #[derive(Serialize, Deserialize, Message, Debug, Clone)]
 #[rtype(result = "()")]
@@ -8,7 +9,27 @@ use crate::user::SafeUser;
 pub enum RawUserEvent {
     GetUsersIds { start: usize, count: usize },
     PublicKey(String),
-    Message { to: usize, message: String, random_id: usize },
+    /// `messages` holds a ciphertext for each device, by its id.
+    Message {
+        to: usize,
+        messages: HashMap<usize, String>,
+        random_id: usize,
+        #[serde(default)]
+        ttl: Option<u64>
+    },
+    AcceptContact(usize),
+    DeclineContact(usize),
+    Block(usize),
+    Unblock(usize),
+    Resume(String),
+    Upload { random_id: usize, size: u64 },
+    Chunk { blob: String, data: String },
+    Retract { to: usize, random_id: usize },
+    LinkCode,
+    Link(String),
+    Revoke(usize),
+    /// The opened nonce of a `Challenge`, base64.
+    Prove(String),
 }
 
 impl RawUserEvent {
@@ -16,7 +37,19 @@ impl RawUserEvent {
         match self {
             Self::GetUsersIds { start, count } => UserEvent::GetUsersIds { start: *start, count: *count, id: from_id },
             Self::PublicKey(key) => UserEvent::PublicKey { from_id, value: key.to_string() },
-            Self::Message { to, message, random_id } => UserEvent::Message { from_id, to_id: *to, message: message.to_string(), random_id: *random_id },
+            Self::Message { to, messages, random_id, ttl } => UserEvent::Message { from_id, to_id: *to, messages: messages.clone(), random_id: *random_id, ttl: *ttl },
+            Self::AcceptContact(id) => UserEvent::AcceptContact { from_id, id: *id },
+            Self::DeclineContact(id) => UserEvent::DeclineContact { from_id, id: *id },
+            Self::Block(id) => UserEvent::Block { from_id, id: *id },
+            Self::Unblock(id) => UserEvent::Unblock { from_id, id: *id },
+            Self::Resume(token) => UserEvent::Resume { from_id, token: token.to_string() },
+            Self::Upload { random_id, size } => UserEvent::Upload { from_id, random_id: *random_id, size: *size },
+            Self::Chunk { blob, data } => UserEvent::Chunk { from_id, blob: blob.to_string(), data: data.to_string() },
+            Self::Retract { to, random_id } => UserEvent::Retract { from_id, to_id: *to, random_id: *random_id },
+            Self::LinkCode => UserEvent::LinkCode { from_id },
+            Self::Link(code) => UserEvent::Link { from_id, code: code.to_string() },
+            Self::Revoke(device) => UserEvent::Revoke { from_id, device: *device },
+            Self::Prove(nonce) => UserEvent::Prove { from_id, nonce: nonce.to_string() },
         }
     }
 }
@@ -27,26 +60,116 @@ impl RawUserEvent {
 pub enum UserEvent {
     GetUsersIds { start: usize, count: usize, id: usize },
     PublicKey { from_id: usize, value: String },
-    Message { from_id: usize, to_id: usize, message: String, random_id: usize },
+    /// `ttl` is in seconds, from the sender's disappearing-messages timer.
+    Message { from_id: usize, to_id: usize, messages: HashMap<usize, String>, random_id: usize, ttl: Option<u64> },
+    AcceptContact { from_id: usize, id: usize },
+    DeclineContact { from_id: usize, id: usize },
+    Block { from_id: usize, id: usize },
+    Unblock { from_id: usize, id: usize },
+    Resume { from_id: usize, token: String },
+    Upload { from_id: usize, random_id: usize, size: u64 },
+    /// `data` is base64, since chunks ride the same JSON text frames.
+    Chunk { from_id: usize, blob: String, data: String },
+    /// Drops a message still waiting in `to_id`'s offline queue.
+    Retract { from_id: usize, to_id: usize, random_id: usize },
+    LinkCode { from_id: usize },
+    Link { from_id: usize, code: String },
+    Revoke { from_id: usize, device: usize },
+    Prove { from_id: usize, nonce: String },
+}
+
+impl UserEvent {
+    /// The variant's name, for logs that mustn't carry keys or ciphertext.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::GetUsersIds { .. } => "get_users_ids",
+            Self::PublicKey { .. } => "public_key",
+            Self::Message { .. } => "message",
+            Self::AcceptContact { .. } => "accept_contact",
+            Self::DeclineContact { .. } => "decline_contact",
+            Self::Block { .. } => "block",
+            Self::Unblock { .. } => "unblock",
+            Self::Resume { .. } => "resume",
+            Self::Upload { .. } => "upload",
+            Self::Chunk { .. } => "chunk",
+            Self::Retract { .. } => "retract",
+            Self::LinkCode { .. } => "link_code",
+            Self::Link { .. } => "link",
+            Self::Revoke { .. } => "revoke",
+            Self::Prove { .. } => "prove",
+        }
+    }
+
+    /// The device the event came from.
+    pub fn sender(&self) -> usize {
+        match self {
+            Self::GetUsersIds { id, .. } => *id,
+            Self::PublicKey { from_id, .. }
+            | Self::Message { from_id, .. }
+            | Self::AcceptContact { from_id, .. }
+            | Self::DeclineContact { from_id, .. }
+            | Self::Block { from_id, .. }
+            | Self::Unblock { from_id, .. }
+            | Self::Resume { from_id, .. }
+            | Self::Upload { from_id, .. }
+            | Self::Chunk { from_id, .. }
+            | Self::Retract { from_id, .. }
+            | Self::LinkCode { from_id }
+            | Self::Link { from_id, .. }
+            | Self::Revoke { from_id, .. }
+            | Self::Prove { from_id, .. } => *from_id,
+        }
+    }
+}
+
+/// What the server stamps on every message it routes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    /// Unique across the server, unlike the client's `random_id`.
+    pub id: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Counts up by one per message between the same two users.
+    pub seq: u64,
 }
 
 #[derive(Serialize, Deserialize, Message, Debug, Clone)]
 #[rtype(result = "()")]
 #[serde(rename_all = "snake_case")]
 pub enum SystemEvent {
+    /// The account id, what other users know us by.
     YourId(usize),
-    Message { from: usize, message: String, random_id: usize },
+    /// The id of this connection's device within the account.
+    YourDevice(usize),
+    /// `to` tells the sender's other devices which conversation it belongs to.
+    Message { from: usize, to: usize, message: String, random_id: usize, stamp: Stamp },
     SetKey(String),
     GetUsersIds(Vec<SafeUser>),
-    MessageStatus { random_id: usize, status: bool },
+    /// `stamp` is what the delivered message got, `None` when it wasn't.
+    MessageStatus { random_id: usize, status: bool, stamp: Option<Stamp> },
     UserIn(SafeUser),
     UserOut(SafeUser),
+    ContactRequest(usize),
+    BlockList(Vec<usize>),
+    ResumeToken(String),
+    /// Answers `Upload`, `None` when the attachment is over a quota.
+    Upload { random_id: usize, blob: Option<String> },
+    BlobStatus { blob: String, status: bool },
+    Devices(Vec<DeviceInfo>),
+    LinkCode(String),
+    Linked(bool),
+    /// This device was unlinked from its account by another one.
+    Revoked,
+    /// A nonce sealed for the key the device announced. Sent back opened
+    /// in `Prove`, it shows the device holds the private key.
+    Challenge(String),
 }
 
 #[derive(Message, Clone)]
 #[rtype(result = "()")]
 pub struct IDisconnect {
     pub id: usize,
+    pub addr: Recipient<SystemEvent>,
 }
 
 #[derive(Message, Clone)]
@@ -54,4 +177,42 @@ pub struct IDisconnect {
 pub struct IConnect {
     pub id: usize,
     pub addr: Recipient<SystemEvent>,
-}
\ No newline at end of file
+    /// How many messages the transport holds for its client between two
+    /// fetches, `None` when it passes them straight on.
+    pub limit: Option<usize>,
+}
+
+/// The client of a queueing transport fetched everything it held.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct Drained {
+    pub id: usize,
+    pub addr: Recipient<SystemEvent>,
+}
+
+/// The users `device` may see, as `GetUsersIds` lists them, for callers
+/// that want the answer back rather than on a socket.
+#[derive(Message, Clone)]
+#[rtype(result = "Vec<SafeUser>")]
+pub struct Lookup {
+    pub device: usize,
+    pub start: usize,
+    pub count: usize,
+}
+
+/// Whether blob `0` is all on disk, so it may be downloaded.
+#[derive(Message, Clone)]
+#[rtype(result = "bool")]
+pub struct BlobComplete(pub String);
+
+/// A message from `from_id`, answered with its `MessageStatus` for callers
+/// that want it back rather than queued for the device.
+#[derive(Message, Clone)]
+#[rtype(result = "SystemEvent")]
+pub struct Post {
+    pub from_id: usize,
+    pub to_id: usize,
+    pub messages: HashMap<usize, String>,
+    pub random_id: usize,
+    pub ttl: Option<u64>,
+}
@@ -0,0 +1,91 @@
+//! The chat server: a WebSocket relay for sealed messages, with REST, SSE
+//! and long-polling transports beside it. The binary runs it from the
+//! environment; tests can start it on a listener of their own.
+
+pub mod api;
+pub mod assets;
+pub mod blobs;
+pub mod config;
+pub mod data;
+pub mod poll;
+pub mod relations;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod transport;
+pub mod user;
+
+use std::{collections::HashMap, net::TcpListener, time::Instant, sync::{Arc, Mutex}};
+use actix::{Addr, Actor};
+use actix_web::{dev, web::{self, Data, Path}, App, Error, HttpRequest, HttpResponse, HttpServer, get};
+use actix_web_actors::ws;
+use server::Server;
+
+use crate::{api::Tokens, assets::Assets, config::Config, data::BlobComplete, poll::Polls, session::Session, transport::{next_id, Transports}};
+
+
+#[get("/chat")]
+async fn index(req: HttpRequest, stream: web::Payload, srv: Data<Addr<Server>>, counter: Data<Arc<Mutex<usize>>>) -> Result<HttpResponse, Error> {
+    ws::start(
+        Session {
+            id: next_id(&counter), 
+            last_ping: Instant::now(), 
+            public_key: None,
+            addr: srv.get_ref().clone()
+        }, 
+        &req, 
+        stream)
+}
+
+/// Serves an uploaded attachment once all of it arrived. Blobs are
+/// ciphertext addressed by random ids, so knowing the id is the only
+/// permission needed.
+#[get("/blobs/{id}")]
+async fn blob(id: Path<String>, srv: Data<Addr<Server>>, config: Data<Config>) -> HttpResponse {
+    let path = match blobs::path(&config.blob_dir, &id) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().finish()
+    };
+    if !srv.send(BlobComplete(id.into_inner())).await.unwrap_or(false) {
+        return HttpResponse::NotFound().finish();
+    }
+    match web::block(move || std::fs::read(path)).await {
+        Ok(Ok(data)) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .insert_header(("Access-Control-Allow-Origin", "*"))
+            .insert_header(("Cache-Control", "private, max-age=31536000, immutable"))
+            .body(data),
+        _ => HttpResponse::NotFound().finish()
+    }
+}
+
+/// Serves the chat on `listener`; `config.address` is left to the caller.
+/// Has to be called from within an actix system, which the returned server
+/// then runs on.
+pub fn run(config: Config, listener: TcpListener) -> std::io::Result<dev::Server> {
+    let counter = Arc::new(Mutex::new(0usize));
+    let manager = Server::new(&config).start();
+    let tokens: Tokens = Arc::new(Mutex::new(HashMap::new()));
+    let transports: Transports = Arc::new(Mutex::new(HashMap::new()));
+    let polls: Polls = Arc::new(Mutex::new(HashMap::new()));
+    let assets = Assets::from_config(&config);
+    if let Some(assets) = &assets {
+        log::info!("serving the web client from {:?}", assets);
+    }
+    Ok(HttpServer::new(move || 
+        App::new()
+            .service(index)
+            .service(blob)
+            .configure(api::routes)
+            .configure(transport::routes)
+            .configure(|cfg| assets::routes(cfg, assets.clone()))
+            .app_data(Data::new(config.clone()))
+            .app_data(Data::new(manager.clone()))
+            .app_data(Data::new(counter.clone()))
+            .app_data(Data::new(tokens.clone()))
+            .app_data(Data::new(transports.clone()))
+            .app_data(Data::new(polls.clone()))
+    )
+    .listen(listener)?
+    .run())
+}
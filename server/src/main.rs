@@ -1,32 +1,6 @@
-pub mod data;
-pub mod server;
-pub mod session;
-pub mod user;
-
-use std::{time::Instant, sync::{Arc, Mutex}};
-use actix::{Addr, Actor};
-use actix_web::{web::{self, Data}, App, Error, HttpRequest, HttpResponse, HttpServer, get};
-use actix_web_actors::ws;
+use std::net::TcpListener;
+use chat_server::{config::Config, data::RawUserEvent};
 use serde_json::json;
-use server::Server;
-
-use crate::{session::Session, data::RawUserEvent};
-
-
-#[get("/chat")]
-async fn index(req: HttpRequest, stream: web::Payload, srv: Data<Addr<Server>>, counter: Data<Arc<Mutex<usize>>>) -> Result<HttpResponse, Error> {
-    let mut counter = counter.lock().unwrap();
-    *counter += 1;
-    ws::start(
-        Session {
-            id: *counter-1, 
-            last_ping: Instant::now(), 
-            public_key: None,
-            addr: srv.get_ref().clone()
-        }, 
-        &req, 
-        stream)
-}
 
 #[actix_web::main]
 async fn main() {
@@ -36,15 +10,8 @@ async fn main() {
 
     println!("{}", json!(RawUserEvent::PublicKey("HELLO WORLD".to_string())));
 
-    let counter = Arc::new(Mutex::new(0usize));
-    let manager = Server::new().start();
-    HttpServer::new(move || 
-        App::new()
-            .service(index)
-            .app_data(Data::new(manager.clone()))
-            .app_data(Data::new(counter.clone()))
-    )
-    .bind("127.0.0.1:8081").unwrap()
-    .run()
-    .await.unwrap();
-}
\ No newline at end of file
+    let config = Config::from_env();
+    let listener = TcpListener::bind(&config.address).unwrap();
+    chat_server::run(config, listener).unwrap()
+        .await.unwrap();
+}
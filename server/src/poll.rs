@@ -0,0 +1,155 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message, ResponseFuture, Running};
+use actix_web::{get, post, web::{Data, Path}, HttpResponse};
+use serde_json::json;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use crate::{
+    config::Config,
+    data::{Drained, IConnect, IDisconnect, SystemEvent},
+    server::Server,
+    transport::{self, next_id, Inbound, Transports},
+};
+
+/// How long a poll waits for something to happen before answering empty.
+const POLL_WAIT: Duration = Duration::from_secs(25);
+/// A client that hasn't polled for this long is gone.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(40);
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Long-polling sessions, by their token.
+pub type Polls = Arc<Mutex<HashMap<String, Addr<PollSession>>>>;
+
+/// A client fetching events with long polls on `/chat/poll/{token}` and
+/// posting its own to `/chat/send/{token}`.
+pub struct PollSession {
+    pub id: usize,
+    pub addr: Addr<Server>,
+    token: String,
+    transports: Transports,
+    polls: Polls,
+    queue: VecDeque<SystemEvent>,
+    /// Most events kept for the next poll, as for a REST identity.
+    limit: usize,
+    /// The poll waiting for the next event, if one is.
+    waiter: Option<oneshot::Sender<Vec<SystemEvent>>>,
+    last_poll: Instant,
+}
+
+impl PollSession {
+    /// Answers the waiting poll with everything queued. A poll that gave up
+    /// in the meantime leaves the events for the next one.
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.queue.is_empty() {
+            return;
+        }
+        if let Some(waiter) = self.waiter.take() {
+            match waiter.send(self.queue.drain(..).collect()) {
+                Ok(()) => self.addr.do_send(Drained { id: self.id, addr: ctx.address().recipient() }),
+                Err(events) => self.queue.extend(events)
+            }
+        }
+    }
+}
+
+impl Actor for PollSession {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.addr.do_send(IConnect { id: self.id, addr: ctx.address().recipient(), limit: Some(self.limit) });
+        ctx.run_interval(CHECK_INTERVAL, |act, ctx| {
+            let waiting = act.waiter.as_ref().is_some_and(|x| !x.is_closed());
+            if !waiting && act.last_poll.elapsed() > CLIENT_TIMEOUT {
+                ctx.stop();
+            }
+        });
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.transports.lock().unwrap().remove(&self.token);
+        self.polls.lock().unwrap().remove(&self.token);
+        self.addr.do_send(IDisconnect { id: self.id, addr: ctx.address().recipient() });
+        Running::Stop
+    }
+}
+
+impl Handler<SystemEvent> for PollSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, ctx: &mut Self::Context) {
+        match msg {
+            SystemEvent::SetKey(_) => return,
+            SystemEvent::YourDevice(id) => self.id = id,
+            _ => ()
+        }
+        transport::enqueue(&mut self.queue, msg, self.limit);
+        self.flush(ctx);
+    }
+}
+
+impl Handler<Inbound> for PollSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Inbound, _: &mut Self::Context) {
+        self.addr.do_send(msg.0.collect(self.id));
+    }
+}
+
+/// Waits for events, answering at once if some are queued.
+#[derive(Message)]
+#[rtype(result = "Vec<SystemEvent>")]
+struct Wait;
+
+impl Handler<Wait> for PollSession {
+    type Result = ResponseFuture<Vec<SystemEvent>>;
+
+    fn handle(&mut self, _: Wait, ctx: &mut Self::Context) -> Self::Result {
+        self.last_poll = Instant::now();
+        let (sender, receiver) = oneshot::channel();
+        // a newer poll replaces one the client has given up on
+        if let Some(old) = self.waiter.replace(sender) {
+            let _ = old.send(vec![]);
+        }
+        self.flush(ctx);
+        Box::pin(async move {
+            match tokio::time::timeout(POLL_WAIT, receiver).await {
+                Ok(Ok(events)) => events,
+                _ => vec![]
+            }
+        })
+    }
+}
+
+/// Starts a long-polling session and answers with its token.
+#[post("/chat/poll")]
+pub async fn open(srv: Data<Addr<Server>>, counter: Data<Arc<Mutex<usize>>>, transports: Data<Transports>, polls: Data<Polls>, config: Data<Config>) -> HttpResponse {
+    let token = Uuid::new_v4().to_string();
+    let session = PollSession {
+        id: next_id(&counter),
+        addr: srv.get_ref().clone(),
+        token: token.clone(),
+        transports: transports.get_ref().clone(),
+        polls: polls.get_ref().clone(),
+        queue: VecDeque::new(),
+        limit: config.api_queue,
+        waiter: None,
+        last_poll: Instant::now(),
+    }.start();
+    // registered before answering, so the first poll can't miss the session
+    transports.lock().unwrap().insert(token.clone(), session.clone().recipient());
+    polls.lock().unwrap().insert(token.clone(), session);
+    HttpResponse::Ok().json(json!({ "token": token }))
+}
+
+/// The next events of the session, an empty list if none came in time.
+#[get("/chat/poll/{token}")]
+pub async fn wait(token: Path<String>, polls: Data<Polls>) -> HttpResponse {
+    let session = polls.lock().unwrap().get(token.as_str()).cloned();
+    match session {
+        Some(session) => match session.send(Wait).await {
+            Ok(events) => HttpResponse::Ok().insert_header(("Cache-Control", "no-store")).json(events),
+            Err(_) => HttpResponse::NotFound().finish()
+        },
+        None => HttpResponse::NotFound().finish()
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::{HashMap, HashSet}, fs, path::PathBuf, sync::{Arc, Mutex}};
+use actix_web::{rt, web};
+use crate::config::Config;
+
+/// Who blocked or accepted whom, by account identity rather than by the
+/// ids that only last a connection, kept on disk so it outlives reconnects
+/// and restarts.
+#[derive(Clone)]
+pub struct Relations {
+    path: PathBuf,
+    relations: HashMap<String, HashSet<String>>,
+    version: u64,
+    /// The newest version on disk, so a slow write can't undo a later one.
+    saved: Arc<Mutex<u64>>,
+}
+
+impl Relations {
+    /// Reads `name`, a JSON file in the data directory.
+    pub fn load(config: &Config, name: &str) -> Relations {
+        if let Err(e) = fs::create_dir_all(&config.data_dir) {
+            log::error!("could not create {}: {}", config.data_dir.display(), e);
+        }
+        let path = config.data_dir.join(name);
+        let relations = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                log::error!("could not read {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new()
+        };
+        Relations { path, relations, version: 0, saved: Arc::new(Mutex::new(0)) }
+    }
+    pub fn contains(&self, by: &str, identity: &str) -> bool {
+        self.relations.get(by).is_some_and(|related| related.contains(identity))
+    }
+    pub fn related(&self, by: &str) -> impl Iterator<Item = &String> {
+        self.relations.get(by).into_iter().flatten()
+    }
+    pub fn insert(&mut self, by: String, identity: String) -> bool {
+        let inserted = self.relations.entry(by).or_default().insert(identity);
+        if inserted {
+            self.save();
+        }
+        inserted
+    }
+    pub fn remove(&mut self, by: &str, identity: &str) -> bool {
+        let removed = self.relations.get_mut(by).is_some_and(|related| related.remove(identity));
+        if removed {
+            self.relations.retain(|_, related| !related.is_empty());
+            self.save();
+        }
+        removed
+    }
+    /// Writes the lists on the blocking thread pool.
+    fn save(&mut self) {
+        self.version += 1;
+        let (version, saved, path) = (self.version, self.saved.clone(), self.path.clone());
+        let data = match serde_json::to_vec(&self.relations) {
+            Ok(data) => data,
+            Err(_) => return
+        };
+        rt::spawn(web::block(move || {
+            let mut saved = saved.lock().unwrap();
+            if *saved > version {
+                return;
+            }
+            let temp = path.with_extension("json.tmp");
+            match fs::write(&temp, data).and_then(|_| fs::rename(&temp, &path)) {
+                Ok(()) => *saved = version,
+                Err(e) => log::error!("could not write {}: {}", path.display(), e)
+            }
+        }));
+    }
+}
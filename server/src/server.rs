@@ -1,48 +1,493 @@
-use std::collections::HashMap;
-use actix::{Context, Actor, Handler};
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use actix::{Context, Actor, ActorFutureExt, Handler, AsyncContext, MessageResult, WrapFuture};
+use client_core::native;
+use uuid::Uuid;
 use crate::{
-    data::{IConnect, IDisconnect, UserEvent, SystemEvent}, user::User, 
+    blobs::{self, Blobs, Chunk}, config::Config, data::{BlobComplete, Drained, IConnect, IDisconnect, Lookup, Post, UserEvent, Stamp, SystemEvent}, relations::Relations, user::{Device, SafeUser, User},
 };
 
+const PRUNE_INTERVAL: Duration = Duration::from_secs(30);
+/// Link codes avoid characters that are easy to misread.
+const LINK_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_LENGTH: usize = 8;
+const CHALLENGE_LENGTH: usize = 32;
+
+/// A fresh nonce, base64, with the same sealed for `key` so only its
+/// holder can open it.
+fn challenge(key: &str) -> Option<(String, String)> {
+    let mut nonce = [0u8; CHALLENGE_LENGTH];
+    getrandom::getrandom(&mut nonce).ok()?;
+    let sealed = native::import_public(key).and_then(|key| native::seal(&key, &nonce)).ok()?;
+    Some((base64::encode(nonce), base64::encode(sealed)))
+}
+
 #[derive(Clone)]
 pub struct Server {
+    /// Accounts with at least one device, live or detached.
     pub sessions: HashMap<usize, User>,
+    /// The account each device belongs to; devices are named by the id of
+    /// the connection that created them.
+    pub devices: HashMap<usize, usize>,
+    /// Resumption tokens, by the device they reclaim.
+    pub tokens: HashMap<String, usize>,
+    /// One-time codes for linking a new device, with the account and the
+    /// moment they were issued.
+    pub links: HashMap<String, (usize, Instant)>,
+    pub blocks: Relations,
+    /// Whom each identity accepted as a contact, for contacts-only routing.
+    pub contacts: Relations,
+    pub contacts_only: bool,
+    pub resume_grace: Duration,
+    pub link_ttl: Duration,
+    pub blobs: Blobs,
+    pub next_id: u64,
+    /// The last sequence number of each conversation, by its pair of users.
+    pub sequences: HashMap<(usize, usize), u64>,
 }
 
 impl Server {
-    pub fn new() -> Server {
+    pub fn new(config: &Config) -> Server {
         Server {
             sessions: HashMap::new(),
+            devices: HashMap::new(),
+            tokens: HashMap::new(),
+            links: HashMap::new(),
+            blocks: Relations::load(config, "blocks.json"),
+            contacts: Relations::load(config, "contacts.json"),
+            contacts_only: config.contacts_only,
+            resume_grace: config.resume_grace,
+            link_ttl: config.link_ttl,
+            blobs: Blobs::new(config),
+            next_id: 0,
+            sequences: HashMap::new(),
         }
     }
+    /// Sends to every live device of an account.
     fn send_message(&self, to: usize, message: SystemEvent) -> bool {
-        self.sessions.get(&to)
-            .and_then(|user| 
-                Some(user.addr.do_send(message))
-            ).is_some()
-    }
-    fn set_key(&mut self, id: usize, pkey: String) {
-        self.sessions.get_mut(&id)
-            .and_then(|user| {
-                user.key = Some(pkey.clone());
-                Some(user.addr.do_send(SystemEvent::SetKey(pkey)))
-            });
-    }
-    fn get_users(&self, id: usize, start: usize, count: usize) {
-        self.sessions.get(&id)
-            .and_then(|user| 
-                Some(user.addr.do_send(SystemEvent::GetUsersIds(
-                    self.sessions.values()
-                        .skip(start).take(count).filter(|x| x.is_applied()).map(|x| x.to_safe()).collect()
-                )))
-            );
-    }
-    fn send_all(&self, data: SystemEvent, addr: Option<&User>) {
+        self.sessions.get(&to).is_some_and(|user| user.send(message))
+    }
+    fn device(&self, id: usize) -> Option<&Device> {
+        self.devices.get(&id)
+            .and_then(|account| self.sessions.get(account))
+            .and_then(|user| user.devices.get(&id))
+    }
+    fn device_mut(&mut self, id: usize) -> Option<&mut Device> {
+        self.devices.get(&id)
+            .and_then(|account| self.sessions.get_mut(account))
+            .and_then(|user| user.devices.get_mut(&id))
+    }
+    /// Answers the one device that asked.
+    fn reply(&self, device: usize, message: SystemEvent) {
+        if let Some(device) = self.device(device).filter(|x| x.is_online()) {
+            device.addr.do_send(message);
+        }
+    }
+    fn account(&self, device: usize) -> Option<usize> {
+        self.devices.get(&device).copied()
+    }
+    /// Sends to the live devices of an account, queuing for detached ones.
+    fn deliver(&mut self, to: usize, message: SystemEvent) -> bool {
+        match self.sessions.get_mut(&to) {
+            Some(user) => {
+                for device in user.devices.values_mut() {
+                    device.deliver(message.clone(), None);
+                }
+                true
+            }
+            None => false
+        }
+    }
+    /// What an account is known by across connections: its primary key.
+    fn identity(&self, account: usize) -> Option<String> {
+        self.sessions.get(&account).and_then(|user| user.identity())
+    }
+    fn is_blocked(&self, by: usize, id: usize) -> bool {
+        match (self.identity(by), self.identity(id)) {
+            (Some(by), Some(id)) => self.blocks.contains(&by, &id),
+            _ => false
+        }
+    }
+    /// Whether `to` accepted `from` as a contact, or is the same account.
+    fn accepts(&self, to: usize, from: usize) -> bool {
+        if to == from {
+            return true;
+        }
+        match (self.identity(to), self.identity(from)) {
+            (Some(to), Some(from)) => self.contacts.contains(&to, &from),
+            _ => false
+        }
+    }
+    fn stamp(&mut self, from_id: usize, to_id: usize) -> Stamp {
+        self.next_id += 1;
+        let seq = self.sequences.entry((from_id.min(to_id), from_id.max(to_id))).or_default();
+        *seq += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis() as u64);
+        Stamp { id: self.next_id, timestamp, seq: *seq }
+    }
+    /// Delivers a message, stamping it only once it's sure to go out so
+    /// sequence numbers have no holes of their own making. `copies` holds
+    /// the ciphertext for each device; the recipient's devices get theirs,
+    /// and so do the sender's other devices to stay in sync.
+    /// `ttl` is how long the message may wait in a queue, in seconds.
+    /// It fails rather than overflow a device whose transport is full, so
+    /// the sender knows to try again.
+    fn route_message(&mut self, from_device: usize, to_id: usize, copies: HashMap<usize, String>, random_id: usize, ttl: Option<u64>) -> Option<Stamp> {
+        let from_id = self.account(from_device)?;
+        if self.is_blocked(to_id, from_id) {
+            return None;
+        }
+        let recipients = self.sessions.get(&to_id)
+            .map(|user| user.devices.values().filter(|x| copies.contains_key(&x.id)).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if recipients.is_empty() || !recipients.iter().all(|x| x.accepts()) {
+            return None;
+        }
+        if self.contacts_only && !self.accepts(to_id, from_id) {
+            self.request_contact(to_id, from_id);
+            return None;
+        }
+        let stamp = self.stamp(from_id, to_id);
+        let expires = ttl.map(|ttl| Instant::now() + Duration::from_secs(ttl));
+        let mut targets = vec![to_id];
+        if from_id != to_id {
+            targets.push(from_id);
+        }
+        for account in targets {
+            let user = match self.sessions.get_mut(&account) {
+                Some(user) => user,
+                None => continue
+            };
+            for device in user.devices.values_mut().filter(|x| account == to_id || (x.id != from_device && x.accepts())) {
+                if let Some(message) = copies.get(&device.id) {
+                    let event = SystemEvent::Message { from: from_id, to: to_id, message: message.clone(), random_id, stamp };
+                    device.deliver(event, expires);
+                }
+            }
+        }
+        Some(stamp)
+    }
+    /// Tells `to` that `from` wants to write, once per identity. Without
+    /// an identity there's nothing the answer could be kept under.
+    fn request_contact(&mut self, to: usize, from: usize) {
+        let identity = match self.identity(from) {
+            Some(identity) => identity,
+            None => return
+        };
+        let user = match self.sessions.get_mut(&to) {
+            Some(user) => user,
+            None => return
+        };
+        if user.requests.insert(identity) {
+            self.deliver(to, SystemEvent::ContactRequest(from));
+        }
+    }
+    fn accept_contact(&mut self, id: usize, contact: usize) {
+        if let (Some(by), Some(identity)) = (self.identity(id), self.identity(contact)) {
+            self.contacts.insert(by, identity);
+        }
+    }
+    /// Without contacts-only routing nothing else would stop the sender,
+    /// so declining blocks them.
+    fn decline_contact(&mut self, id: usize, contact: usize) {
+        if let (Some(by), Some(identity)) = (self.identity(id), self.identity(contact)) {
+            self.contacts.remove(&by, &identity);
+        }
+        if !self.contacts_only {
+            self.block(id, contact);
+        }
+    }
+    fn block(&mut self, id: usize, other: usize) {
+        let (by, identity) = match (self.identity(id), self.identity(other)) {
+            (Some(by), Some(identity)) if by != identity => (by, identity),
+            _ => return
+        };
+        if !self.blocks.insert(by, identity) {
+            return;
+        }
+        if let Some(user) = self.sessions.get(&id).filter(|x| x.is_applied()) {
+            self.send_message(other, SystemEvent::UserOut(user.to_safe()));
+        }
+        self.send_block_list(id);
+    }
+    fn unblock(&mut self, id: usize, other: usize) {
+        let removed = match (self.identity(id), self.identity(other)) {
+            (Some(by), Some(identity)) => self.blocks.remove(&by, &identity),
+            _ => false
+        };
+        if !removed {
+            return;
+        }
+        if let Some(user) = self.sessions.get(&id).filter(|x| x.is_applied()) {
+            self.send_message(other, SystemEvent::UserIn(user.to_safe()));
+        }
+        self.send_block_list(id);
+    }
+    /// Lists the accounts around now whose identity `id` blocked.
+    fn send_block_list(&self, id: usize) {
+        let by = match self.identity(id) {
+            Some(by) => by,
+            None => return
+        };
+        let blocked: HashSet<&String> = self.blocks.related(&by).collect();
+        let ids = self.sessions.values()
+            .filter(|x| x.identity().is_some_and(|identity| blocked.contains(&identity)))
+            .map(|x| x.id)
+            .collect();
+        self.send_message(id, SystemEvent::BlockList(ids));
+    }
+    fn send_devices(&self, id: usize) {
+        if let Some(user) = self.sessions.get(&id) {
+            user.send(SystemEvent::Devices(user.device_list()));
+        }
+    }
+    /// Takes the key `device` announced, and challenges it to show it holds
+    /// the private key before the key stands for the account.
+    fn set_key(&mut self, device: usize, pkey: String) {
+        let challenge = challenge(&pkey);
+        if let Some(device) = self.device_mut(device) {
+            device.key = Some(pkey.clone());
+            device.proven = false;
+            device.challenge = None;
+            device.addr.do_send(SystemEvent::SetKey(pkey));
+            if let Some((nonce, sealed)) = challenge {
+                device.challenge = Some(nonce);
+                device.addr.do_send(SystemEvent::Challenge(sealed));
+            }
+        }
+    }
+    /// Accepts the answer to the challenge of `device`; its account's block
+    /// lists apply from then on.
+    fn prove(&mut self, device: usize, nonce: String) {
+        match self.device_mut(device) {
+            Some(device) if device.challenge.as_ref() == Some(&nonce) => {
+                device.challenge = None;
+                device.proven = true;
+            }
+            _ => return
+        }
+        if let Some(account) = self.account(device) {
+            self.send_block_list(account);
+            self.announce(account, None);
+        }
+    }
+    /// Tells everyone about the account's current devices, and the device
+    /// `newcomer` about everyone.
+    /// Those who blocked the account also get their block list again, as
+    /// it may be back under a new id.
+    fn announce(&self, account: usize, newcomer: Option<usize>) {
+        if let Some(user) = self.sessions.get(&account).filter(|x| x.is_applied()) {
+            self.send_all(SystemEvent::UserIn(user.to_safe()), account, newcomer);
+        }
+        for user in self.sessions.values().filter(|x| x.id != account && self.is_blocked(x.id, account)) {
+            self.send_block_list(user.id);
+        }
+    }
+    /// The users the account of `device` may see, those who didn't block it.
+    fn users(&self, device: usize, start: usize, count: usize) -> Vec<SafeUser> {
+        let id = match self.account(device) {
+            Some(id) => id,
+            None => return vec![]
+        };
+        self.sessions.values()
+            .filter(|x| !self.is_blocked(x.id, id))
+            .skip(start).take(count).filter(|x| x.is_applied()).map(|x| x.to_safe()).collect()
+    }
+    fn get_users(&self, device: usize, start: usize, count: usize) {
+        if self.account(device).is_some() {
+            self.reply(device, SystemEvent::GetUsersIds(self.users(device, start, count)));
+        }
+    }
+    /// Drops an account that has no devices left.
+    fn remove_account(&mut self, id: usize) {
+        if self.sessions.get(&id).is_some_and(|user| user.devices.is_empty()) {
+            self.sessions.remove(&id);
+            self.sequences.retain(|(a, b), _| *a != id && *b != id);
+        }
+    }
+    /// Takes a device out of its account, for moving it or forgetting it.
+    fn take_device(&mut self, id: usize) -> Option<Device> {
+        let account = self.devices.remove(&id)?;
+        let device = self.sessions.get_mut(&account)?.devices.remove(&id)?;
+        self.remove_account(account);
+        Some(device)
+    }
+    /// Moves the connection `from_id` onto the device owning `token`, if that
+    /// device lost its connection: a live one keeps it. A failed attempt
+    /// answers with the connection's own token, so the client knows to carry
+    /// on as a new identity.
+    fn resume(&mut self, from_id: usize, token: String) {
+        let id = match self.tokens.get(&token) {
+            Some(id) if *id != from_id && self.device(*id).is_some_and(|x| x.detached.is_some()) => *id,
+            _ => {
+                if let Some(device) = self.device(from_id) {
+                    device.addr.do_send(SystemEvent::ResumeToken(device.token.clone()));
+                }
+                return;
+            }
+        };
+        let fresh = match self.take_device(from_id) {
+            Some(device) => device,
+            None => return
+        };
+        self.tokens.remove(&fresh.token);
+        let (account, device) = match (self.account(id), self.device_mut(id)) {
+            (Some(account), Some(device)) => (account, device),
+            _ => return
+        };
+        // a connection that never noticed it dropped must not go on as this device
+        if device.addr.connected() {
+            device.addr.do_send(SystemEvent::Revoked);
+        }
+        device.addr = fresh.addr;
+        device.limit = fresh.limit;
+        device.held = 0;
+        device.detached = None;
+        device.addr.do_send(SystemEvent::YourDevice(id));
+        device.addr.do_send(SystemEvent::YourId(account));
+        device.addr.do_send(SystemEvent::ResumeToken(token));
+        let now = Instant::now();
+        for (_, event) in device.queue.drain(..).filter(|(expires, _)| expires.is_none_or(|x| x > now)) {
+            device.addr.do_send(event);
+        }
+        self.send_block_list(account);
+        self.send_devices(account);
+    }
+    /// Issues a one-time code that links a new device to this one's account.
+    fn link_code(&mut self, device: usize) {
+        let account = match self.account(device) {
+            Some(account) => account,
+            None => return
+        };
+        let mut bytes = [0u8; LINK_LENGTH];
+        if getrandom::getrandom(&mut bytes).is_err() {
+            return;
+        }
+        let code: String = bytes.iter()
+            .map(|x| LINK_ALPHABET[*x as usize % LINK_ALPHABET.len()] as char)
+            .collect();
+        self.links.insert(code.clone(), (account, Instant::now()));
+        self.reply(device, SystemEvent::LinkCode(code));
+    }
+    /// Moves the fresh device `from_id` into the account that issued `code`.
+    fn link(&mut self, from_id: usize, code: String) {
+        let account = match self.links.remove(&code) {
+            Some((account, since)) if since.elapsed() <= self.link_ttl && self.sessions.contains_key(&account) => account,
+            _ => {
+                self.reply(from_id, SystemEvent::Linked(false));
+                return;
+            }
+        };
+        let fresh = match self.account(from_id) {
+            Some(id) if id != account && self.sessions[&id].devices.len() == 1 => self.sessions[&id].clone(),
+            _ => {
+                self.reply(from_id, SystemEvent::Linked(false));
+                return;
+            }
+        };
+        if fresh.is_applied() {
+            self.send_all(SystemEvent::UserOut(fresh.to_safe()), fresh.id, None);
+        }
+        let device = match self.take_device(from_id) {
+            Some(device) => device,
+            None => return
+        };
+        self.devices.insert(from_id, account);
+        self.sessions.get_mut(&account).unwrap().devices.insert(from_id, device);
+        self.reply(from_id, SystemEvent::YourId(account));
+        self.reply(from_id, SystemEvent::Linked(true));
+        self.send_block_list(account);
+        self.send_devices(account);
+        self.announce(account, Some(from_id));
+    }
+    /// Unlinks `device` from the account of `from_id`, which can't be itself.
+    fn revoke(&mut self, from_id: usize, device: usize) {
+        let account = match (self.account(from_id), self.account(device)) {
+            (Some(a), Some(b)) if a == b && from_id != device => a,
+            _ => return
+        };
+        if let Some(device) = self.take_device(device) {
+            self.tokens.remove(&device.token);
+            if device.is_online() {
+                device.addr.do_send(SystemEvent::Revoked);
+            }
+        }
+        self.send_devices(account);
+        self.announce(account, None);
+    }
+    fn upload(&mut self, from_id: usize, random_id: usize, size: u64) {
+        let blob = self.account(from_id).and_then(|account| self.blobs.start(account, size));
+        self.reply(from_id, SystemEvent::Upload { random_id, blob });
+    }
+    /// Books the chunk right away, then writes it off the actor and answers
+    /// once it's on disk.
+    fn chunk(&mut self, from_id: usize, blob: String, data: String, ctx: &mut Context<Self>) {
+        let reserved = match (self.account(from_id), base64::decode(&data)) {
+            (Some(account), Ok(data)) => self.blobs.reserve(account, &blob, data.len() as u64).map(|at| (at, data)),
+            _ => None
+        };
+        let ((path, offset), data) = match reserved {
+            Some(reserved) => reserved,
+            None => return self.reply(from_id, SystemEvent::BlobStatus { blob, status: false })
+        };
+        let len = data.len() as u64;
+        ctx.spawn(blobs::write(path, offset, data).into_actor(self).map(move |ok, act, _| {
+            match act.blobs.written(&blob, len, ok) {
+                Some(Chunk::Accepted) | None => {},
+                Some(status) => act.reply(from_id, SystemEvent::BlobStatus { blob, status: status == Chunk::Complete }),
+            }
+        }));
+    }
+    fn retract(&mut self, from_id: usize, to_id: usize, random_id: usize) {
+        let user = match self.sessions.get_mut(&to_id) {
+            Some(user) => user,
+            None => return
+        };
+        for device in user.devices.values_mut() {
+            device.queue.retain(|(_, event)| !matches!(
+                event,
+                SystemEvent::Message { from, random_id: id, .. } if *from == from_id && *id == random_id
+            ));
+        }
+    }
+    fn prune(&mut self) {
+        self.blobs.prune();
+        let now = Instant::now();
+        self.links.retain(|_, (_, since)| since.elapsed() <= self.link_ttl);
+        let mut expired = Vec::new();
+        for user in self.sessions.values_mut() {
+            for device in user.devices.values_mut() {
+                device.queue.retain(|(expires, _)| expires.is_none_or(|x| x > now));
+                if device.detached.is_some_and(|since| since.elapsed() > self.resume_grace) {
+                    expired.push(device.id);
+                }
+            }
+        }
+        for id in expired {
+            let account = self.account(id);
+            if let Some(device) = self.take_device(id) {
+                self.tokens.remove(&device.token);
+            }
+            if let Some(account) = account.filter(|x| self.sessions.contains_key(x)) {
+                self.send_devices(account);
+                self.announce(account, None);
+            }
+        }
+    }
+    /// Sends `data` about `subject` to every account that hasn't been
+    /// blocked by it, and to the device `newcomer` what everyone else looks like.
+    fn send_all(&self, data: SystemEvent, subject: usize, newcomer: Option<usize>) {
         for user in self.sessions.values() {
             if user.is_applied() {
-                user.addr.do_send(data.clone());
-                addr.and_then(|other| 
-                    Some(if other.id != user.id {other.addr.do_send(SystemEvent::UserIn(user.to_safe()))}));
+                if !self.is_blocked(subject, user.id) {
+                    user.send(data.clone());
+                }
+                if let Some(device) = newcomer {
+                    if subject != user.id && !self.is_blocked(user.id, subject) {
+                        self.reply(device, SystemEvent::UserIn(user.to_safe()));
+                    }
+                }
             }
         }
     }
@@ -51,15 +496,35 @@ impl Server {
 
 impl Actor for Server {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PRUNE_INTERVAL, |act, _| act.prune());
+    }
 }
 
 impl Handler<IConnect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: IConnect, _: &mut Context<Self>) -> Self::Result {
-        println!("[+] New connection [+]");
+        let token = Uuid::new_v4().to_string();
+        msg.addr.do_send(SystemEvent::YourDevice(msg.id));
         msg.addr.do_send(SystemEvent::YourId(msg.id));
-        self.sessions.insert(msg.id, User::new(msg.id, msg.addr));
+        msg.addr.do_send(SystemEvent::ResumeToken(token.clone()));
+        self.tokens.insert(token.clone(), msg.id);
+        self.devices.insert(msg.id, msg.id);
+        let mut device = Device::new(msg.id, msg.addr, token);
+        device.limit = msg.limit;
+        self.sessions.insert(msg.id, User::new(device));
+    }
+}
+
+impl Handler<Drained> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Drained, _: &mut Context<Self>) {
+        if let Some(device) = self.device_mut(msg.id).filter(|x| x.addr == msg.addr) {
+            device.held = 0;
+        }
     }
 }
 
@@ -67,32 +532,91 @@ impl Handler<IDisconnect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: IDisconnect, _: &mut Context<Self>) {
-        println!("[+] Disconnect [+]");
-        let user = self.sessions.remove(&msg.id).unwrap();
-        self.send_all(SystemEvent::UserOut(user.to_safe()), None);
+        let account = match self.account(msg.id) {
+            Some(account) => account,
+            None => return
+        };
+        let applied = self.sessions[&account].is_applied();
+        match self.device_mut(msg.id) {
+            Some(device) if device.addr == msg.addr && device.is_online() => device.detached = Some(Instant::now()),
+            _ => return
+        }
+        let user = &self.sessions[&account];
+        if applied && !user.is_online() {
+            self.send_all(SystemEvent::UserOut(user.to_safe()), account, None);
+        }
+        self.send_devices(account);
+    }
+}
+
+impl Handler<Lookup> for Server {
+    type Result = MessageResult<Lookup>;
+
+    fn handle(&mut self, msg: Lookup, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.users(msg.device, msg.start, msg.count))
+    }
+}
+
+impl Handler<BlobComplete> for Server {
+    type Result = bool;
+
+    fn handle(&mut self, msg: BlobComplete, _: &mut Context<Self>) -> bool {
+        self.blobs.is_complete(&msg.0)
+    }
+}
+
+impl Handler<Post> for Server {
+    type Result = MessageResult<Post>;
+
+    fn handle(&mut self, msg: Post, _: &mut Context<Self>) -> Self::Result {
+        let Post { from_id, to_id, messages, random_id, ttl } = msg;
+        log::debug!("post from #{}", from_id);
+        let stamp = self.route_message(from_id, to_id, messages, random_id, ttl);
+        MessageResult(SystemEvent::MessageStatus { random_id, status: stamp.is_some(), stamp })
     }
 }
 
 impl Handler<UserEvent> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: UserEvent, _: &mut Context<Self>) {
-        println!("[+] Data: {:?} [+]", msg);
+    fn handle(&mut self, msg: UserEvent, ctx: &mut Context<Self>) {
+        log::debug!("{} from #{}", msg.kind(), msg.sender());
         match msg {
             UserEvent::GetUsersIds { id, start, count } => self.get_users(id, start, count),
             UserEvent::PublicKey { from_id, value } => {
-                self.set_key(from_id, value.clone());
-                self.sessions.get(&from_id).and_then(|user| 
-                    Some(self.send_all(SystemEvent::UserIn(user.to_safe()), Some(&user))));
+                self.set_key(from_id, value);
+                if let Some(account) = self.account(from_id) {
+                    self.send_devices(account);
+                    self.send_block_list(account);
+                    self.announce(account, Some(from_id));
+                }
+            },
+            UserEvent::Message { messages, from_id, to_id, random_id, ttl } => {
+                let stamp = self.route_message(from_id, to_id, messages, random_id, ttl);
+                self.reply(from_id, SystemEvent::MessageStatus { random_id, status: stamp.is_some(), stamp });
+            },
+            UserEvent::AcceptContact { from_id, id } => if let Some(account) = self.account(from_id) {
+                self.accept_contact(account, id)
+            },
+            UserEvent::DeclineContact { from_id, id } => if let Some(account) = self.account(from_id) {
+                self.decline_contact(account, id)
             },
-            UserEvent::Message { message, from_id, to_id, random_id } => {self.send_message(
-                from_id, 
-                SystemEvent::MessageStatus { random_id, status: self.send_message(
-                    to_id, 
-                    SystemEvent::Message { from: from_id, message, random_id }
-                )}
-            );}
+            UserEvent::Block { from_id, id } => if let Some(account) = self.account(from_id) {
+                self.block(account, id)
+            },
+            UserEvent::Unblock { from_id, id } => if let Some(account) = self.account(from_id) {
+                self.unblock(account, id)
+            },
+            UserEvent::Resume { from_id, token } => self.resume(from_id, token),
+            UserEvent::Upload { from_id, random_id, size } => self.upload(from_id, random_id, size),
+            UserEvent::Chunk { from_id, blob, data } => self.chunk(from_id, blob, data, ctx),
+            UserEvent::Retract { from_id, to_id, random_id } => if let Some(account) = self.account(from_id) {
+                self.retract(account, to_id, random_id)
+            },
+            UserEvent::LinkCode { from_id } => self.link_code(from_id),
+            UserEvent::Link { from_id, code } => self.link(from_id, code),
+            UserEvent::Revoke { from_id, device } => self.revoke(from_id, device),
+            UserEvent::Prove { from_id, nonce } => self.prove(from_id, nonce),
         };
     }
 }
-
@@ -19,7 +19,6 @@ impl Session {
     pub fn ping(&self, ctx: &mut WebsocketContext<Self>) {
         ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
             if Instant::now().duration_since(act.last_ping) > CLIENT_TIMEOUT {
-                act.addr.do_send(IDisconnect { id: act.id });
                 ctx.stop();
                 return;
             }
@@ -28,9 +27,9 @@ impl Session {
         });
     }
     fn handle(&mut self, msg: String) {
-        let _ = serde_json::from_str(&msg.to_string())
-                    .and_then(|message: RawUserEvent| 
-                        Ok(self.addr.do_send(message.collect(self.id))));
+        if let Ok(message) = serde_json::from_str::<RawUserEvent>(&msg) {
+            self.addr.do_send(message.collect(self.id));
+        }
     }
 }
 
@@ -45,6 +44,7 @@ impl Actor for Session {
             .send(IConnect {
                 id: self.id,
                 addr: addr.recipient(),
+                limit: None,
             })
             .into_actor(self)
             .then(|res, _, ctx| {
@@ -57,8 +57,8 @@ impl Actor for Session {
             .wait(ctx);
     }
 
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        self.addr.do_send(IDisconnect { id: self.id });
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.addr.do_send(IDisconnect { id: self.id, addr: ctx.address().recipient() });
         Running::Stop
     }
 }
@@ -69,6 +69,10 @@ impl Handler<SystemEvent> for Session {
     fn handle(&mut self, msg: SystemEvent, ctx: &mut Self::Context) {
         match msg {
             SystemEvent::SetKey(key) => self.public_key = Some(key),
+            SystemEvent::YourDevice(id) => {
+                self.id = id;
+                ctx.text(json!(SystemEvent::YourDevice(id)).to_string())
+            },
             e => ctx.text(json!(e).to_string())
         }
     }
@@ -76,7 +80,7 @@ impl Handler<SystemEvent> for Session {
 
 impl StreamHandler<Result<Message, ProtocolError>> for Session {
     fn handle(&mut self, msg: Result<Message, ProtocolError>, ctx: &mut Self::Context) {        
-        msg.and_then(|msg| Ok(match msg {
+        msg.map(|msg| match msg {
             Message::Text(text) => {
                 self.handle(text.to_string());
             },
@@ -92,6 +96,6 @@ impl StreamHandler<Result<Message, ProtocolError>> for Session {
                 ctx.stop();
             },
             Message::Nop => (),
-        })).unwrap();
+        }).unwrap();
     }
 }
\ No newline at end of file
@@ -0,0 +1,96 @@
+use std::{convert::Infallible, sync::{Arc, Mutex}, time::Duration};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Context, Handler, Running};
+use actix_web::{get, web::{Bytes, Data}, HttpResponse};
+use futures_util::stream;
+use serde_json::json;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use uuid::Uuid;
+use crate::{
+    data::{IConnect, IDisconnect, SystemEvent},
+    server::Server,
+    transport::{next_id, Inbound, Transports},
+};
+
+/// Comments keep proxies from timing the stream out, and tell when the
+/// client is gone: writing to a dropped response fails.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A client reading events from a Server-Sent Events stream and posting its
+/// own to `/chat/send/{token}`.
+pub struct SseSession {
+    pub id: usize,
+    pub addr: Addr<Server>,
+    token: String,
+    transports: Transports,
+    stream: UnboundedSender<Bytes>,
+}
+
+impl SseSession {
+    fn write(&self, frame: String, ctx: &mut Context<Self>) {
+        if self.stream.send(Bytes::from(frame)).is_err() {
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for SseSession {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.transports.lock().unwrap().insert(self.token.clone(), ctx.address().recipient());
+        self.write(format!("event: session\ndata: {}\n\n", self.token), ctx);
+        self.addr.do_send(IConnect { id: self.id, addr: ctx.address().recipient(), limit: None });
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| act.write(": ping\n\n".to_string(), ctx));
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.transports.lock().unwrap().remove(&self.token);
+        self.addr.do_send(IDisconnect { id: self.id, addr: ctx.address().recipient() });
+        Running::Stop
+    }
+}
+
+impl Handler<SystemEvent> for SseSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, ctx: &mut Self::Context) {
+        match msg {
+            SystemEvent::SetKey(_) => (),
+            SystemEvent::YourDevice(id) => {
+                self.id = id;
+                self.write(format!("data: {}\n\n", json!(SystemEvent::YourDevice(id))), ctx)
+            },
+            e => self.write(format!("data: {}\n\n", json!(e)), ctx)
+        }
+    }
+}
+
+impl Handler<Inbound> for SseSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Inbound, _: &mut Self::Context) {
+        self.addr.do_send(msg.0.collect(self.id));
+    }
+}
+
+/// Opens an event stream. Its first event, named `session`, carries the
+/// token to post with.
+#[get("/chat/sse")]
+pub async fn open(srv: Data<Addr<Server>>, counter: Data<Arc<Mutex<usize>>>, transports: Data<Transports>) -> HttpResponse {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    SseSession {
+        id: next_id(&counter),
+        addr: srv.get_ref().clone(),
+        token: Uuid::new_v4().to_string(),
+        transports: transports.get_ref().clone(),
+        stream: sender,
+    }.start();
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|frame| (Ok::<_, Infallible>(frame), receiver))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
@@ -0,0 +1,100 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+use actix::{Message, Recipient};
+use actix_web::{post, web::{Data, Json, Path, ServiceConfig}, HttpResponse};
+use crate::{data::{RawUserEvent, SystemEvent}, poll, sse};
+
+/// An event from the client of an HTTP transport, which the session turns
+/// into a `UserEvent` for its device as a socket `Session` does.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Inbound(pub RawUserEvent);
+
+/// Sessions of the HTTP transports, by the token their client posts with.
+pub type Transports = Arc<Mutex<HashMap<String, Recipient<Inbound>>>>;
+
+/// Hands out connection ids, shared by every kind of session.
+pub fn next_id(counter: &Mutex<usize>) -> usize {
+    let mut counter = counter.lock().unwrap();
+    *counter += 1;
+    *counter - 1
+}
+
+/// Queues `event` for a transport's client, which holds at most `limit`.
+/// The server stops routing messages to a transport holding `limit` of
+/// them, so other events make room oldest first; with only messages left
+/// to make room, `event` is dropped instead.
+pub fn enqueue(queue: &mut VecDeque<SystemEvent>, event: SystemEvent, limit: usize) {
+    if queue.len() >= limit {
+        match queue.iter().position(|x| !matches!(x, SystemEvent::Message { .. })) {
+            Some(oldest) => { queue.remove(oldest); }
+            None => return
+        }
+    }
+    queue.push_back(event);
+}
+
+/// Client to server for both SSE and long-polling sessions.
+#[post("/chat/send/{token}")]
+async fn send(token: Path<String>, body: Json<RawUserEvent>, transports: Data<Transports>) -> HttpResponse {
+    let session = transports.lock().unwrap().get(token.as_str()).cloned();
+    match session {
+        Some(session) => {
+            session.do_send(Inbound(body.into_inner()));
+            HttpResponse::Accepted().finish()
+        }
+        None => HttpResponse::NotFound().finish()
+    }
+}
+
+pub fn routes(config: &mut ServiceConfig) {
+    config
+        .service(send)
+        .service(sse::open)
+        .service(poll::open)
+        .service(poll::wait);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(random_id: usize) -> SystemEvent {
+        let stamp = crate::data::Stamp { id: random_id as u64, timestamp: 0, seq: random_id as u64 };
+        SystemEvent::Message { from: 1, to: 2, message: String::new(), random_id, stamp }
+    }
+
+    fn kinds(queue: &VecDeque<SystemEvent>) -> Vec<String> {
+        queue.iter().map(|x| match x {
+            SystemEvent::Message { random_id, .. } => format!("message {}", random_id),
+            SystemEvent::ContactRequest(id) => format!("request {}", id),
+            other => format!("{:?}", other)
+        }).collect()
+    }
+
+    #[test]
+    fn other_events_make_room_oldest_first() {
+        let mut queue = VecDeque::new();
+        enqueue(&mut queue, SystemEvent::ContactRequest(1), 3);
+        enqueue(&mut queue, message(1), 3);
+        enqueue(&mut queue, SystemEvent::ContactRequest(2), 3);
+        enqueue(&mut queue, SystemEvent::ContactRequest(3), 3);
+        assert_eq!(kinds(&queue), ["message 1", "request 2", "request 3"]);
+    }
+
+    #[test]
+    fn never_grows_past_the_limit() {
+        let mut queue = VecDeque::new();
+        enqueue(&mut queue, message(1), 2);
+        enqueue(&mut queue, message(2), 2);
+        enqueue(&mut queue, message(3), 2);
+        enqueue(&mut queue, SystemEvent::ContactRequest(1), 2);
+        assert_eq!(kinds(&queue), ["message 1", "message 2"]);
+    }
+
+    #[test]
+    fn holds_nothing_with_no_room() {
+        let mut queue = VecDeque::new();
+        enqueue(&mut queue, SystemEvent::ContactRequest(1), 0);
+        assert!(queue.is_empty());
+    }
+}
@@ -1,28 +1,136 @@
+use std::{collections::{BTreeMap, HashSet}, time::Instant};
 use actix::Recipient;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use crate::data::SystemEvent;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct SafeUser {
+pub struct SafeDevice {
     pub id: usize,
     pub key: String
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SafeUser {
+    pub id: usize,
+    /// The account's primary key, the one safety numbers are made from.
+    pub key: String,
+    /// Every device a message has to be encrypted for.
+    pub devices: Vec<SafeDevice>
+}
+
+/// What the account's own devices see on the linked devices page.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceInfo {
+    pub id: usize,
+    pub key: Option<String>,
+    pub online: bool
+}
+
 #[derive(Clone, Debug)]
-pub struct User {
+pub struct Device {
     pub id: usize,
     pub addr: Recipient<SystemEvent>,
-    pub key: Option<String>
+    pub key: Option<String>,
+    /// Whether the device proved it holds the private half of `key`.
+    pub proven: bool,
+    /// The nonce sealed for `key`, base64, until the device answers.
+    pub challenge: Option<String>,
+    pub token: String,
+    /// When the socket dropped; the device is kept until `resume_grace`
+    /// runs out so the client can reclaim it with the resumption token.
+    pub detached: Option<Instant>,
+    /// Events waiting while detached, each with the moment it stops being
+    /// worth delivering.
+    pub queue: Vec<(Option<Instant>, SystemEvent)>,
+    /// How many messages the transport holds until its client fetches them,
+    /// `None` for one that passes them straight on.
+    pub limit: Option<usize>,
+    /// Messages handed to the transport since the client last fetched.
+    pub held: usize
+}
+
+impl Device {
+    pub fn new(id: usize, addr: Recipient<SystemEvent>, token: String) -> Self {
+        Self { id, addr, key: None, proven: false, challenge: None, token, detached: None, queue: Vec::new(), limit: None, held: 0 }
+    }
+    pub fn is_online(&self) -> bool {
+        self.detached.is_none()
+    }
+    /// Whether `deliver` would take another message rather than overflow
+    /// the transport's queue.
+    pub fn accepts(&self) -> bool {
+        self.detached.is_some() || self.limit.is_none_or(|limit| self.held < limit)
+    }
+    /// Sends to the live socket, or queues while detached.
+    pub fn deliver(&mut self, message: SystemEvent, expires: Option<Instant>) {
+        match self.detached {
+            Some(_) => self.queue.push((expires, message)),
+            None => {
+                self.held += 1;
+                self.addr.do_send(message)
+            }
+        }
+    }
+}
+
+/// An account, reachable through any of its devices. Its id is the id of
+/// the device that created it.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub id: usize,
+    pub devices: BTreeMap<usize, Device>,
+    /// Identities that asked to write since the account came online, so
+    /// each is only announced once.
+    pub requests: HashSet<String>
 }
 
 impl User {
-    pub fn new(id: usize, addr: Recipient<SystemEvent>) -> Self {
-        Self { id, addr, key: None }
+    pub fn new(device: Device) -> Self {
+        let id = device.id;
+        Self { id, devices: BTreeMap::from([(id, device)]), requests: HashSet::new() }
+    }
+    /// The key of the oldest device that announced one.
+    pub fn key(&self) -> Option<&String> {
+        self.devices.values().find_map(|x| x.key.as_ref())
+    }
+    /// A digest of `key`, what the account is known by across connections.
+    /// Keys are public, so it counts only once the device holding it proved
+    /// it has the private key too.
+    pub fn identity(&self) -> Option<String> {
+        self.devices.values()
+            .find(|x| x.key.is_some())
+            .filter(|x| x.proven)
+            .and_then(|x| x.key.as_ref())
+            .map(|key| Sha256::digest(key.as_bytes()).iter().map(|x| format!("{:02x}", x)).collect())
     }
     pub fn to_safe(&self) -> SafeUser {
-        SafeUser { id: self.id, key: self.key.clone().unwrap() }
+        SafeUser {
+            id: self.id,
+            key: self.key().cloned().unwrap(),
+            devices: self.devices.values()
+                .filter_map(|x| x.key.clone().map(|key| SafeDevice { id: x.id, key }))
+                .collect()
+        }
+    }
+    pub fn device_list(&self) -> Vec<DeviceInfo> {
+        self.devices.values()
+            .map(|x| DeviceInfo { id: x.id, key: x.key.clone(), online: x.is_online() })
+            .collect()
+    }
+    pub fn is_online(&self) -> bool {
+        self.devices.values().any(|x| x.is_online())
     }
     pub fn is_applied(&self) -> bool {
-        self.key.is_some()
+        self.is_online() && self.key().is_some()
+    }
+    /// Sends to every live device.
+    pub fn send(&self, message: SystemEvent) -> bool {
+        let mut sent = false;
+        for device in self.devices.values().filter(|x| x.is_online()) {
+            device.addr.do_send(message.clone());
+            sent = true;
+        }
+        sent
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,154 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
+use actix::{Actor, Addr, Context, Handler};
+use chat_server::{
+    config::Config,
+    data::{IConnect, Post, SystemEvent, UserEvent},
+    server::Server,
+};
+use client_core::native::Identity;
+
+const ALICE: &str = include_str!("keys/alice.pem");
+const BOB: &str = include_str!("keys/bob.pem");
+
+/// A device's connection, keeping whatever the server sends it.
+struct Recorder(Arc<Mutex<Vec<SystemEvent>>>);
+
+impl Actor for Recorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<SystemEvent> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: SystemEvent, _: &mut Self::Context) {
+        self.0.lock().unwrap().push(msg);
+    }
+}
+
+type Events = Arc<Mutex<Vec<SystemEvent>>>;
+
+/// A server with its files under a scratch directory, set up the same
+/// whatever the environment says.
+fn server(name: &str, contacts_only: bool) -> Addr<Server> {
+    let dir: PathBuf = std::env::temp_dir().join(format!("chat-server-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config {
+        address: "127.0.0.1:0".to_string(),
+        contacts_only,
+        resume_grace: Duration::from_secs(300),
+        link_ttl: Duration::from_secs(300),
+        blob_dir: dir.join("blobs"),
+        data_dir: dir.join("data"),
+        blob_max: 1024,
+        blob_quota: 4096,
+        blob_ttl: Duration::from_secs(60),
+        api_idle: Duration::from_secs(60),
+        api_queue: 10,
+        web_dir: None,
+        socket_url: None,
+    };
+    Server::new(&config).start()
+}
+
+/// Waits for the first event `found` picks, failing after a few seconds.
+async fn wait_for<T>(events: &Events, mut found: impl FnMut(&SystemEvent) -> Option<T>) -> T {
+    for _ in 0..500 {
+        if let Some(x) = events.lock().unwrap().iter().find_map(&mut found) {
+            return x;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no such event in {:?}", events.lock().unwrap());
+}
+
+/// Connects device `id` and announces `key`, without proving it holds it.
+async fn announce(srv: &Addr<Server>, id: usize, key: String) -> Events {
+    let events = Events::default();
+    let addr = Recorder(events.clone()).start();
+    srv.send(IConnect { id, addr: addr.recipient(), limit: None }).await.unwrap();
+    srv.send(UserEvent::PublicKey { from_id: id, value: key }).await.unwrap();
+    events
+}
+
+/// Connects device `id` as the holder of `pem`, answering the challenge.
+async fn connect(srv: &Addr<Server>, id: usize, pem: &str) -> Events {
+    let identity = Identity::from_pem(pem).unwrap();
+    let events = announce(srv, id, identity.public_key()).await;
+    let sealed = wait_for(&events, |x| match x {
+        SystemEvent::Challenge(sealed) => Some(sealed.clone()),
+        _ => None
+    }).await;
+    let nonce = identity.open(&base64::decode(sealed).unwrap()).unwrap();
+    srv.send(UserEvent::Prove { from_id: id, nonce: base64::encode(nonce) }).await.unwrap();
+    events
+}
+
+async fn post(srv: &Addr<Server>, from_id: usize, to_id: usize, random_id: usize) -> bool {
+    let messages = HashMap::from([(to_id, "sealed".to_string())]);
+    let status = srv.send(Post { from_id, to_id, messages, random_id, ttl: None }).await.unwrap();
+    matches!(status, SystemEvent::MessageStatus { status: true, .. })
+}
+
+fn received(events: &Events, random_id: usize) -> bool {
+    events.lock().unwrap().iter().any(|x| matches!(x, SystemEvent::Message { random_id: id, .. } if *id == random_id))
+}
+
+#[actix_web::test]
+async fn routes_messages_to_the_devices_sealed_for() {
+    let srv = server("route", false);
+    let _alice = connect(&srv, 1, ALICE).await;
+    let bob = connect(&srv, 2, BOB).await;
+    assert!(post(&srv, 1, 2, 7).await);
+    wait_for(&bob, |x| matches!(x, SystemEvent::Message { from: 1, random_id: 7, .. }).then_some(())).await;
+    // nothing sealed for any of the recipient's devices
+    let status = srv.send(Post { from_id: 1, to_id: 2, messages: HashMap::new(), random_id: 8, ttl: None }).await.unwrap();
+    assert!(matches!(status, SystemEvent::MessageStatus { status: false, stamp: None, .. }));
+    assert!(!post(&srv, 1, 3, 9).await);
+}
+
+#[actix_web::test]
+async fn refuses_blocked_senders_until_unblocked() {
+    let srv = server("block", false);
+    let _alice = connect(&srv, 1, ALICE).await;
+    let bob = connect(&srv, 2, BOB).await;
+    srv.send(UserEvent::Block { from_id: 2, id: 1 }).await.unwrap();
+    wait_for(&bob, |x| matches!(x, SystemEvent::BlockList(ids) if ids == &[1]).then_some(())).await;
+    assert!(!post(&srv, 1, 2, 7).await);
+    srv.send(UserEvent::Unblock { from_id: 2, id: 1 }).await.unwrap();
+    assert!(post(&srv, 1, 2, 8).await);
+    wait_for(&bob, |x| matches!(x, SystemEvent::Message { random_id: 8, .. }).then_some(())).await;
+    assert!(!received(&bob, 7));
+}
+
+#[actix_web::test]
+async fn keys_count_for_blocks_only_once_proven() {
+    let srv = server("prove", false);
+    let _alice = connect(&srv, 1, ALICE).await;
+    let bob = connect(&srv, 2, BOB).await;
+    srv.send(UserEvent::Block { from_id: 2, id: 1 }).await.unwrap();
+    wait_for(&bob, |x| matches!(x, SystemEvent::BlockList(ids) if !ids.is_empty()).then_some(())).await;
+    // someone else announcing bob's key learns nothing and changes nothing
+    let bob_key = Identity::from_pem(BOB).unwrap().public_key();
+    let mallory = announce(&srv, 3, bob_key).await;
+    srv.send(UserEvent::Unblock { from_id: 3, id: 1 }).await.unwrap();
+    assert!(!post(&srv, 1, 2, 7).await);
+    assert!(!mallory.lock().unwrap().iter().any(|x| matches!(x, SystemEvent::BlockList(_))));
+    // a wrong answer doesn't do either
+    srv.send(UserEvent::Prove { from_id: 3, nonce: base64::encode([0u8; 32]) }).await.unwrap();
+    srv.send(UserEvent::Unblock { from_id: 3, id: 1 }).await.unwrap();
+    assert!(!post(&srv, 1, 2, 8).await);
+    assert!(!mallory.lock().unwrap().iter().any(|x| matches!(x, SystemEvent::BlockList(_))));
+}
+
+#[actix_web::test]
+async fn contacts_only_routes_between_accepted_contacts() {
+    let srv = server("contacts", true);
+    let _alice = connect(&srv, 1, ALICE).await;
+    let bob = connect(&srv, 2, BOB).await;
+    assert!(!post(&srv, 1, 2, 7).await);
+    wait_for(&bob, |x| matches!(x, SystemEvent::ContactRequest(1)).then_some(())).await;
+    srv.send(UserEvent::AcceptContact { from_id: 2, id: 1 }).await.unwrap();
+    assert!(post(&srv, 1, 2, 8).await);
+    srv.send(UserEvent::DeclineContact { from_id: 2, id: 1 }).await.unwrap();
+    assert!(!post(&srv, 1, 2, 9).await);
+}